@@ -0,0 +1,21 @@
+use crate::hitting::Colour;
+
+// Haze that fades things towards fog_colour the further away they are. Light
+// travelling through it is attenuated following the Beer-Lambert law.
+pub struct AtmosphericFog {
+    pub fog_colour: Colour,
+    pub density: f64,
+}
+
+impl AtmosphericFog {
+    // Light that has come `distance` through the fog. Rays that escape to the
+    // sky have come an infinite distance, so they see only fog.
+    pub fn apply(&self, colour: Colour, distance: f64) -> Colour {
+        let transmittance = self.transmittance(distance);
+        transmittance * colour + (1.0 - transmittance) * self.fog_colour
+    }
+    // The fraction of light that makes it `distance` through the fog
+    pub fn transmittance(&self, distance: f64) -> f64 {
+        (-self.density * distance).exp()
+    }
+}
@@ -0,0 +1,227 @@
+use anyhow::{anyhow, Result};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::atmosphere::AtmosphericFog;
+use crate::camera::{Camera, Sky};
+use crate::config::Scene;
+use crate::hitting::{find_emitters, BVHNode, Hittable, Material};
+use crate::textures::Texture;
+
+// Puts a scene together in code, as an alternative to a config file.
+// Textures, materials, and objects are registered by name so they can be
+// looked up again while building the rest of the scene, and objects have to
+// be added to the world by name to be rendered, just like in a config file.
+#[derive(Default)]
+pub struct SceneBuilder {
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    objects: HashMap<String, Arc<dyn Hittable>>,
+    camera: Option<Camera>,
+    background: Option<Box<dyn Sky>>,
+    atmosphere: Option<AtmosphericFog>,
+    world: Vec<String>,
+}
+
+impl SceneBuilder {
+    pub fn new() -> SceneBuilder {
+        SceneBuilder::default()
+    }
+
+    pub fn add_texture(&mut self, name: &str, texture: Arc<dyn Texture>) -> &mut Self {
+        self.textures.insert(String::from(name), texture);
+        self
+    }
+    pub fn add_material(&mut self, name: &str, material: Arc<dyn Material>) -> &mut Self {
+        self.materials.insert(String::from(name), material);
+        self
+    }
+    pub fn add_object(&mut self, name: &str, object: Arc<dyn Hittable>) -> &mut Self {
+        self.objects.insert(String::from(name), object);
+        self
+    }
+    pub fn set_camera(&mut self, camera: Camera) -> &mut Self {
+        self.camera = Some(camera);
+        self
+    }
+    pub fn set_background(&mut self, sky: Box<dyn Sky>) -> &mut Self {
+        self.background = Some(sky);
+        self
+    }
+    pub fn set_atmosphere(&mut self, fog: AtmosphericFog) -> &mut Self {
+        self.atmosphere = Some(fog);
+        self
+    }
+    pub fn add_to_world(&mut self, name: &str) -> &mut Self {
+        self.world.push(String::from(name));
+        self
+    }
+
+    pub fn texture(&self, name: &str) -> Result<&Arc<dyn Texture>> {
+        self.textures
+            .get(name)
+            .ok_or_else(|| anyhow!("Texture {} does not exist", name))
+    }
+    pub fn material(&self, name: &str) -> Result<&Arc<dyn Material>> {
+        self.materials
+            .get(name)
+            .ok_or_else(|| anyhow!("Material {} does not exist", name))
+    }
+    pub fn object(&self, name: &str) -> Result<&Arc<dyn Hittable>> {
+        self.objects
+            .get(name)
+            .ok_or_else(|| anyhow!("Object {} does not exist", name))
+    }
+
+    // Returns the same things as config::load_config
+    pub fn build(self) -> Result<Scene> {
+        let camera = self.camera.ok_or_else(|| anyhow!("Scene has no camera"))?;
+        let sky = self
+            .background
+            .ok_or_else(|| anyhow!("Scene has no background"))?;
+        let objects = self.objects;
+        let world = self
+            .world
+            .iter()
+            .map(|name| {
+                objects
+                    .get(name)
+                    .map(Arc::clone)
+                    .ok_or_else(|| anyhow!("Object {} does not exist", name))
+            })
+            .collect::<Result<Vec<Arc<dyn Hittable>>>>()?;
+        let lights = find_emitters(&world);
+        let (start_time, end_time) = camera.time_range();
+        let world = BVHNode::from_vec(world, start_time, end_time)?;
+        let aspect_ratio = camera.aspect_ratio();
+        Ok((camera, world, lights, sky, self.atmosphere, aspect_ratio))
+    }
+}
+
+#[test]
+fn cornell_box_builder_test() {
+    use crate::camera::{Camera, SolidSky};
+    use crate::config::load_config;
+    use crate::hitting::Colour;
+    use crate::materials::{DiffuseLight, Lambertian};
+    use crate::math::{Point3, Vec3};
+    use crate::objects::{Block, XYRect, XZRect, YZRect};
+    use crate::textures::SolidColour;
+    use crate::transforms::{RotateY, Translate};
+
+    // the same scene as examples/cornell.json5
+    let mut builder = SceneBuilder::new();
+    builder
+        .set_camera(Camera::new(
+            Point3::new(278, 278, -800),
+            Point3::new(278, 278, 0),
+            Vec3::new(0, 1, 0),
+            40,
+            1.0,
+            0.0,
+            900.0,
+            0.0,
+            1.0,
+        ))
+        .set_background(Box::new(SolidSky {
+            colour: Colour::new(0, 0, 0),
+        }))
+        .add_texture(
+            "red",
+            Arc::new(SolidColour {
+                colour: Colour::new(0.65, 0.05, 0.05),
+            }),
+        )
+        .add_texture(
+            "white",
+            Arc::new(SolidColour {
+                colour: Colour::new(0.73, 0.73, 0.73),
+            }),
+        )
+        .add_texture(
+            "green",
+            Arc::new(SolidColour {
+                colour: Colour::new(0.12, 0.45, 0.15),
+            }),
+        )
+        .add_texture(
+            "light",
+            Arc::new(SolidColour {
+                colour: Colour::new(15, 15, 15),
+            }),
+        );
+    for name in &["red", "white", "green"] {
+        let material = Lambertian::with_texture(builder.texture(name).unwrap());
+        builder.add_material(name, material);
+    }
+    let light = Arc::new(DiffuseLight {
+        emit: Arc::clone(builder.texture("light").unwrap()),
+        strength: 1.0,
+        double_sided: false,
+    });
+    builder.add_material("light", light);
+
+    let green = Arc::clone(builder.material("green").unwrap());
+    let red = Arc::clone(builder.material("red").unwrap());
+    let white = Arc::clone(builder.material("white").unwrap());
+    let light = Arc::clone(builder.material("light").unwrap());
+    builder
+        .add_object("leftWall", YZRect::new(0, 555, 0, 555, 555, &green, false))
+        .add_object("rightWall", YZRect::new(0, 555, 0, 555, 0, &red, true))
+        .add_object("light", XZRect::new(214, 343, 227, 332, 554, &light, false))
+        .add_object("floor", XZRect::new(0, 555, 0, 555, 0, &white, true))
+        .add_object("ceiling", XZRect::new(0, 555, 0, 555, 555, &white, false))
+        .add_object("backWall", XYRect::new(0, 555, 0, 555, 555, &white, false))
+        .add_object(
+            "tallBlock0",
+            Block::new(Point3::new(0, 0, 0), Point3::new(165, 330, 165), &white),
+        )
+        .add_object(
+            "shortBlock0",
+            Block::new(Point3::new(0, 0, 0), Point3::new(165, 165, 165), &white),
+        );
+    let tall = RotateY::by_degrees(builder.object("tallBlock0").unwrap(), 15.0);
+    let short = RotateY::by_degrees(builder.object("shortBlock0").unwrap(), -18.0);
+    builder
+        .add_object(
+            "tallBlock2",
+            Translate::translate(&tall, Vec3::new(265, 0, 295)),
+        )
+        .add_object(
+            "shortBlock2",
+            Translate::translate(&short, Vec3::new(130, 0, 65)),
+        );
+    for name in &[
+        "leftWall",
+        "rightWall",
+        "floor",
+        "ceiling",
+        "backWall",
+        "light",
+        "tallBlock2",
+        "shortBlock2",
+    ] {
+        builder.add_to_world(name);
+    }
+    let (camera, world, _, _, _, aspect_ratio) = builder.build().unwrap();
+
+    let (expected_camera, expected_world, _, _, _, expected_aspect_ratio) =
+        load_config("examples/cornell.json5", &[], 0.0, None, None).unwrap();
+    assert_eq!(aspect_ratio, expected_aspect_ratio);
+    for i in 0..10 {
+        for j in 0..10 {
+            // offset so no ray runs exactly into a corner, which either wall could claim
+            let (u, v) = ((i as f64 + 0.3) / 10.0, (j as f64 + 0.6) / 10.0);
+            let ray = camera.find_ray(u, v);
+            assert_eq!(ray.direction, expected_camera.find_ray(u, v).direction);
+            let hit = world
+                .hit(&ray, 0.001, f64::INFINITY)
+                .map(|h| (h.intersection, h.normal));
+            let expected = expected_world
+                .hit(&ray, 0.001, f64::INFINITY)
+                .map(|h| (h.intersection, h.normal));
+            assert_eq!(hit, expected);
+        }
+    }
+}
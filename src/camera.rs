@@ -1,22 +1,75 @@
+use anyhow::Result;
+use image::codecs::hdr::HdrDecoder;
 use rand::Rng;
 
+use crate::colour_space::srgb_to_linear;
 use crate::hitting::Colour;
-use crate::math::{cross, dot, random_in_unit_disc, Point3, Ray, Vec3};
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+
+use crate::math::{
+    cross, dot, get_sphere_uv, random_in_unit_disc, random_in_unit_sphere, AliasTable, Point3, Ray,
+    Vec3,
+};
+use crate::random;
 
 pub const TIME_MIN: f64 = 0.0;
 pub const TIME_MAX: f64 = 1.0;
 
-pub type Sky = Box<dyn Fn(&Ray) -> Colour + Send + Sync + 'static>;
+// What a ray sees when it doesn't hit anything
+pub trait Sky: Send + Sync {
+    fn colour(&self, ray: &Ray) -> Colour;
+    // whether it's worth sampling like a light, for skies bright in some
+    // directions and dim in others
+    fn sampled_as_light(&self) -> bool {
+        false
+    }
+    // A random direction, the probability density (over solid angle) of
+    // picking it, and the light from that direction
+    fn sample(&self) -> (Vec3, f64, Colour) {
+        let direction = random_in_unit_sphere().unit_vector();
+        let colour = self.colour(&Ray::new(Point3::new(0, 0, 0), direction, TIME_MIN));
+        (direction, self.pdf(direction), colour)
+    }
+    // The density sample would give the direction
+    fn pdf(&self, _direction: Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+// How directions in the image map to directions in the world
+enum Projection {
+    // through a thin lens, with depth of field
+    Perspective,
+    // equirectangular: the whole sphere around the camera, from left to
+    // right and bottom to top, with straight ahead in the middle
+    Panoramic,
+}
+
+// The shape of the lens opening, which is the shape out-of-focus highlights take
+#[derive(Clone, Copy)]
+pub enum ApertureShape {
+    Circle,
+    Hexagon,
+    // a regular polygon with this many sides
+    Pentagon(u32),
+    Square,
+}
 
 pub struct Camera {
+    projection: Projection,
     origin: Point3,
     lower_left_corner: Point3,
     horizontal: Vec3,
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
-    _w: Vec3,
+    w: Vec3,
+    aspect_ratio: f64,
     lens_radius: f64,
+    aperture_shape: ApertureShape,
+    gamma: Option<f64>,
     start_time: f64,
     end_time: f64,
 }
@@ -52,35 +105,285 @@ impl Camera {
         let lens_radius = aperture / 2.0;
 
         Camera {
+            projection: Projection::Perspective,
             origin,
             lower_left_corner,
             horizontal,
             vertical,
             u,
             v,
-            _w: w,
+            w,
+            aspect_ratio,
             lens_radius,
+            aperture_shape: ApertureShape::Circle,
+            gamma: None,
             start_time,
             end_time,
         }
     }
+    // Always in focus, and the image is meant to be twice as wide as it is tall
+    pub fn panoramic(
+        look_from: Point3,
+        look_at: Point3,
+        direction_up: Vec3,
+        start_time: f64,
+        end_time: f64,
+    ) -> Camera {
+        let mut camera = Camera::new(
+            look_from,
+            look_at,
+            direction_up,
+            90,
+            2.0,
+            0.0,
+            1.0,
+            start_time,
+            end_time,
+        );
+        camera.projection = Projection::Panoramic;
+        camera
+    }
+    pub fn with_aperture_shape(mut self, aperture_shape: ApertureShape) -> Camera {
+        self.aperture_shape = aperture_shape;
+        self
+    }
+    pub fn with_gamma(mut self, gamma: Option<f64>) -> Camera {
+        self.gamma = gamma;
+        self
+    }
+    pub fn aspect_ratio(&self) -> f64 {
+        self.aspect_ratio
+    }
+    // what the scene asks for, if anything
+    pub fn gamma(&self) -> Option<f64> {
+        self.gamma
+    }
+    pub fn time_range(&self) -> (f64, f64) {
+        (self.start_time, self.end_time)
+    }
     pub fn find_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disc();
+        if let Projection::Panoramic = self.projection {
+            let azimuth = 2.0 * PI * (s - 0.5);
+            let elevation = PI * (t - 0.5);
+            let direction = elevation.cos() * (azimuth.sin() * self.u - azimuth.cos() * self.w)
+                + elevation.sin() * self.v;
+            return Ray::new(
+                self.origin,
+                direction,
+                random::rng().gen_range(self.start_time..=self.end_time),
+            );
+        }
+        let rd = self.lens_radius * self.random_in_aperture();
         let offset = self.u * rd.x + self.v * rd.y;
         Ray::new(
             self.origin + offset,
             self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-            rand::thread_rng().gen_range(self.start_time..=self.end_time),
+            random::rng().gen_range(self.start_time..=self.end_time),
         )
     }
+    fn random_in_aperture(&self) -> Vec3 {
+        let sides = match self.aperture_shape {
+            ApertureShape::Circle => return random_in_unit_disc(),
+            ApertureShape::Hexagon => 6,
+            ApertureShape::Pentagon(n_blades) => n_blades,
+            ApertureShape::Square => 4,
+        };
+        // the polygon is inscribed in the unit circle, so reject points of the
+        // disc that fall past the edge of the side they face
+        let sector = 2.0 * PI / sides as f64;
+        loop {
+            let p = random_in_unit_disc();
+            let angle = p.y.atan2(p.x).rem_euclid(sector) - sector / 2.0;
+            if p.length() * angle.cos() <= (sector / 2.0).cos() {
+                return p;
+            }
+        }
+    }
+}
+
+pub struct SolidSky {
+    pub colour: Colour,
+}
+
+impl Sky for SolidSky {
+    fn colour(&self, _ray: &Ray) -> Colour {
+        self.colour
+    }
+}
+
+// Fades from col1 opposite direction to col2 along it
+pub struct GradientSky {
+    direction: Vec3,
+    col1: Colour,
+    col2: Colour,
+}
+
+impl GradientSky {
+    pub fn new(direction: Vec3, col1: Colour, col2: Colour) -> GradientSky {
+        // col1 used to be 1,1,1, col2 used to be 0.5,0.7,1.0
+        GradientSky {
+            direction: direction.unit_vector(),
+            col1,
+            col2,
+        }
+    }
 }
 
-pub fn gradient_background(dir: Vec3, col1: Colour, col2: Colour) -> Sky {
-    // col1 used to be 1,1,1, col2 used to be 0.5,0.7,1.0
-    let unit_dir = dir.unit_vector();
-    Box::new(move |ray: &Ray| {
-        let gradient_pos = dot(unit_dir, ray.direction.unit_vector());
+impl Sky for GradientSky {
+    fn colour(&self, ray: &Ray) -> Colour {
+        let gradient_pos = dot(self.direction, ray.direction.unit_vector());
         let t = 0.5 * (gradient_pos + 1.0);
-        1.0 * ((1.0 - t) * col1 + t * col2)
-    })
+        self.col1.lerp(self.col2, t)
+    }
+}
+
+// An equirectangular environment map wrapped around the scene. Radiance (.hdr)
+// files keep their full range; other image formats are decoded from sRGB.
+pub struct HdriSky {
+    width: u32,
+    height: u32,
+    pixels: Vec<Colour>,
+    // in turns
+    rotation: f64,
+    exposure: f64,
+    // picks pixels by how much light comes from them, allowing for the rows
+    // near the poles covering less of the sphere; None for a black image
+    pixel_table: Option<AliasTable>,
+}
+
+impl HdriSky {
+    pub fn from_file(filename: &str, rotation_degrees: f64, exposure: f64) -> Result<HdriSky> {
+        let (width, height, pixels) = if filename.to_lowercase().ends_with(".hdr") {
+            let decoder = HdrDecoder::new(BufReader::new(File::open(filename)?))?;
+            let meta = decoder.metadata();
+            let pixels = decoder
+                .read_image_hdr()?
+                .into_iter()
+                .map(|p| Colour::new(p[0], p[1], p[2]))
+                .collect::<Vec<_>>();
+            (meta.width, meta.height, pixels)
+        } else {
+            let image = image::io::Reader::open(filename)?.decode()?.into_rgb8();
+            let pixels = image
+                .pixels()
+                .map(|p| srgb_to_linear(Colour::new(p[0], p[1], p[2]) / 255.0))
+                .collect::<Vec<_>>();
+            (image.width(), image.height(), pixels)
+        };
+        Ok(HdriSky::new(
+            width,
+            height,
+            pixels,
+            rotation_degrees,
+            exposure,
+        ))
+    }
+    pub fn new(
+        width: u32,
+        height: u32,
+        pixels: Vec<Colour>,
+        rotation_degrees: f64,
+        exposure: f64,
+    ) -> HdriSky {
+        let weights = pixels
+            .iter()
+            .enumerate()
+            .map(|(k, p)| {
+                let row = k as u32 / width;
+                let theta = PI * (1.0 - (row as f64 + 0.5) / height as f64);
+                (0.2126 * p.x + 0.7152 * p.y + 0.0722 * p.z).max(0.0) * theta.sin()
+            })
+            .collect::<Vec<_>>();
+        HdriSky {
+            width,
+            height,
+            pixel_table: AliasTable::new(&weights),
+            pixels,
+            rotation: rotation_degrees / 360.0,
+            exposure,
+        }
+    }
+    // the pixel a direction falls in
+    fn pixel_index(&self, direction: Vec3) -> usize {
+        let (u, v) = get_sphere_uv(direction);
+        let u = (u + self.rotation).rem_euclid(1.0);
+        let i = u32::min((u * self.width as f64) as u32, self.width - 1);
+        let j = u32::min(((1.0 - v) * self.height as f64) as u32, self.height - 1);
+        (j * self.width + i) as usize
+    }
+}
+
+impl Sky for HdriSky {
+    fn colour(&self, ray: &Ray) -> Colour {
+        self.exposure * self.pixels[self.pixel_index(ray.direction)]
+    }
+    fn sampled_as_light(&self) -> bool {
+        self.pixel_table.is_some()
+    }
+    // a pixel from the table, then a point in it picked uniformly by its uv
+    // coordinates, undoing get_sphere_uv
+    fn sample(&self) -> (Vec3, f64, Colour) {
+        let table = match &self.pixel_table {
+            Some(table) => table,
+            None => return (Vec3::new(0, 1, 0), 0.0, Colour::new(0, 0, 0)),
+        };
+        let index = table.sample();
+        let (i, j) = (index as u32 % self.width, index as u32 / self.width);
+        let mut rng = random::rng();
+        let u = (i as f64 + rng.gen_range(0.0..1.0)) / self.width as f64 - self.rotation;
+        let v = 1.0 - (j as f64 + rng.gen_range(0.0..1.0)) / self.height as f64;
+        let (phi, theta) = (2.0 * PI * u, PI * v);
+        let direction = Vec3::new(
+            -theta.sin() * phi.cos(),
+            -theta.cos(),
+            theta.sin() * phi.sin(),
+        );
+        (
+            direction,
+            self.pdf(direction),
+            self.exposure * self.pixels[index],
+        )
+    }
+    // each pixel covers 1/(width * height) of the uv square, which maps onto
+    // the sphere with 2 pi^2 sin(theta) steradians per unit area
+    fn pdf(&self, direction: Vec3) -> f64 {
+        let table = match &self.pixel_table {
+            Some(table) => table,
+            None => return 0.0,
+        };
+        let direction = direction.unit_vector();
+        let sin_theta = (1.0 - direction.y * direction.y).max(0.0).sqrt();
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        let pixel_area = 1.0 / (self.width as f64 * self.height as f64);
+        table.probability(self.pixel_index(direction)) / pixel_area / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+#[test]
+fn hdri_sampling_test() {
+    let pixels = (0..32)
+        .map(|k| Colour::new(1, 1, 1) * (1 + k % 5) as f64)
+        .collect::<Vec<_>>();
+    let sky = HdriSky::new(8, 4, pixels, 90.0, 2.0);
+    assert!(sky.sampled_as_light());
+    for _ in 0..100 {
+        let (direction, pdf, colour) = sky.sample();
+        assert!((sky.pdf(direction) - pdf).abs() < 1e-9 * pdf);
+        let ray = Ray::new(Point3::new(0, 0, 0), direction, 0.0);
+        assert_eq!(sky.colour(&ray), colour);
+    }
+    // the density covers the whole sphere once
+    let samples = 40000;
+    let total = (0..samples)
+        .map(|_| sky.pdf(random_in_unit_sphere().unit_vector()))
+        .sum::<f64>()
+        * 4.0
+        * PI
+        / samples as f64;
+    assert!((total - 1.0).abs() < 0.05);
+
+    let black = HdriSky::new(2, 1, vec![Colour::new(0, 0, 0); 2], 0.0, 1.0);
+    assert!(!black.sampled_as_light());
 }
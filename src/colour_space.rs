@@ -0,0 +1,54 @@
+use crate::hitting::Colour;
+use crate::math::Vec3;
+
+// The renderer works in linear light with sRGB primaries. These convert to and
+// from the sRGB transfer curve (IEC 61966-2-1), and from CIE XYZ (D65 white).
+
+pub fn srgb_to_linear(c: Colour) -> Colour {
+    Colour::new(
+        srgb_channel_to_linear(c.x),
+        srgb_channel_to_linear(c.y),
+        srgb_channel_to_linear(c.z),
+    )
+}
+
+pub fn linear_to_srgb(c: Colour) -> Colour {
+    Colour::new(
+        linear_channel_to_srgb(c.x),
+        linear_channel_to_srgb(c.y),
+        linear_channel_to_srgb(c.z),
+    )
+}
+
+pub fn xyz_to_rgb(v: Vec3) -> Colour {
+    Colour::new(
+        3.2406 * v.x - 1.5372 * v.y - 0.4986 * v.z,
+        -0.9689 * v.x + 1.8758 * v.y + 0.0415 * v.z,
+        0.0557 * v.x - 0.2040 * v.y + 1.0570 * v.z,
+    )
+}
+
+fn srgb_channel_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_channel_to_srgb(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[test]
+fn colour_space_test() {
+    let c = Colour::new(0.1, 0.5, 0.9);
+    assert!((linear_to_srgb(srgb_to_linear(c)) - c).near_zero());
+    // D65 white
+    let white = xyz_to_rgb(Vec3::new(0.9505, 1.0, 1.089));
+    assert!((white - Colour::new(1, 1, 1)).length() < 1e-3);
+}
@@ -1,65 +1,992 @@
-use anyhow::{anyhow, bail, Result};
 use json5;
+use serde::de::{self, DeserializeOwned};
+use serde::{Deserialize, Deserializer};
 use serde_derive::Deserialize;
+use serde_json::{Map, Value};
 
 use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt::{self, Write};
 use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
+use std::io::{self, Read};
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 
-use crate::camera::{gradient_background, Camera, Sky};
-use crate::hitting::{BVHNode, Colour, Hittable, Material};
+use crate::atmosphere::AtmosphericFog;
+use crate::camera::{
+    ApertureShape, Camera, GradientSky, HdriSky, Sky, SolidSky, TIME_MAX, TIME_MIN,
+};
+use crate::csg;
+use crate::hitting::{
+    colour_from_kelvin, find_emitters, surrounding_box, BVHNode, Colour, Emitter, Hittable,
+    Material, MaterialOverride, VisibilityFlags, AABB,
+};
 use crate::materials;
-use crate::math::{Point3, Vec3};
+use crate::math::{Mat4, Point3, Quat, Vec3};
 use crate::objects;
-use crate::textures::{self, Texture};
+use crate::sky;
+use crate::stats;
+use crate::textures::{self, Texture, VoronoiMode};
 use crate::transforms;
 
-pub fn load_config(filename: &PathBuf) -> Result<(Camera, Arc<dyn Hittable>, Sky, f64)> {
-    let mut config_string = String::new();
-    File::open(filename)?.read_to_string(&mut config_string)?;
-    let config = json5::from_str(&config_string)?;
+// The camera, the world, the lights in it that can be sampled directly, the
+// background, any fog, and the image's aspect ratio
+pub type Scene = (
+    Camera,
+    Arc<dyn Hittable>,
+    Vec<Arc<dyn Emitter>>,
+    Box<dyn Sky>,
+    Option<AtmosphericFog>,
+    f64,
+);
+
+// Picks out some of the objects in the world for debugging. The rest are
+// either left out or turned matte black, still casting shadows.
+pub struct Isolate {
+    pub names: Vec<String>,
+    pub remove_others: bool,
+}
+
+// Everything that can go wrong loading a scene, so that code embedding the
+// renderer can tell a typo from a missing file
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    // the message, and the line and column if known (otherwise 0)
+    Parse(String, usize, usize),
+    MissingTexture(String),
+    MissingMaterial(String),
+    MissingObject(String),
+    // things that depend on each other in a loop, or on nothing that exists
+    ImpossibleConstruction(String),
+    InvalidDimensions(String),
+    // any other value the config gets wrong
+    Invalid(String),
+    // a mesh, image or other file the config refers to
+    Load(anyhow::Error),
+    // what was happening when the error underneath it went wrong
+    Context(String, Box<ConfigError>),
+}
+
+impl ConfigError {
+    // The error underneath any context
+    pub fn root(&self) -> &ConfigError {
+        match self {
+            ConfigError::Context(_, inner) => inner.root(),
+            _ => self,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::Load(e) => write!(f, "{}", e),
+            ConfigError::Parse(message, _, _)
+            | ConfigError::MissingTexture(message)
+            | ConfigError::MissingMaterial(message)
+            | ConfigError::MissingObject(message)
+            | ConfigError::ImpossibleConstruction(message)
+            | ConfigError::InvalidDimensions(message)
+            | ConfigError::Invalid(message)
+            | ConfigError::Context(message, _) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            // these already display as their own first message
+            ConfigError::Io(e) => e.source(),
+            ConfigError::Load(e) => e.source(),
+            ConfigError::Context(_, inner) => Some(inner.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> ConfigError {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(e: serde_json::Error) -> ConfigError {
+        ConfigError::Parse(e.to_string(), e.line(), e.column())
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl From<serde_yaml::Error> for ConfigError {
+    fn from(e: serde_yaml::Error) -> ConfigError {
+        let (line, column) = e.location().map_or((0, 0), |l| (l.line(), l.column()));
+        ConfigError::Parse(e.to_string(), line, column)
+    }
+}
+
+impl From<anyhow::Error> for ConfigError {
+    fn from(e: anyhow::Error) -> ConfigError {
+        ConfigError::Load(e)
+    }
+}
+
+type Result<T, E = ConfigError> = std::result::Result<T, E>;
+
+// Like anyhow's Context, wrapping errors in a description of what was going on
+trait Context<T> {
+    fn context(self, message: &str) -> Result<T>;
+    fn with_context<F: FnOnce() -> String>(self, f: F) -> Result<T>;
+}
+
+impl<T, E: Into<ConfigError>> Context<T> for std::result::Result<T, E> {
+    fn context(self, message: &str) -> Result<T> {
+        self.with_context(|| message.to_string())
+    }
+
+    fn with_context<F: FnOnce() -> String>(self, f: F) -> Result<T> {
+        self.map_err(|e| ConfigError::Context(f(), Box::new(e.into())))
+    }
+}
+
+// Reads a JSON5 config, or a YAML one if the file ends in .yaml or .yml. The
+// scene is as it is at time, in seconds, if anything in it is animated, and
+// seen through one of its namedViews if view is given.
+pub fn load_config(
+    filename: impl AsRef<Path>,
+    overrides: &[(String, String)],
+    time: f64,
+    isolate: Option<&Isolate>,
+    view: Option<&str>,
+) -> Result<Scene> {
+    let filename = filename.as_ref();
+    if is_yaml(filename) {
+        return load_config_yaml(filename, overrides, time, isolate, view);
+    }
+    let config_value = read_config_value(filename)?;
+    let config = parse_config(config_value, filename, overrides, time, view)?;
+    build_scene(config, isolate)
+}
+
+pub fn load_config_yaml(
+    filename: impl AsRef<Path>,
+    overrides: &[(String, String)],
+    time: f64,
+    isolate: Option<&Isolate>,
+    view: Option<&str>,
+) -> Result<Scene> {
+    let filename = filename.as_ref();
+    let config_value = read_yaml_value(filename)?;
+    let config = parse_config(config_value, filename, overrides, time, view)?;
+    build_scene(config, isolate)
+}
+
+// Describes each of the config's namedViews, one per line
+pub fn list_views(filename: impl AsRef<Path>) -> Result<String> {
+    let filename = filename.as_ref();
+    let config_value = if is_yaml(filename) {
+        read_yaml_value(filename)?
+    } else {
+        read_config_value(filename)?
+    };
+    let config = parse_config(config_value, filename, &[], 0.0, None)?;
+    let mut names: Vec<&String> = config.named_views.keys().collect();
+    names.sort_unstable();
+    let mut report = String::new();
+    for name in names {
+        let view = &config.named_views[name];
+        let (from, at) = (view.look_from, view.look_at);
+        let _ = writeln!(
+            report,
+            "{}: from {} looking at {}",
+            name,
+            Vec3::new(from[0], from[1], from[2]),
+            Vec3::new(at[0], at[1], at[2])
+        );
+    }
+    Ok(report)
+}
+
+// Finds every file the config includes, directly or not, by its canonical path
+pub fn included_files(filename: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+    let filename = filename.as_ref();
+    let config_value = if is_yaml(filename) {
+        read_yaml_value(filename)?
+    } else {
+        read_config_value(filename)?
+    };
+    let includes = includes_of(&config_value, filename)?;
+    let mut include_stack = vec![filename
+        .canonicalize()
+        .with_context(|| format!("Reading config {}", filename.display()))?];
+    let mut loaded = Vec::new();
+    load_includes(&includes, filename, &mut include_stack, &mut loaded)?;
+    Ok(loaded)
+}
+
+pub struct Validation {
+    pub report: String,
+    pub warnings: usize,
+}
+
+// Loads everything in the config without rendering it, and describes the
+// scene: each object's type and bounds, and anything that looks wrong.
+// Broken references are errors, since the scene can't be built at all.
+pub fn validate_config(
+    filename: impl AsRef<Path>,
+    overrides: &[(String, String)],
+    time: f64,
+) -> Result<Validation> {
+    let filename = filename.as_ref();
+    let config_value = if is_yaml(filename) {
+        read_yaml_value(filename)?
+    } else {
+        read_config_value(filename)?
+    };
+    // included files are libraries, so only this file's unused names are worth mentioning
+    let defined_here = |section: &str| -> Vec<String> {
+        let names = config_value.get(section).and_then(Value::as_object);
+        names.map_or_else(Vec::new, |names| names.keys().cloned().collect())
+    };
+    let (own_textures, own_materials) = (defined_here("textures"), defined_here("materials"));
+    let config = parse_config(config_value, filename, overrides, time, None)?;
     let textures = build_textures(&config)?;
     let materials = build_materials(&config, &textures)?;
-    let hittables = build_hittables(&config, &materials)?;
-    let world = config
+    let hittables = build_hittables(&config, &textures, &materials)?;
+    build_camera(&config.camera, &hittables)?;
+    let (start_time, end_time) = (config.camera.start_time, config.camera.end_time);
+
+    let mut report = String::from("Objects:\n");
+    let mut warnings = Vec::new();
+    // everything that uses a texture or material holds a reference to it,
+    // so the only reference to an unused one is the one in its own list
+    let mut unused = |kind: &str, names: &[String], references: &dyn Fn(&str) -> usize| {
+        let mut names: Vec<&String> = names.iter().filter(|n| references(n) == 1).collect();
+        names.sort_unstable();
+        for name in names {
+            warnings.push(format!("{} {} is never used", kind, name));
+        }
+    };
+    unused("Texture", &own_textures, &|name| {
+        Arc::strong_count(&textures[name])
+    });
+    unused("Material", &own_materials, &|name| {
+        Arc::strong_count(&materials[name])
+    });
+    let mut names: Vec<&str> = hittables.keys().copied().collect();
+    names.sort_unstable();
+    for name in names {
+        let entry = &config.objects[name];
+        match hittables[name].bounding_box(start_time, end_time) {
+            Some(bbox) => {
+                let _ = writeln!(
+                    report,
+                    "  {} ({}): {} to {}",
+                    name, entry.kind, bbox.minimum, bbox.maximum
+                );
+            }
+            None => {
+                let _ = writeln!(report, "  {} ({}): unbounded", name, entry.kind);
+                warnings.push(format!(
+                    "{} has no bounding box, so it's left out of the BVH and tested against every ray",
+                    name
+                ));
+            }
+        }
+        match &entry.object {
+            ObjectConfig::Sphere { radius, .. } | ObjectConfig::MovingSphere { radius, .. }
+                if *radius <= 0.0 =>
+            {
+                warnings.push(format!(
+                    "The radius of sphere {} must be positive, not {}",
+                    name, radius
+                ))
+            }
+            ObjectConfig::Rect {
+                corner0, corner1, ..
+            } if (0..3).filter(|&i| corner0[i] == corner1[i]).count() > 1 => {
+                warnings.push(format!("Rect {} has no area", name))
+            }
+            _ => (),
+        }
+        if let ObjectConfig::ConstantMedium { boundary, .. }
+        | ObjectConfig::HeterogeneousMedium { boundary, .. } = &entry.object
+        {
+            let bounded = hittables
+                .get(boundary as &str)
+                .and_then(|b| b.bounding_box(start_time, end_time))
+                .is_some();
+            if !bounded {
+                warnings.push(format!(
+                    "Medium {} has an unbounded boundary {}, so rays can't find where they leave it",
+                    name, boundary
+                ));
+            }
+        }
+    }
+
+    if config.world.is_empty() {
+        warnings.push(String::from("The world has no objects in it"));
+    } else {
+        let world = config
+            .world
+            .iter()
+            .map(|s| lookup(&hittables, "Object", s, "world").map(Arc::clone))
+            .collect::<Result<Vec<Arc<dyn Hittable>>>>()?;
+        match BVHNode::from_vec(world, start_time, end_time)?.bounding_box(start_time, end_time) {
+            Some(bbox) => {
+                let _ = writeln!(
+                    report,
+                    "World: {} objects, {} to {}",
+                    config.world.len(),
+                    bbox.minimum,
+                    bbox.maximum
+                );
+            }
+            None => {
+                let _ = writeln!(report, "World: {} objects, unbounded", config.world.len());
+            }
+        }
+    }
+    if warnings.is_empty() {
+        report.push_str("No problems found\n");
+    } else {
+        report.push_str("Warnings:\n");
+        for warning in &warnings {
+            let _ = writeln!(report, "  {}", warning);
+        }
+    }
+    Ok(Validation {
+        report,
+        warnings: warnings.len(),
+    })
+}
+
+// Applies overrides and animation to the raw config, then reads it and
+// everything it includes
+fn parse_config(
+    mut config_value: Value,
+    filename: &Path,
+    overrides: &[(String, String)],
+    time: f64,
+    view: Option<&str>,
+) -> Result<MasterConfig> {
+    // overrides of the camera apply to whichever view is chosen
+    if let Some(view) = view {
+        select_view(&mut config_value, view)?;
+    }
+    // included definitions are merged in first, so that overrides reach them too
+    let includes = includes_of(&config_value, filename)?;
+    let mut include_stack = vec![filename
+        .canonicalize()
+        .with_context(|| format!("Reading config {}", filename.display()))?];
+    let included = load_includes(&includes, filename, &mut include_stack, &mut Vec::new())?;
+    merge_definitions(&mut config_value, included, false);
+    for (key, value) in overrides {
+        apply_override(&mut config_value, key, value)?;
+    }
+    substitute_variables(&mut config_value)
+        .with_context(|| format!("Substituting variables in {}", filename.display()))?;
+    evaluate_at_time(&mut config_value, time)
+        .with_context(|| format!("Animating config {}", filename.display()))?;
+    serde_json::from_value(config_value)
+        .with_context(|| format!("Parsing config {}", filename.display()))
+}
+
+fn build_scene(config: MasterConfig, isolate: Option<&Isolate>) -> Result<Scene> {
+    let textures = build_textures(&config)?;
+    let materials = build_materials(&config, &textures)?;
+    let hittables = build_hittables(&config, &textures, &materials)?;
+    let mut world = config
         .world
         .iter()
-        .map(|s| {
-            hittables
-                .get(&s as &str)
-                .and_then(|a| Some(Arc::clone(a)))
-                .ok_or(anyhow!("Object {} does not exist", s))
-        })
+        .map(|s| lookup(&hittables, "Object", s, "world").map(Arc::clone))
         .collect::<Result<Vec<Arc<dyn Hittable>>>>()?;
-    let world = BVHNode::from_vec(world, config.camera.start_time, config.camera.end_time);
-    let aspect_ratio = config.camera.aspect_ratio[0] / config.camera.aspect_ratio[1];
-    let camera = Camera::new(
-        Point3::new(
-            config.camera.look_from[0],
-            config.camera.look_from[1],
-            config.camera.look_from[2],
-        ),
-        Point3::new(
-            config.camera.look_at[0],
-            config.camera.look_at[1],
-            config.camera.look_at[2],
-        ),
-        Point3::new(
-            config.camera.direction_up[0],
-            config.camera.direction_up[1],
-            config.camera.direction_up[2],
-        ),
-        config.camera.vertical_fov,
-        aspect_ratio,
-        config.camera.aperture,
-        config.camera.focus_dist,
-        config.camera.start_time,
-        config.camera.end_time,
+    if let Some(isolate) = isolate {
+        world = isolate_objects(&config.world, world, isolate)?;
+    }
+    let lights = find_emitters(&world);
+    let bvh_start = Instant::now();
+    let world = BVHNode::from_vec(world, config.camera.start_time, config.camera.end_time)
+        .context("Building the world")?;
+    stats::BVH_BUILD_NANOS.add(bvh_start.elapsed().as_nanos() as u64);
+    let camera = build_camera(&config.camera, &hittables)?;
+    let sky = get_background(config.background)?;
+    let atmosphere = config.atmosphere.map(|atmosphere| match atmosphere {
+        AtmosphereConfig::Fog { density, colour } => AtmosphericFog {
+            fog_colour: Colour::new(colour[0], colour[1], colour[2]),
+            density,
+        },
+    });
+    let aspect_ratio = camera.aspect_ratio();
+    Ok((camera, world, lights, sky, atmosphere, aspect_ratio))
+}
+
+fn isolate_objects(
+    names: &[String],
+    world: Vec<Arc<dyn Hittable>>,
+    isolate: &Isolate,
+) -> Result<Vec<Arc<dyn Hittable>>> {
+    for name in &isolate.names {
+        if !names.contains(name) {
+            return Err(ConfigError::MissingObject(format!(
+                "Can't isolate {}, since it isn't in the world",
+                name
+            )));
+        }
+    }
+    let black = materials::Lambertian::with_colour(Colour::new(0, 0, 0));
+    Ok(names
+        .iter()
+        .zip(world)
+        .filter_map(|(name, object)| {
+            if isolate.names.contains(name) {
+                Some(object)
+            } else if isolate.remove_others {
+                None
+            } else {
+                Some(MaterialOverride::new(&object, &black))
+            }
+        })
+        .collect())
+}
+
+fn build_camera(
+    config: &CameraConfig,
+    hittables: &HashMap<&str, Arc<dyn Hittable>>,
+) -> Result<Camera> {
+    let (start_time, end_time) = (config.start_time, config.end_time);
+    if start_time < TIME_MIN || end_time > TIME_MAX || start_time > end_time {
+        return Err(ConfigError::Invalid(format!(
+            "Camera needs {} <= startTime <= endTime <= {}, but they are {} and {}",
+            TIME_MIN, TIME_MAX, start_time, end_time
+        )));
+    }
+    let look_from = Point3::new(
+        config.look_from[0],
+        config.look_from[1],
+        config.look_from[2],
     );
-    let sky = get_background(config.background);
-    Ok((camera, world, sky, aspect_ratio))
+    let look_at = Point3::new(config.look_at[0], config.look_at[1], config.look_at[2]);
+    let direction_up = Vec3::new(
+        config.direction_up[0],
+        config.direction_up[1],
+        config.direction_up[2],
+    );
+    let camera = match config.camera_type {
+        CameraType::Perspective => {
+            let missing = |field| ConfigError::Invalid(format!("Camera needs {}", field));
+            let aspect_ratio = config.aspect_ratio.ok_or_else(|| missing("aspectRatio"))?;
+            let focus_dist = match (config.focus_dist, &config.focus_target) {
+                (Some(dist), None) => dist,
+                (None, Some(target)) => {
+                    let bbox = lookup(hittables, "Object", target, "camera")?
+                        .bounding_box(config.start_time, config.end_time)
+                        .ok_or_else(|| {
+                            ConfigError::Invalid(format!(
+                                "Camera can't focus on {}, which has no bounds",
+                                target
+                            ))
+                        })?;
+                    let centre = (bbox.minimum + bbox.maximum) / 2.0;
+                    (centre - look_from).length()
+                }
+                (Some(_), Some(_)) => {
+                    return Err(ConfigError::Invalid(String::from(
+                        "Camera can't have both distanceToFocus and focusTarget",
+                    )))
+                }
+                (None, None) => return Err(missing("distanceToFocus or focusTarget")),
+            };
+            let aperture_shape = match config.aperture_shape {
+                ApertureShapeConfig::Circle => ApertureShape::Circle,
+                ApertureShapeConfig::Hexagon => ApertureShape::Hexagon,
+                ApertureShapeConfig::Pentagon(n_blades) if n_blades >= 3 => {
+                    ApertureShape::Pentagon(n_blades)
+                }
+                ApertureShapeConfig::Pentagon(n_blades) => {
+                    return Err(ConfigError::Invalid(format!(
+                        "An aperture can't have {} blades",
+                        n_blades
+                    )))
+                }
+                ApertureShapeConfig::Square => ApertureShape::Square,
+            };
+            Camera::new(
+                look_from,
+                look_at,
+                direction_up,
+                config.vertical_fov.ok_or_else(|| missing("fieldOfView"))?,
+                aspect_ratio[0] / aspect_ratio[1],
+                config.aperture.ok_or_else(|| missing("aperture"))?,
+                focus_dist,
+                config.start_time,
+                config.end_time,
+            )
+            .with_aperture_shape(aperture_shape)
+        }
+        CameraType::Panoramic => {
+            if config.vertical_fov.is_some()
+                || config.aperture.is_some()
+                || config.focus_dist.is_some()
+                || config.focus_target.is_some()
+            {
+                return Err(ConfigError::Invalid(String::from(
+                    "Panoramic cameras have no fieldOfView, aperture, or focus",
+                )));
+            }
+            if let Some([w, h]) = config.aspect_ratio {
+                if w != 2.0 * h {
+                    return Err(ConfigError::Invalid(String::from(
+                        "Panoramic cameras need an aspectRatio of 2:1",
+                    )));
+                }
+            }
+            Camera::panoramic(
+                look_from,
+                look_at,
+                direction_up,
+                config.start_time,
+                config.end_time,
+            )
+        }
+    };
+    if let Some(gamma) = config.gamma {
+        if gamma <= 0.0 {
+            return Err(ConfigError::Invalid(format!(
+                "Camera gamma must be positive, not {}",
+                gamma
+            )));
+        }
+    }
+    Ok(camera.with_gamma(config.gamma))
+}
+
+// The value of an animated field at time. The keyframes must be in order of
+// time; before the first and after the last, the field holds still.
+fn interpolate(
+    keyframes: &[Keyframe],
+    interpolation: KeyframeInterpolation,
+    time: f64,
+) -> [f64; 3] {
+    let vector = |k: &Keyframe| Vec3::new(k.value[0], k.value[1], k.value[2]);
+    let last = keyframes.len() - 1;
+    let i = match keyframes.iter().position(|k| k.time > time) {
+        Some(0) => return keyframes[0].value,
+        None => return keyframes[last].value,
+        Some(i) => i,
+    };
+    let (from, to) = (&keyframes[i - 1], &keyframes[i]);
+    let span = to.time - from.time;
+    let t = (time - from.time) / span;
+    let value = match interpolation {
+        KeyframeInterpolation::Linear => vector(from).lerp(vector(to), t),
+        KeyframeInterpolation::Hermite => {
+            // Catmull-Rom tangents: the slope between each keyframe's
+            // neighbours, or to its one neighbour at either end
+            let tangent = |j: usize| {
+                let before = &keyframes[j.saturating_sub(1)];
+                let after = &keyframes[(j + 1).min(last)];
+                (vector(after) - vector(before)) / (after.time - before.time)
+            };
+            let (t2, t3) = (t * t, t * t * t);
+            (2.0 * t3 - 3.0 * t2 + 1.0) * vector(from)
+                + (t3 - 2.0 * t2 + t) * span * tangent(i - 1)
+                + (3.0 * t2 - 2.0 * t3) * vector(to)
+                + (t3 - t2) * span * tangent(i)
+        }
+    };
+    [value.x, value.y, value.z]
+}
+
+fn read_config_value(filename: &Path) -> Result<Value> {
+    let mut config_string = String::new();
+    File::open(filename)
+        .with_context(|| format!("Opening config {}", filename.display()))?
+        .read_to_string(&mut config_string)?;
+    json5::from_str(&config_string).map_err(|e| {
+        let json5::Error::Message { location, .. } = &e;
+        let (line, column) = location.as_ref().map_or((0, 0), |l| (l.line, l.column));
+        ConfigError::Parse(
+            format!(
+                "Parsing config {}\n{}",
+                filename.display(),
+                format_config_error(&config_string, &e)
+            ),
+            line,
+            column,
+        )
+    })
+}
+
+fn is_yaml(filename: &Path) -> bool {
+    matches!(
+        filename.extension().and_then(|e| e.to_str()),
+        Some("yaml") | Some("yml")
+    )
+}
+
+#[cfg(feature = "yaml")]
+fn read_yaml_value(filename: &Path) -> Result<Value> {
+    let file =
+        File::open(filename).with_context(|| format!("Opening config {}", filename.display()))?;
+    serde_yaml::from_reader(file).with_context(|| format!("Parsing config {}", filename.display()))
+}
+
+#[cfg(not(feature = "yaml"))]
+fn read_yaml_value(filename: &Path) -> Result<Value> {
+    Err(ConfigError::Invalid(format!(
+        "Can't read config {}: this build doesn't have the yaml feature",
+        filename.display()
+    )))
+}
+
+// Shows the line the error happened on, with a ^ under the offending character
+pub fn format_config_error(source: &str, err: &json5::Error) -> String {
+    let json5::Error::Message { msg, location } = err;
+    let location = if let Some(l) = location {
+        l
+    } else {
+        return msg.clone();
+    };
+    let line = source.lines().nth(location.line - 1).unwrap_or("");
+    // pest reports columns in characters, and tabs need to stay tabs to line up
+    let pointer = line
+        .chars()
+        .take(location.column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    format!(
+        "line {}, column {}: {}\n{}\n{}^",
+        location.line,
+        location.column,
+        // pest's own message repeats the source line, so only keep its summary
+        msg.lines()
+            .last()
+            .unwrap_or("")
+            .trim_start_matches(&[' ', '='][..]),
+        line,
+        pointer
+    )
+}
+
+fn lookup<'a, T>(list: &'a HashMap<&str, T>, kind: &str, key: &str, user: &str) -> Result<&'a T> {
+    let missing = match kind {
+        "Texture" => ConfigError::MissingTexture,
+        "Material" => ConfigError::MissingMaterial,
+        _ => ConfigError::MissingObject,
+    };
+    list.get(key).ok_or_else(|| {
+        let suggestion = list
+            .keys()
+            .map(|k| (levenshtein(k, key), k))
+            .filter(|(d, _)| *d <= usize::max(2, key.chars().count() / 3))
+            .min()
+            .map(|(_, k)| format!(" (did you mean {}?)", k))
+            .unwrap_or_default();
+        missing(format!(
+            "{} {} used by {} does not exist{}",
+            kind, key, user, suggestion
+        ))
+    })
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = usize::min(substitution, usize::min(row[j], row[j + 1]) + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// Replaces the camera with one of the namedViews
+fn select_view(config: &mut Value, name: &str) -> Result<()> {
+    let view = config
+        .get("namedViews")
+        .and_then(|views| views.get(name))
+        .cloned()
+        .ok_or_else(|| ConfigError::Invalid(format!("The config has no view named {}", name)))?;
+    config["camera"] = view;
+    Ok(())
+}
+
+// Sets the field at a dot-separated path like "camera.lookFrom.0" to value.
+// The value is read as JSON5 if possible, and as a plain string otherwise.
+fn apply_override(config: &mut Value, key: &str, value: &str) -> Result<()> {
+    let mut target = config;
+    for field in key.split('.') {
+        target = match target {
+            Value::Object(map) => map.entry(field).or_insert(Value::Null),
+            Value::Array(list) => field
+                .parse::<usize>()
+                .ok()
+                .and_then(move |i| list.get_mut(i))
+                .ok_or_else(|| {
+                    ConfigError::Invalid(format!("Cannot override {}: no index {}", key, field))
+                })?,
+            _ => {
+                return Err(ConfigError::Invalid(format!(
+                    "Cannot override {}: {} is not an object or array",
+                    key, field
+                )))
+            }
+        };
+    }
+    *target = json5::from_str(value).unwrap_or_else(|_| Value::String(String::from(value)));
+    Ok(())
+}
+
+// Takes the top-level variables map, e.g. {ior: 1.5, floor: "grey"}, and
+// replaces every string like "$ior" elsewhere in the config with its value.
+// This runs after overrides, so variables can be set from the command line.
+fn substitute_variables(config: &mut Value) -> Result<()> {
+    let variables = match config.as_object_mut().and_then(|c| c.remove("variables")) {
+        Some(Value::Object(variables)) => variables,
+        Some(_) => {
+            return Err(ConfigError::Invalid(String::from(
+                "Variables must be an object like {name: value}",
+            )))
+        }
+        None => serde_json::Map::new(),
+    };
+    substitute(config, &variables)
+}
+
+fn substitute(value: &mut Value, variables: &serde_json::Map<String, Value>) -> Result<()> {
+    match value {
+        Value::String(s) => {
+            if let Some(name) = variable_name(s) {
+                *value = variables
+                    .get(name)
+                    .ok_or_else(|| ConfigError::Invalid(format!("Undefined variable ${}", name)))?
+                    .clone();
+            }
+        }
+        Value::Array(list) => {
+            for v in list {
+                substitute(v, variables)?;
+            }
+        }
+        Value::Object(map) => {
+            for v in map.values_mut() {
+                substitute(v, variables)?;
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+// "$ior" refers to the variable ior; strings like "$5" or "a$b" are left alone
+fn variable_name(s: &str) -> Option<&str> {
+    let name = s.strip_prefix('$')?;
+    let mut chars = name.chars();
+    let starts_well = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    if starts_well && chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+// Camera fields that can be animated, by giving a list of keyframes like
+// [{time: 0, value: [0, 0, 0]}, {time: 2, value: [1, 0, 0]}] instead of a value
+const ANIMATED_CAMERA_FIELDS: [&str; 2] = ["lookFrom", "lookAt"];
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Keyframe {
+    time: f64,
+    value: [f64; 3],
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum KeyframeInterpolation {
+    #[default]
+    Linear,
+    // a smooth curve through the keyframes
+    Hermite,
+}
+
+// Replaces each animated field with its value at time, interpolating between
+// keyframes as keyframeInterpolation says and holding still before the first
+// and after the last
+fn evaluate_at_time(config: &mut Value, time: f64) -> Result<()> {
+    if let Some(Value::Object(camera)) = config.get_mut("camera") {
+        evaluate_camera_at_time(camera, "camera", time)?;
+    }
+    if let Some(Value::Object(views)) = config.get_mut("namedViews") {
+        for (name, view) in views {
+            if let Value::Object(view) = view {
+                evaluate_camera_at_time(view, &format!("namedViews.{}", name), time)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn evaluate_camera_at_time(
+    camera: &mut serde_json::Map<String, Value>,
+    path: &str,
+    time: f64,
+) -> Result<()> {
+    let interpolation: KeyframeInterpolation = match camera.remove("keyframeInterpolation") {
+        Some(value) => serde_json::from_value(value)
+            .with_context(|| format!("Parsing {}.keyframeInterpolation", path))?,
+        None => KeyframeInterpolation::default(),
+    };
+    // cameraKeyframes keyframes lookFrom and lookAt together, and takes
+    // precedence over them
+    if let Some(keyframes) = camera.remove("cameraKeyframes") {
+        let keyframes = match keyframes {
+            Value::Array(keyframes) if !keyframes.is_empty() => keyframes,
+            _ => {
+                return Err(ConfigError::Invalid(format!(
+                    "{}.cameraKeyframes needs at least one keyframe",
+                    path
+                )))
+            }
+        };
+        for &field in &ANIMATED_CAMERA_FIELDS {
+            let split = keyframes
+                .iter()
+                .map(|keyframe| {
+                    let mut split = Map::new();
+                    for (key, name) in &[("time", "time"), ("value", field)] {
+                        let value = keyframe.get(name).cloned().ok_or_else(|| {
+                            ConfigError::Invalid(format!(
+                                "Every keyframe in {}.cameraKeyframes needs {}",
+                                path, name
+                            ))
+                        })?;
+                        split.insert(String::from(*key), value);
+                    }
+                    Ok(Value::Object(split))
+                })
+                .collect::<Result<Vec<Value>>>()?;
+            camera.insert(String::from(field), Value::Array(split));
+        }
+    }
+    for &field in &ANIMATED_CAMERA_FIELDS {
+        let value = match camera.get_mut(field) {
+            Some(value) if value.get(0).is_some_and(Value::is_object) => value,
+            _ => continue,
+        };
+        let keyframes: Vec<Keyframe> = serde_json::from_value(value.take())
+            .with_context(|| format!("Reading keyframes for {}.{}", path, field))?;
+        if keyframes.windows(2).any(|w| w[0].time >= w[1].time) {
+            return Err(ConfigError::Invalid(format!(
+                "Keyframes for {}.{} must be in order of time",
+                path, field
+            )));
+        }
+        let [x, y, z] = interpolate(&keyframes, interpolation, time);
+        *value = Value::from(vec![x, y, z]);
+    }
+    Ok(())
+}
+
+// Reads every file in includes (and, recursively, the files they include),
+// merging them in order so that later files take precedence over earlier ones.
+// Paths are relative to the directory of the file doing the including.
+fn load_includes(
+    includes: &[String],
+    parent: &Path,
+    include_stack: &mut Vec<PathBuf>,
+    loaded: &mut Vec<PathBuf>,
+) -> Result<Value> {
+    let base_dir = parent.parent().unwrap_or_else(|| Path::new(""));
+    let mut merged = Value::Object(Map::new());
+    for include in includes {
+        let path = base_dir.join(include);
+        let canonical = path
+            .canonicalize()
+            .with_context(|| format!("Including {} from {}", include, parent.display()))?;
+        if include_stack.contains(&canonical) {
+            return Err(ConfigError::ImpossibleConstruction(format!(
+                "Circular include: {} is included by {} while already being loaded",
+                path.display(),
+                parent.display()
+            )));
+        }
+        if !loaded.contains(&canonical) {
+            loaded.push(canonical.clone());
+        }
+        include_stack.push(canonical);
+        let included = if is_yaml(&path) {
+            read_yaml_value(&path)?
+        } else {
+            read_config_value(&path)?
+        };
+        // checked now so that mistakes are blamed on the file they're in
+        let IncludeConfig { includes, .. } = IncludeConfig::deserialize(&included)
+            .with_context(|| format!("Parsing config {}", path.display()))?;
+        let nested = load_includes(&includes, &path, include_stack, loaded)?;
+        merge_definitions(&mut merged, nested, true);
+        merge_definitions(&mut merged, included, true);
+        include_stack.pop();
+    }
+    Ok(merged)
+}
+
+fn includes_of(config_value: &Value, filename: &Path) -> Result<Vec<String>> {
+    match config_value.get("includes") {
+        Some(includes) => Vec::deserialize(includes)
+            .with_context(|| format!("Parsing config {}", filename.display())),
+        None => Ok(Vec::new()),
+    }
+}
+
+// Copies the textures, materials, objects and world of one config into
+// another. later says whether from counts as coming after config, in which
+// case its definitions win over config's and its world goes at the end.
+fn merge_definitions(config: &mut Value, mut from: Value, later: bool) {
+    let config = match config {
+        Value::Object(config) => config,
+        _ => return,
+    };
+    for section in &["textures", "materials", "objects"] {
+        if let Some(Value::Object(definitions)) = from.get_mut(*section).map(Value::take) {
+            let entry = config
+                .entry(*section)
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(existing) = entry {
+                for (name, definition) in definitions {
+                    if later || !existing.contains_key(&name) {
+                        existing.insert(name, definition);
+                    }
+                }
+            }
+        }
+    }
+    if let Some(Value::Array(added)) = from.get_mut("world").map(Value::take) {
+        let entry = config
+            .entry("world")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(world) = entry {
+            let own = mem::take(world);
+            let (first, second) = if later { (own, added) } else { (added, own) };
+            for name in first.into_iter().chain(second) {
+                if !world.contains(&name) {
+                    world.push(name);
+                }
+            }
+        }
+    }
 }
 
 fn build_textures(master_config: &MasterConfig) -> Result<HashMap<&str, Arc<dyn Texture>>> {
@@ -72,29 +999,168 @@ fn build_textures(master_config: &MasterConfig) -> Result<HashMap<&str, Arc<dyn
     'begin_search: while texture_configs.len() != 0 {
         for _ in 0..texture_configs.len() {
             let (name, texture) = texture_configs.pop_front().unwrap();
-            let found_texture: Option<Arc<dyn Texture>> = match texture {
-                TextureConfig::SolidColour { colour } => Some(Arc::new(textures::SolidColour {
-                    colour: Colour::new(colour[0], colour[1], colour[2]),
-                })),
-                TextureConfig::ImageTexture { filename } => {
-                    Some(textures::ImageTexture::from_file(&filename)?)
-                }
-            };
-            if let Some(texture) = found_texture {
+            if let Some(texture) = build_texture(texture, &texture_list)? {
                 texture_list.insert(name, texture);
                 continue 'begin_search;
             } else {
                 texture_configs.push_back((name, texture));
             }
         }
-        bail!(
+        return Err(ConfigError::ImpossibleConstruction(format!(
             "Texture {} is impossible to construct",
             texture_configs[0].0
-        );
+        )));
     }
     Ok(texture_list)
 }
 
+// Returns None if the texture depends on others that haven't been built yet
+fn build_texture(
+    texture: &TextureConfig,
+    texture_list: &HashMap<&str, Arc<dyn Texture>>,
+) -> Result<Option<Arc<dyn Texture>>> {
+    Ok(match texture {
+        TextureConfig::SolidColour {
+            colour,
+            kelvin,
+            intensity,
+        } => {
+            let colour = match (colour, kelvin) {
+                (Some([r, g, b]), None) => Colour::new(*r, *g, *b),
+                (None, Some(kelvin)) => colour_from_kelvin(*kelvin),
+                _ => {
+                    return Err(ConfigError::Invalid(String::from(
+                        "A solidColour texture needs one of colour and kelvin",
+                    )))
+                }
+            };
+            Some(Arc::new(textures::SolidColour {
+                colour: intensity.unwrap_or(1.0) * colour,
+            }))
+        }
+        TextureConfig::ImageTexture {
+            filename,
+            colour_space,
+            filter_mode,
+            wrap_u,
+            wrap_v,
+        } => {
+            let filter = match filter_mode {
+                FilterMode::Nearest => textures::FilterMode::Nearest,
+                FilterMode::Bilinear => textures::FilterMode::Bilinear,
+            };
+            let wrap = textures::WrapMode {
+                u: wrap_u.wrap(),
+                v: wrap_v.wrap(),
+            };
+            Some(Arc::new(
+                textures::ImageTexture::from_file(
+                    filename,
+                    matches!(colour_space, ColourSpace::Srgb),
+                )?
+                .with_filter(filter)
+                .with_wrap(wrap),
+            ))
+        }
+        TextureConfig::StarField {
+            density,
+            min_brightness,
+            max_brightness,
+            seed,
+        } => {
+            if !(0.0..=1.0).contains(density) {
+                return Err(ConfigError::Invalid(String::from(
+                    "A starField texture's density must be between 0 and 1",
+                )));
+            }
+            if min_brightness > max_brightness {
+                return Err(ConfigError::Invalid(String::from(
+                    "A starField texture's minBrightness is above its maxBrightness",
+                )));
+            }
+            Some(Arc::new(textures::StarField {
+                density: *density,
+                min_brightness: *min_brightness,
+                max_brightness: *max_brightness,
+                seed: *seed,
+            }))
+        }
+        TextureConfig::Voronoi { scale, mode } => Some(Arc::new(textures::VoronoiTexture {
+            scale: *scale,
+            colour_mode: match mode {
+                VoronoiModeConfig::F1 => VoronoiMode::F1,
+                VoronoiModeConfig::F2MinusF1 => VoronoiMode::F2MinusF1,
+                VoronoiModeConfig::CellColour => VoronoiMode::CellColour,
+            },
+        })),
+        TextureConfig::TriPlanar {
+            inner,
+            blend_sharpness,
+        } => match texture_dependency(inner, texture_list)? {
+            Some(inner) => Some(Arc::new(textures::TriPlanar {
+                inner,
+                blend_sharpness: blend_sharpness.unwrap_or(4.0),
+            })),
+            None => None,
+        },
+        TextureConfig::AtlasTile {
+            filename,
+            u0,
+            v0,
+            u1,
+            v1,
+        } => Some(Arc::new(textures::AtlasTile {
+            atlas: textures::TextureAtlas::open(filename)?,
+            u0: *u0,
+            v0: *v0,
+            u1: *u1,
+            v1: *v1,
+        })),
+        TextureConfig::Invert { inner } => match texture_dependency(inner, texture_list)? {
+            Some(inner) => Some(Arc::new(textures::InvertTexture { inner })),
+            None => None,
+        },
+        TextureConfig::BoxUnwrap { inner } => match texture_dependency(inner, texture_list)? {
+            Some(inner) => Some(Arc::new(textures::BoxUnwrapTexture { inner })),
+            None => None,
+        },
+        TextureConfig::Scale { inner, scale } => match texture_dependency(inner, texture_list)? {
+            Some(inner) => Some(Arc::new(textures::ScaleTexture {
+                inner,
+                scale: *scale,
+            })),
+            None => None,
+        },
+    })
+}
+
+// Like texture_ref, but None if a named texture hasn't been built yet
+fn texture_dependency(
+    texture: &TextureRef,
+    texture_list: &HashMap<&str, Arc<dyn Texture>>,
+) -> Result<Option<Arc<dyn Texture>>> {
+    match texture {
+        NameOrInline::Name(name) => Ok(texture_list.get(name as &str).cloned()),
+        NameOrInline::Inline(texture) => build_texture(texture, texture_list),
+    }
+}
+
+fn texture_ref(
+    texture: &TextureRef,
+    textures: &HashMap<&str, Arc<dyn Texture>>,
+    user: &str,
+) -> Result<Arc<dyn Texture>> {
+    match texture {
+        NameOrInline::Name(name) => lookup(textures, "Texture", name, user).map(Arc::clone),
+        NameOrInline::Inline(texture) => build_texture(texture, textures)?.ok_or_else(|| {
+            ConfigError::ImpossibleConstruction(format!(
+                "Inline texture in {} is impossible to construct",
+                user
+            ))
+        }),
+    }
+}
+
 fn build_materials<'a>(
     master_config: &'a MasterConfig,
     textures: &HashMap<&str, Arc<dyn Texture>>,
@@ -108,93 +1174,134 @@ fn build_materials<'a>(
     'begin_search: while material_configs.len() != 0 {
         for _ in 0..material_configs.len() {
             let (name, material) = material_configs.pop_front().unwrap();
-            let found_material: Option<Arc<dyn Material>> = match material {
-                MaterialConfig::Lambertian { texture } => {
-                    let texture = textures
-                        .get(&texture as &str)
-                        .ok_or(anyhow!("Texture {} does not exist", texture))?;
-                    Some(materials::Lambertian::with_texture(texture))
-                }
-                MaterialConfig::Metal { fuzz, albedo } => Some(Arc::new(materials::Metal {
-                    albedo: Colour::new(albedo[0], albedo[1], albedo[2]),
-                    fuzz: *fuzz,
-                })),
-                MaterialConfig::Dielectric {
-                    index_of_refraction,
-                } => Some(Arc::new(materials::Dielectric {
-                    index_of_refraction: *index_of_refraction,
-                })),
-                MaterialConfig::DiffuseLight { emit } => {
-                    let texture = textures
-                        .get(&emit as &str)
-                        .ok_or(anyhow!("Texture {} does not exist", emit))?;
-                    Some(Arc::new(materials::DiffuseLight {
-                        emit: Arc::clone(texture),
-                    }))
-                }
-                MaterialConfig::Isotropic { albedo } => {
-                    let texture = textures
-                        .get(&albedo as &str)
-                        .ok_or(anyhow!("Texture {} does not exist", albedo))?;
-                    Some(Arc::new(materials::Isotropic {
-                        albedo: Arc::clone(texture),
-                    }))
-                }
-                MaterialConfig::Checkered {
-                    odd,
-                    even,
-                    tile_density,
-                } => {
-                    if material_list.contains_key(&odd as &str)
-                        && material_list.contains_key(&even as &str)
-                    {
-                        Some(Arc::new(materials::Checkered {
-                            odd: Arc::clone(&material_list[odd as &str]),
-                            even: Arc::clone(&material_list[even as &str]),
-                            tile_density: *tile_density,
-                        }))
-                    } else {
-                        None
-                    }
-                }
-            };
-            if let Some(material) = found_material {
+            if let Some(material) = build_material(name, material, textures, &material_list)? {
                 material_list.insert(name, material);
                 continue 'begin_search;
             } else {
                 material_configs.push_back((name, material));
             }
         }
-        bail!(
+        return Err(ConfigError::ImpossibleConstruction(format!(
             "Material {} is impossible to construct",
             material_configs[0].0
-        );
+        )));
     }
     Ok(material_list)
 }
 
+// Returns None if the material depends on others that haven't been built yet
+fn build_material(
+    name: &str,
+    material: &MaterialConfig,
+    textures: &HashMap<&str, Arc<dyn Texture>>,
+    material_list: &HashMap<&str, Arc<dyn Material>>,
+) -> Result<Option<Arc<dyn Material>>> {
+    Ok(match material {
+        MaterialConfig::Lambertian { texture } => {
+            let texture = texture_ref(texture, textures, name)?;
+            Some(materials::Lambertian::with_texture(&texture))
+        }
+        MaterialConfig::Metal { fuzz, albedo } => Some(Arc::new(materials::Metal {
+            albedo: Colour::new(albedo[0], albedo[1], albedo[2]),
+            fuzz: *fuzz,
+        })),
+        MaterialConfig::Dielectric {
+            index_of_refraction,
+        } => Some(Arc::new(materials::Dielectric {
+            index_of_refraction: *index_of_refraction,
+        })),
+        MaterialConfig::DiffuseLight {
+            emit,
+            strength,
+            double_sided,
+        } => Some(Arc::new(materials::DiffuseLight {
+            emit: texture_ref(emit, textures, name)?,
+            strength: strength.unwrap_or(1.0),
+            double_sided: *double_sided,
+        })),
+        MaterialConfig::Isotropic { albedo } => Some(Arc::new(materials::Isotropic {
+            albedo: texture_ref(albedo, textures, name)?,
+        })),
+        MaterialConfig::RandomWalkSss {
+            albedo,
+            mean_free_path,
+            g,
+        } => {
+            if mean_free_path.is_nan() || *mean_free_path <= 0.0 {
+                return Err(ConfigError::Invalid(format!(
+                    "Material {} needs a positive meanFreePath",
+                    name
+                )));
+            }
+            if !(-1.0 < *g && *g < 1.0) {
+                return Err(ConfigError::Invalid(format!(
+                    "Material {} needs g between -1 and 1, not {}",
+                    name, g
+                )));
+            }
+            Some(Arc::new(materials::RandomWalkSss {
+                albedo: texture_ref(albedo, textures, name)?,
+                mean_free_path: *mean_free_path,
+                g: *g,
+            }))
+        }
+        MaterialConfig::Checkered {
+            odd,
+            even,
+            tile_density,
+        } => {
+            if material_list.contains_key(odd as &str) && material_list.contains_key(even as &str) {
+                Some(Arc::new(materials::Checkered {
+                    odd: Arc::clone(&material_list[odd as &str]),
+                    even: Arc::clone(&material_list[even as &str]),
+                    tile_density: *tile_density,
+                }))
+            } else {
+                None
+            }
+        }
+    })
+}
+
+fn material_ref(
+    material: &MaterialRef,
+    materials: &HashMap<&str, Arc<dyn Material>>,
+    textures: &HashMap<&str, Arc<dyn Texture>>,
+    user: &str,
+) -> Result<Arc<dyn Material>> {
+    match material {
+        NameOrInline::Name(name) => lookup(materials, "Material", name, user).map(Arc::clone),
+        NameOrInline::Inline(material) => build_material(user, material, textures, materials)?
+            .ok_or_else(|| {
+                ConfigError::ImpossibleConstruction(format!(
+                    "Inline material in {} is impossible to construct",
+                    user
+                ))
+            }),
+    }
+}
+
 fn build_hittables<'a>(
     master_config: &'a MasterConfig,
+    textures: &HashMap<&str, Arc<dyn Texture>>,
     materials: &HashMap<&str, Arc<dyn Material>>,
 ) -> Result<HashMap<&'a str, Arc<dyn Hittable>>> {
     let mut hittable_list: HashMap<&str, Arc<dyn Hittable>> = HashMap::new();
-    let mut hittable_configs: VecDeque<(&str, &ObjectConfig)> = master_config
+    let mut hittable_configs: VecDeque<(&str, &ObjectEntry)> = master_config
         .objects
         .iter()
         .map(|(s, t)| (s as &str, t))
         .collect();
     'begin_search: while hittable_configs.len() != 0 {
         for _ in 0..hittable_configs.len() {
-            let (name, hittable) = hittable_configs.pop_front().unwrap();
-            let found_hittable: Option<Arc<dyn Hittable>> = match hittable {
+            let (name, entry) = hittable_configs.pop_front().unwrap();
+            let found_hittable: Option<Arc<dyn Hittable>> = match &entry.object {
                 ObjectConfig::Sphere {
                     centre,
                     radius,
                     material,
                 } => {
-                    let material = materials
-                        .get(&material as &str)
-                        .ok_or(anyhow!("Material {} does not exist", material))?;
+                    let material = &material_ref(material, materials, textures, name)?;
                     Some(objects::Sphere::new(
                         Point3::new(centre[0], centre[1], centre[2]),
                         *radius,
@@ -209,9 +1316,7 @@ fn build_hittables<'a>(
                     radius,
                     material,
                 } => {
-                    let material = materials
-                        .get(&material as &str)
-                        .ok_or(anyhow!("Material {} does not exist", material))?;
+                    let material = &material_ref(material, materials, textures, name)?;
                     Some(objects::MovingSphere::new(
                         Point3::new(centre0[0], centre0[1], centre0[2]),
                         Point3::new(centre1[0], centre1[1], centre1[2]),
@@ -225,15 +1330,41 @@ fn build_hittables<'a>(
                     corner0,
                     corner1,
                     material,
+                    face_materials,
+                    unwrap_uvs,
                 } => {
-                    let material = materials
-                        .get(&material as &str)
-                        .ok_or(anyhow!("Material {} does not exist", material))?;
-                    Some(objects::Block::new(
-                        Point3::new(corner0[0], corner0[1], corner0[2]),
-                        Point3::new(corner1[0], corner1[1], corner1[2]),
-                        material,
-                    ))
+                    let minimum = Point3::new(corner0[0], corner0[1], corner0[2]);
+                    let maximum = Point3::new(corner1[0], corner1[1], corner1[2]);
+                    match (face_materials, material) {
+                        (Some(_), _) if *unwrap_uvs => {
+                            return Err(ConfigError::Invalid(format!(
+                                "Block {} can't have both faceMaterials and unwrapUvs",
+                                name
+                            )))
+                        }
+                        (Some(face_materials), _) => {
+                            let face = |i: usize| {
+                                material_ref(&face_materials[i], materials, textures, name)
+                            };
+                            let faces =
+                                [face(0)?, face(1)?, face(2)?, face(3)?, face(4)?, face(5)?];
+                            Some(objects::MultiMaterialBlock::new(minimum, maximum, &faces))
+                        }
+                        (None, Some(material)) => {
+                            let material = &material_ref(material, materials, textures, name)?;
+                            if *unwrap_uvs {
+                                Some(objects::Block::unwrapped(minimum, maximum, material))
+                            } else {
+                                Some(objects::Block::new(minimum, maximum, material))
+                            }
+                        }
+                        (None, None) => {
+                            return Err(ConfigError::Invalid(format!(
+                                "Block {} needs a material or faceMaterials",
+                                name
+                            )))
+                        }
+                    }
                 }
                 ObjectConfig::Rect {
                     corner0,
@@ -241,9 +1372,7 @@ fn build_hittables<'a>(
                     facing_forward,
                     material,
                 } => {
-                    let material = materials
-                        .get(&material as &str)
-                        .ok_or(anyhow!("Material {} does not exist", material))?;
+                    let material = &material_ref(material, materials, textures, name)?;
                     Some(if corner0[0] == corner1[0] {
                         objects::YZRect::new(
                             corner0[1],
@@ -275,18 +1404,57 @@ fn build_hittables<'a>(
                             *facing_forward,
                         )
                     } else {
-                        bail!("Rectangles are 2d; corner0 and corner1 must be equal along one axis")
+                        return Err(ConfigError::InvalidDimensions(
+                            "Rectangles are 2d; corner0 and corner1 must be equal along one axis"
+                                .to_string(),
+                        ));
                     })
                 }
+                ObjectConfig::Sdf {
+                    sdf,
+                    material,
+                    max_steps,
+                    epsilon,
+                } => {
+                    let material = &material_ref(material, materials, textures, name)?;
+                    let (sdf, bbox) = sdf.build();
+                    Some(objects::SDFObject::new(
+                        sdf,
+                        bbox,
+                        material,
+                        max_steps.unwrap_or(256),
+                        epsilon.unwrap_or(0.0001),
+                    ))
+                }
+                ObjectConfig::BezierPatch {
+                    control_points,
+                    material,
+                } => {
+                    let material = &material_ref(material, materials, textures, name)?;
+                    let point = |i: usize| {
+                        let [x, y, z] = control_points[i];
+                        Point3::new(x, y, z)
+                    };
+                    let row = |i: usize| {
+                        [
+                            point(i * 4),
+                            point(i * 4 + 1),
+                            point(i * 4 + 2),
+                            point(i * 4 + 3),
+                        ]
+                    };
+                    Some(objects::BezierPatch::new(
+                        [row(0), row(1), row(2), row(3)],
+                        material,
+                    ))
+                }
                 ObjectConfig::Triangle {
                     point0,
                     point1,
                     point2,
                     material,
                 } => {
-                    let material = materials
-                        .get(&material as &str)
-                        .ok_or(anyhow!("Material {} does not exist", material))?;
+                    let material = &material_ref(material, materials, textures, name)?;
                     Some(objects::Triangle::new(
                         Point3::new(point0[0], point0[1], point0[2]),
                         Point3::new(point1[0], point1[1], point1[2]),
@@ -301,10 +1469,8 @@ fn build_hittables<'a>(
                     uv_repeat,
                     material,
                 } => {
-                    let material = materials
-                        .get(&material as &str)
-                        .ok_or(anyhow!("Material {} does not exist", material))?;
-                    Some(objects::Plane::new(
+                    let material = &material_ref(material, materials, textures, name)?;
+                    Some(objects::InfinitePlane::new(
                         Point3::new(point0[0], point0[1], point0[2]),
                         Point3::new(point1[0], point1[1], point1[2]),
                         Point3::new(point2[0], point2[1], point2[2]),
@@ -312,16 +1478,66 @@ fn build_hittables<'a>(
                         material,
                     ))
                 }
+                ObjectConfig::HeightField {
+                    filename,
+                    x0,
+                    z0,
+                    x1,
+                    z1,
+                    y_min,
+                    y_max,
+                    material,
+                } => {
+                    let material = &material_ref(material, materials, textures, name)?;
+                    Some(objects::HeightField::from_file(
+                        filename,
+                        Point3::new(*x0, *y_min, *z0),
+                        Point3::new(*x1, *y_max, *z1),
+                        material,
+                    )?)
+                }
                 ObjectConfig::Mesh {
                     filename,
+                    format,
                     object_name,
                     material,
+                    smooth_normals,
                 } => {
-                    let material = materials
-                        .get(&material as &str)
-                        .ok_or(anyhow!("Material {} does not exist", material))?;
-                    let object_name = if let Some(n) = object_name { n } else { "" };
-                    Some(objects::load_mesh(filename, object_name, material)?)
+                    let material = material
+                        .as_ref()
+                        .map(|m| material_ref(m, materials, textures, name))
+                        .transpose()?;
+                    match format {
+                        MeshFormat::Obj => {
+                            let material = material.as_ref().ok_or_else(|| {
+                                ConfigError::Invalid(format!(
+                                    "Mesh {} needs a material for an OBJ file",
+                                    name
+                                ))
+                            })?;
+                            let object_name = if let Some(n) = object_name { n } else { "" };
+                            Some(objects::load_mesh(
+                                filename,
+                                object_name,
+                                material,
+                                *smooth_normals,
+                            )?)
+                        }
+                        MeshFormat::Stl => {
+                            let material = material.as_ref().ok_or_else(|| {
+                                ConfigError::Invalid(format!(
+                                    "Mesh {} needs a material for an STL file",
+                                    name
+                                ))
+                            })?;
+                            Some(objects::load_stl(filename, material)?)
+                        }
+                        // gltf meshes always have smooth normals, if they have normals at all
+                        MeshFormat::Gltf => Some(
+                            objects::load_gltf(filename, material.as_ref())
+                                .with_context(|| format!("Loading mesh {}", name))?,
+                        ),
+                    }
                 }
                 ObjectConfig::Spotlight {
                     look_from,
@@ -329,28 +1545,192 @@ fn build_hittables<'a>(
                     length,
                     width,
                     light,
+                    cone_angle,
+                    penumbra_angle,
                 } => Some(objects::Spotlight::new(
                     Point3::new(look_from[0], look_from[1], look_from[2]),
                     Point3::new(look_at[0], look_at[1], look_at[2]),
                     *length,
                     *width,
                     Colour::new(light[0], light[1], light[2]),
+                    cone_angle.unwrap_or(90.0),
+                    penumbra_angle.unwrap_or(0.0),
                 )),
+                ObjectConfig::PointLight {
+                    position,
+                    colour,
+                    radius,
+                } => {
+                    if *radius <= 0.0 {
+                        return Err(ConfigError::InvalidDimensions(format!(
+                            "Point light {} needs a positive radius",
+                            name
+                        )));
+                    }
+                    Some(objects::PointLight::new(
+                        Point3::new(position[0], position[1], position[2]),
+                        Colour::new(colour[0], colour[1], colour[2]),
+                        *radius,
+                    ))
+                }
+                ObjectConfig::DirectionalLight {
+                    direction,
+                    colour,
+                    angular_radius_degrees,
+                    angular_diameter_degrees,
+                } => {
+                    let angular_radius_degrees =
+                        match (angular_radius_degrees, angular_diameter_degrees) {
+                            (Some(radius), None) => *radius,
+                            (None, Some(diameter)) => diameter / 2.0,
+                            _ => return Err(ConfigError::Invalid(format!(
+                                "Directional light {} needs one of angularRadiusDegrees and angularDiameterDegrees",
+                                name
+                            ))),
+                        };
+                    Some(objects::DirectionalLight::new(
+                        Vec3::new(direction[0], direction[1], direction[2]),
+                        Colour::new(colour[0], colour[1], colour[2]),
+                        angular_radius_degrees,
+                    ))
+                }
                 ObjectConfig::ConstantMedium {
                     boundary,
                     phase_function,
                     density,
                 } => {
-                    if hittable_list.contains_key(&boundary as &str) {
-                        let material = materials
-                            .get(&phase_function as &str)
-                            .ok_or(anyhow!("Material {} does not exist", phase_function))?;
-                        let boundary = hittable_list.get(&boundary as &str).unwrap();
-                        Some(objects::ConstantMedium::new(boundary, material, *density))
+                    if hittable_list.contains_key(&boundary as &str) {
+                        let material = &material_ref(phase_function, materials, textures, name)?;
+                        let boundary = hittable_list.get(&boundary as &str).unwrap();
+                        Some(objects::ConstantMedium::new(boundary, material, *density))
+                    } else {
+                        None
+                    }
+                }
+                ObjectConfig::HeterogeneousMedium {
+                    boundary,
+                    phase_function,
+                    density,
+                    max_density,
+                } => {
+                    if let Some(max_density) = max_density {
+                        if *max_density <= 0.0 {
+                            return Err(ConfigError::Invalid(format!(
+                                "The maxDensity of medium {} must be positive",
+                                name
+                            )));
+                        }
+                    }
+                    if hittable_list.contains_key(boundary as &str) {
+                        let material = &material_ref(phase_function, materials, textures, name)?;
+                        let density = &texture_ref(density, textures, name)?;
+                        let boundary = hittable_list.get(boundary as &str).unwrap();
+                        Some(objects::HeterogeneousMedium::new(
+                            boundary,
+                            material,
+                            density,
+                            *max_density,
+                        ))
+                    } else {
+                        None
+                    }
+                }
+                ObjectConfig::CSGUnion { left, right } => {
+                    match (
+                        hittable_list.get(left as &str),
+                        hittable_list.get(right as &str),
+                    ) {
+                        (Some(left), Some(right)) => Some(csg::CSGUnion::new(left, right)),
+                        _ => None,
+                    }
+                }
+                ObjectConfig::CSGIntersection { left, right } => {
+                    match (
+                        hittable_list.get(left as &str),
+                        hittable_list.get(right as &str),
+                    ) {
+                        (Some(left), Some(right)) => Some(csg::CSGIntersection::new(left, right)),
+                        _ => None,
+                    }
+                }
+                ObjectConfig::CSGDifference { base, cutter } => {
+                    match (
+                        hittable_list.get(base as &str),
+                        hittable_list.get(cutter as &str),
+                    ) {
+                        (Some(base), Some(cutter)) => Some(csg::CSGDifference::new(base, cutter)),
+                        _ => None,
+                    }
+                }
+                ObjectConfig::Grid {
+                    prototype,
+                    nx,
+                    nz,
+                    spacing_x,
+                    spacing_z,
+                    y_height_texture,
+                    height_scale,
+                } => {
+                    if *nx == 0 || *nz == 0 {
+                        return Err(ConfigError::InvalidDimensions(format!(
+                            "Grid {} needs at least one copy each way",
+                            name
+                        )));
+                    }
+                    if let Some(prototype) = hittable_list.get(prototype as &str) {
+                        let heights = match y_height_texture {
+                            Some(texture) => Some(texture_ref(texture, textures, name)?),
+                            None => None,
+                        };
+                        let fraction = |i: u32, n: u32| {
+                            if n > 1 {
+                                i as f64 / (n - 1) as f64
+                            } else {
+                                0.0
+                            }
+                        };
+                        let mut copies = Vec::with_capacity((nx * nz) as usize);
+                        for i in 0..*nx {
+                            for k in 0..*nz {
+                                let (x, z) = (i as f64 * spacing_x, k as f64 * spacing_z);
+                                let y = heights.as_ref().map_or(0.0, |texture| {
+                                    let (u, v) = (fraction(i, *nx), fraction(k, *nz));
+                                    let colour = texture.value(
+                                        u,
+                                        v,
+                                        Point3::new(x, 0, z),
+                                        Vec3::new(0, 1, 0),
+                                    );
+                                    height_scale.unwrap_or(1.0) * (colour.x + colour.y + colour.z)
+                                        / 3.0
+                                });
+                                copies.push(transforms::Translate::translate(
+                                    prototype,
+                                    Vec3::new(x, y, z),
+                                ));
+                            }
+                        }
+                        Some(
+                            objects::ObjectGroup::new(copies)
+                                .with_context(|| format!("Building grid {}", name))?,
+                        )
                     } else {
                         None
                     }
                 }
+                ObjectConfig::Group { members } => {
+                    let members: Option<Vec<_>> = members
+                        .iter()
+                        .map(|member| hittable_list.get(member as &str).cloned())
+                        .collect();
+                    match members {
+                        Some(members) => Some(
+                            objects::ObjectGroup::new(members)
+                                .with_context(|| format!("Building group {}", name))?,
+                        ),
+                        None => None,
+                    }
+                }
                 ObjectConfig::Translate { prototype, offset } => {
                     if hittable_list.contains_key(&prototype as &str) {
                         let prototype = hittable_list.get(&prototype as &str).unwrap();
@@ -378,6 +1758,24 @@ fn build_hittables<'a>(
                         None
                     }
                 }
+                ObjectConfig::AnimatedRotateY {
+                    prototype,
+                    degrees_at0,
+                    degrees_at1,
+                } => {
+                    if hittable_list.contains_key(prototype as &str) {
+                        let prototype = hittable_list.get(prototype as &str).unwrap();
+                        Some(transforms::AnimatedRotateY::by_degrees(
+                            prototype,
+                            *degrees_at0,
+                            *degrees_at1,
+                            master_config.camera.start_time,
+                            master_config.camera.end_time,
+                        ))
+                    } else {
+                        None
+                    }
+                }
                 ObjectConfig::RotateZ { prototype, degrees } => {
                     if hittable_list.contains_key(&prototype as &str) {
                         let prototype = hittable_list.get(&prototype as &str).unwrap();
@@ -386,27 +1784,135 @@ fn build_hittables<'a>(
                         None
                     }
                 }
+                ObjectConfig::Rotate {
+                    prototype,
+                    rotation,
+                    end_rotation,
+                } => {
+                    if let Some(prototype) = hittable_list.get(prototype as &str) {
+                        let start = rotation.quat();
+                        Some(match end_rotation {
+                            Some(end) => {
+                                transforms::RotateQuat::turning(prototype, start, end.quat())
+                            }
+                            None => transforms::RotateQuat::from_quat(prototype, start),
+                        })
+                    } else {
+                        None
+                    }
+                }
+                ObjectConfig::Instance {
+                    prototype,
+                    transforms,
+                } => {
+                    if let Some(prototype) = hittable_list.get(prototype as &str) {
+                        // applied in order, so later operations multiply on the left
+                        let matrix = transforms
+                            .iter()
+                            .fold(Mat4::identity(), |matrix, op| op.matrix() * matrix);
+                        Some(
+                            transforms::MatrixTransform::new(prototype, matrix).ok_or_else(
+                                || {
+                                    ConfigError::InvalidDimensions(format!(
+                                        "Transforms of {} squash it flat",
+                                        name
+                                    ))
+                                },
+                            )?,
+                        )
+                    } else {
+                        None
+                    }
+                }
             };
             if let Some(hittable) = found_hittable {
+                let hittable = if entry.visible && entry.casts_shadow {
+                    hittable
+                } else {
+                    VisibilityFlags::new(&hittable, entry.visible, entry.casts_shadow)
+                };
                 hittable_list.insert(name, hittable);
                 continue 'begin_search;
             } else {
-                hittable_configs.push_back((name, hittable));
+                hittable_configs.push_back((name, entry));
             }
         }
-        bail!(
-            "Object {} is impossible to construct",
-            hittable_configs[0].0
-        );
+        return Err(unbuildable_objects(
+            hittable_configs.into_iter().collect(),
+            master_config,
+        ));
     }
     Ok(hittable_list)
 }
 
-fn get_background(config: BackgroundConfig) -> Sky {
-    match config {
-        BackgroundConfig::PlainColour { colour: [r, g, b] } => {
-            Box::new(move |_| Colour::new(r, g, b))
+// Explains why these objects can't be built: each one is waiting on an object
+// that doesn't exist, or on another stuck one, and it points out a loop if any
+fn unbuildable_objects(mut stuck: Vec<(&str, &ObjectEntry)>, config: &MasterConfig) -> ConfigError {
+    stuck.sort_unstable_by_key(|&(name, _)| name);
+    let waiting_on = |name: &str| -> Vec<&str> {
+        let entry = stuck.iter().find(|&&(n, _)| n == name).map(|(_, e)| e);
+        entry.map_or_else(Vec::new, |e| e.object.object_dependencies())
+    };
+    let is_stuck = |name: &str| stuck.iter().any(|&(n, _)| n == name);
+    let mut message = String::from("Some objects are impossible to construct:");
+    // drop objects that can't be part of a loop, being stuck only on missing
+    // objects or on ones that were dropped; what's left is in a loop or leads into one
+    let mut in_loops: Vec<&str> = stuck.iter().map(|&(name, _)| name).collect();
+    loop {
+        let remaining: Vec<&str> = in_loops
+            .iter()
+            .copied()
+            .filter(|&n| waiting_on(n).iter().any(|d| in_loops.contains(d)))
+            .collect();
+        if remaining.len() == in_loops.len() {
+            break;
+        }
+        in_loops = remaining;
+    }
+    if let Some(&first) = in_loops.first() {
+        let mut path = vec![first];
+        loop {
+            let last = path[path.len() - 1];
+            let next = waiting_on(last)
+                .into_iter()
+                .find(|d| in_loops.contains(d))
+                .unwrap();
+            if let Some(start) = path.iter().position(|&p| p == next) {
+                path.push(next);
+                let _ = write!(
+                    message,
+                    "\n  circular reference: {}",
+                    path[start..].join(" -> ")
+                );
+                break;
+            }
+            path.push(next);
         }
+    }
+    for &(name, entry) in &stuck {
+        let unresolved: Vec<String> = entry
+            .object
+            .object_dependencies()
+            .into_iter()
+            .filter(|&d| is_stuck(d) || !config.objects.contains_key(d))
+            .map(|d| {
+                if config.objects.contains_key(d) {
+                    String::from(d)
+                } else {
+                    format!("{} (not defined)", d)
+                }
+            })
+            .collect();
+        let _ = write!(message, "\n  {} needs {}", name, unresolved.join(", "));
+    }
+    ConfigError::ImpossibleConstruction(message)
+}
+
+fn get_background(config: BackgroundConfig) -> Result<Box<dyn Sky>> {
+    Ok(match config {
+        BackgroundConfig::PlainColour { colour: [r, g, b] } => Box::new(SolidSky {
+            colour: Colour::new(r, g, b),
+        }),
         BackgroundConfig::Gradient {
             direction,
             colour0,
@@ -415,22 +1921,75 @@ fn get_background(config: BackgroundConfig) -> Sky {
             let direction = Vec3::new(direction[0], direction[1], direction[2]);
             let colour0 = Colour::new(colour0[0], colour0[1], colour0[2]);
             let colour1 = Colour::new(colour1[0], colour1[1], colour1[2]);
-            gradient_background(direction, colour0, colour1)
+            Box::new(GradientSky::new(direction, colour0, colour1))
         }
-    }
+        BackgroundConfig::Hdri {
+            filename,
+            rotation_degrees,
+            exposure,
+        } => Box::new(
+            HdriSky::from_file(&filename, rotation_degrees, exposure.unwrap_or(1.0))
+                .with_context(|| format!("Loading environment map {}", filename))?,
+        ),
+        BackgroundConfig::PhysicalSky {
+            sun_direction,
+            turbidity,
+            ground_albedo,
+        } => {
+            if turbidity < 1.0 {
+                return Err(ConfigError::Invalid(format!(
+                    "Sky turbidity must be at least 1, not {}",
+                    turbidity
+                )));
+            }
+            Box::new(sky::PhysicalSky::new(
+                Vec3::new(sun_direction[0], sun_direction[1], sun_direction[2]),
+                turbidity,
+                Colour::new(ground_albedo[0], ground_albedo[1], ground_albedo[2]),
+            ))
+        }
+    })
 }
 
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 struct MasterConfig {
+    // already merged in by parse_config
+    #[serde(default, rename = "includes")]
+    _includes: Vec<String>,
     camera: CameraConfig,
+    // other cameras, picked by name with --view
+    #[serde(default)]
+    named_views: HashMap<String, CameraConfig>,
     background: BackgroundConfig,
+    atmosphere: Option<AtmosphereConfig>,
+    #[serde(default)]
     textures: HashMap<String, TextureConfig>,
+    #[serde(default)]
     materials: HashMap<String, MaterialConfig>,
-    objects: HashMap<String, ObjectConfig>,
+    #[serde(default)]
+    objects: HashMap<String, ObjectEntry>,
     world: Vec<String>,
 }
 
+// A file pulled in through the includes list of another config.
+// It can contribute anything but a camera or background. The definitions
+// are merged in as they were written, so they're only read here to check them.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct IncludeConfig {
+    #[serde(default)]
+    includes: Vec<String>,
+    #[serde(default, rename = "textures")]
+    _textures: HashMap<String, TextureConfig>,
+    #[serde(default, rename = "materials")]
+    _materials: HashMap<String, MaterialConfig>,
+    #[serde(default, rename = "objects")]
+    _objects: HashMap<String, ObjectEntry>,
+    #[serde(default, rename = "world")]
+    _world: Vec<String>,
+}
+
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields, tag = "type", rename_all = "camelCase")]
 enum BackgroundConfig {
@@ -442,46 +2001,257 @@ enum BackgroundConfig {
         colour0: [f64; 3],
         colour1: [f64; 3],
     },
+    // an equirectangular image, turned about the vertical axis. Its bright
+    // parts can be sampled like lights.
+    #[serde(alias = "hdrEnvironment", rename_all = "camelCase")]
+    Hdri {
+        filename: String,
+        #[serde(default)]
+        rotation_degrees: f64,
+        exposure: Option<f64>,
+    },
+    // daylight from the Preetham model, with +y up
+    #[serde(rename_all = "camelCase")]
+    PhysicalSky {
+        sun_direction: [f64; 3],
+        turbidity: f64,
+        ground_albedo: [f64; 3],
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, tag = "type", rename_all = "camelCase")]
+enum AtmosphereConfig {
+    // density is how much light is lost per unit of distance
+    #[serde(rename_all = "camelCase")]
+    Fog { density: f64, colour: [f64; 3] },
 }
 
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 struct CameraConfig {
+    #[serde(default)]
+    camera_type: CameraType,
     look_from: [f64; 3],
     look_at: [f64; 3],
     direction_up: [f64; 3],
+    // the rest are needed by perspective cameras only
     #[serde(rename = "fieldOfView")]
-    vertical_fov: f64,
-    aspect_ratio: [f64; 2],
-    aperture: f64,
+    vertical_fov: Option<f64>,
+    aspect_ratio: Option<[f64; 2]>,
+    aperture: Option<f64>,
     #[serde(rename = "distanceToFocus")]
-    focus_dist: f64,
+    focus_dist: Option<f64>,
+    // focus on the centre of this object instead of at distanceToFocus
+    focus_target: Option<String>,
+    #[serde(default)]
+    aperture_shape: ApertureShapeConfig,
+    // overrides the default gamma the image is saved with
+    gamma: Option<f64>,
     start_time: f64,
     end_time: f64,
 }
 
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum CameraType {
+    #[default]
+    Perspective,
+    Panoramic,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum ApertureShapeConfig {
+    #[default]
+    Circle,
+    Hexagon,
+    Pentagon(u32),
+    Square,
+}
+
+// Textures and materials can be referred to by name, or defined in place
+enum NameOrInline<T> {
+    Name(String),
+    Inline(Box<T>),
+}
+
+// Works like #[serde(untagged)], but keeps the error from the inline definition
+// instead of replacing it with "data did not match any variant"
+impl<'de, T: DeserializeOwned> Deserialize<'de> for NameOrInline<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        match Value::deserialize(deserializer)? {
+            Value::String(name) => Ok(NameOrInline::Name(name)),
+            value => serde_json::from_value(value)
+                .map(NameOrInline::Inline)
+                .map_err(de::Error::custom),
+        }
+    }
+}
+
+// Any object can also be hidden from the camera (visible: false) or from
+// everything else (castsShadow: false)
+struct ObjectEntry {
+    object: ObjectConfig,
+    visible: bool,
+    casts_shadow: bool,
+    // the type field, for describing the scene
+    kind: String,
+}
+
+// ObjectConfig denies unknown fields, so the flags are taken out before the
+// rest is parsed
+impl<'de> Deserialize<'de> for ObjectEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let mut value = Value::deserialize(deserializer)?;
+        let mut flag = |key| match value.as_object_mut().and_then(|map| map.remove(key)) {
+            None => Ok(true),
+            Some(Value::Bool(flag)) => Ok(flag),
+            Some(_) => Err(de::Error::custom(format!("{} must be true or false", key))),
+        };
+        let visible = flag("visible")?;
+        let casts_shadow = flag("castsShadow")?;
+        let kind = value
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .to_string();
+        Ok(ObjectEntry {
+            object: serde_json::from_value(value).map_err(de::Error::custom)?,
+            visible,
+            casts_shadow,
+            kind,
+        })
+    }
+}
+
+type TextureRef = NameOrInline<TextureConfig>;
+type MaterialRef = NameOrInline<MaterialConfig>;
+
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields, tag = "type", rename_all = "camelCase")]
 enum TextureConfig {
+    // either an RGB colour, or the colour of a black body at some temperature
+    #[serde(rename_all = "camelCase")]
+    SolidColour {
+        colour: Option<[f64; 3]>,
+        kelvin: Option<f64>,
+        intensity: Option<f64>,
+    },
+    #[serde(rename_all = "camelCase")]
+    ImageTexture {
+        filename: String,
+        #[serde(default)]
+        colour_space: ColourSpace,
+        #[serde(default)]
+        filter_mode: FilterMode,
+        #[serde(default)]
+        wrap_u: Wrap,
+        #[serde(default)]
+        wrap_v: Wrap,
+    },
+    // density is the chance of a star in each of the field's cells
+    #[serde(rename_all = "camelCase")]
+    StarField {
+        density: f64,
+        min_brightness: f64,
+        max_brightness: f64,
+        seed: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    Voronoi { scale: f64, mode: VoronoiModeConfig },
+    #[serde(rename_all = "camelCase")]
+    TriPlanar {
+        inner: TextureRef,
+        blend_sharpness: Option<f64>,
+    },
+    // part of an image shared with other tiles, which is only loaded once
+    #[serde(rename_all = "camelCase")]
+    AtlasTile {
+        filename: String,
+        u0: f64,
+        v0: f64,
+        u1: f64,
+        v1: f64,
+    },
     #[serde(rename_all = "camelCase")]
-    SolidColour { colour: [f64; 3] },
+    Invert { inner: TextureRef },
+    // for blocks with unwrapUvs
     #[serde(rename_all = "camelCase")]
-    ImageTexture { filename: String },
+    BoxUnwrap { inner: TextureRef },
+    #[serde(rename_all = "camelCase")]
+    Scale { inner: TextureRef, scale: f64 },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum VoronoiModeConfig {
+    F1,
+    F2MinusF1,
+    CellColour,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum ColourSpace {
+    #[default]
+    Srgb,
+    Linear,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum FilterMode {
+    #[default]
+    Nearest,
+    Bilinear,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum Wrap {
+    #[default]
+    Clamp,
+    Repeat,
+    MirrorRepeat,
+}
+
+impl Wrap {
+    fn wrap(&self) -> textures::Wrap {
+        match self {
+            Wrap::Clamp => textures::Wrap::Clamp,
+            Wrap::Repeat => textures::Wrap::Repeat,
+            Wrap::MirrorRepeat => textures::Wrap::MirrorRepeat,
+        }
+    }
 }
 
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields, tag = "type", rename_all = "camelCase")]
 enum MaterialConfig {
     #[serde(rename_all = "camelCase")]
-    Lambertian { texture: String },
+    Lambertian { texture: TextureRef },
     #[serde(rename_all = "camelCase")]
     Metal { fuzz: f64, albedo: [f64; 3] },
     #[serde(rename_all = "camelCase")]
     Dielectric { index_of_refraction: f64 },
     #[serde(rename_all = "camelCase")]
-    DiffuseLight { emit: String },
+    DiffuseLight {
+        emit: TextureRef,
+        strength: Option<f64>,
+        #[serde(default)]
+        double_sided: bool,
+    },
     #[serde(rename_all = "camelCase")]
-    Isotropic { albedo: String },
+    Isotropic { albedo: TextureRef },
+    // light wanders under the surface before coming out
+    #[serde(rename = "subsurface", rename_all = "camelCase")]
+    RandomWalkSss {
+        albedo: TextureRef,
+        mean_free_path: f64,
+        #[serde(default)]
+        g: f64,
+    },
     #[serde(rename_all = "camelCase")]
     Checkered {
         odd: String,
@@ -497,7 +2267,7 @@ enum ObjectConfig {
     Sphere {
         centre: [f64; 3],
         radius: f64,
-        material: String,
+        material: MaterialRef,
     },
     #[serde(rename_all = "camelCase")]
     MovingSphere {
@@ -506,13 +2276,18 @@ enum ObjectConfig {
         time0: f64,
         time1: f64,
         radius: f64,
-        material: String,
+        material: MaterialRef,
     },
     #[serde(rename_all = "camelCase")]
     Block {
         corner0: [f64; 3],
         corner1: [f64; 3],
-        material: String,
+        material: Option<MaterialRef>,
+        // in the order -z, +z, -y, +y, -x, +x; overrides material
+        face_materials: Option<[MaterialRef; 6]>,
+        // lets a boxUnwrap texture wrap around the whole block
+        #[serde(default)]
+        unwrap_uvs: bool,
     },
     #[serde(rename_all = "camelCase")]
     Rect {
@@ -520,7 +2295,20 @@ enum ObjectConfig {
         corner0: [f64; 3],
         corner1: [f64; 3],
         facing_forward: bool,
-        material: String,
+        material: MaterialRef,
+    },
+    #[serde(rename_all = "camelCase")]
+    Sdf {
+        sdf: SdfConfig,
+        material: MaterialRef,
+        max_steps: Option<u32>,
+        epsilon: Option<f64>,
+    },
+    #[serde(rename_all = "camelCase")]
+    BezierPatch {
+        // four rows of four, with u along each row
+        control_points: [[f64; 3]; 16],
+        material: MaterialRef,
     },
     #[serde(rename_all = "camelCase")]
     Triangle {
@@ -528,7 +2316,7 @@ enum ObjectConfig {
         point0: [f64; 3],
         point1: [f64; 3],
         point2: [f64; 3],
-        material: String,
+        material: MaterialRef,
     },
     #[serde(rename_all = "camelCase")]
     Plane {
@@ -536,13 +2324,32 @@ enum ObjectConfig {
         point1: [f64; 3],
         point2: [f64; 3],
         uv_repeat: f64,
-        material: String,
+        material: MaterialRef,
+    },
+    // a greyscale image, spread over x0..x1 and z0..z1, with black at yMin
+    // and white at yMax
+    #[serde(rename_all = "camelCase")]
+    HeightField {
+        filename: String,
+        x0: f64,
+        z0: f64,
+        x1: f64,
+        z1: f64,
+        y_min: f64,
+        y_max: f64,
+        material: MaterialRef,
     },
     #[serde(rename_all = "camelCase")]
     Mesh {
         filename: String,
+        #[serde(default)]
+        format: MeshFormat,
         object_name: Option<String>,
-        material: String,
+        // gltf files can bring their own materials
+        material: Option<MaterialRef>,
+        // interpolate the vertex normals from the file across each face
+        #[serde(default)]
+        smooth_normals: bool,
     },
     #[serde(rename_all = "camelCase")]
     Spotlight {
@@ -551,13 +2358,62 @@ enum ObjectConfig {
         length: f64,
         width: f64,
         light: [f64; 3],
+        // in degrees from the centre of the beam; by default it lights
+        // everything the opening faces
+        cone_angle: Option<f64>,
+        penumbra_angle: Option<f64>,
+    },
+    #[serde(rename_all = "camelCase")]
+    PointLight {
+        position: [f64; 3],
+        colour: [f64; 3],
+        radius: f64,
+    },
+    #[serde(rename_all = "camelCase")]
+    DirectionalLight {
+        // pointing towards the light
+        direction: [f64; 3],
+        colour: [f64; 3],
+        // the sun is about half a degree across
+        angular_radius_degrees: Option<f64>,
+        angular_diameter_degrees: Option<f64>,
     },
     #[serde(rename_all = "camelCase")]
     ConstantMedium {
         boundary: String,
-        phase_function: String,
+        phase_function: MaterialRef,
         density: f64,
     },
+    // density comes from a texture; maxDensity is estimated if it's missing
+    #[serde(rename_all = "camelCase")]
+    HeterogeneousMedium {
+        boundary: String,
+        phase_function: MaterialRef,
+        density: TextureRef,
+        max_density: Option<f64>,
+    },
+    #[serde(rename = "csgUnion", rename_all = "camelCase")]
+    CSGUnion { left: String, right: String },
+    #[serde(rename = "csgIntersection", rename_all = "camelCase")]
+    CSGIntersection { left: String, right: String },
+    #[serde(rename = "csgDifference", rename_all = "camelCase")]
+    CSGDifference { base: String, cutter: String },
+    // a set of objects with their own BVH, e.g. all the furniture in a room;
+    // groups can contain other groups
+    #[serde(rename_all = "camelCase")]
+    Group { members: Vec<String> },
+    // nx by nz copies of the prototype, spaced out along +x and +z; a height
+    // texture raises each copy by its brightness there, times heightScale
+    #[serde(rename_all = "camelCase")]
+    Grid {
+        prototype: String,
+        nx: u32,
+        nz: u32,
+        spacing_x: f64,
+        spacing_z: f64,
+        y_height_texture: Option<TextureRef>,
+        height_scale: Option<f64>,
+    },
     #[serde(rename_all = "camelCase")]
     Translate { prototype: String, offset: [f64; 3] },
     #[serde(rename_all = "camelCase")]
@@ -566,4 +2422,797 @@ enum ObjectConfig {
     RotateY { prototype: String, degrees: f64 },
     #[serde(rename_all = "camelCase")]
     RotateZ { prototype: String, degrees: f64 },
+    // turns from degreesAt0 to degreesAt1 over the shutter, for motion blur
+    #[serde(rename_all = "camelCase")]
+    AnimatedRotateY {
+        prototype: String,
+        degrees_at0: f64,
+        degrees_at1: f64,
+    },
+    // about any axis; with an endRotation it turns while the shutter is open
+    #[serde(rename_all = "camelCase", alias = "rotateQuat")]
+    Rotate {
+        prototype: String,
+        rotation: RotationConfig,
+        end_rotation: Option<RotationConfig>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Instance {
+        prototype: String,
+        transforms: Vec<TransformOp>,
+    },
+}
+
+impl ObjectConfig {
+    // the other objects this one is built from
+    fn object_dependencies(&self) -> Vec<&str> {
+        match self {
+            ObjectConfig::ConstantMedium { boundary, .. }
+            | ObjectConfig::HeterogeneousMedium { boundary, .. } => vec![boundary],
+            ObjectConfig::CSGUnion { left, right }
+            | ObjectConfig::CSGIntersection { left, right } => {
+                vec![left, right]
+            }
+            ObjectConfig::CSGDifference { base, cutter } => vec![base, cutter],
+            ObjectConfig::Group { members } => members.iter().map(|m| m as &str).collect(),
+            ObjectConfig::Grid { prototype, .. } => vec![prototype],
+            ObjectConfig::Translate { prototype, .. }
+            | ObjectConfig::RotateX { prototype, .. }
+            | ObjectConfig::RotateY { prototype, .. }
+            | ObjectConfig::RotateZ { prototype, .. }
+            | ObjectConfig::AnimatedRotateY { prototype, .. }
+            | ObjectConfig::Rotate { prototype, .. }
+            | ObjectConfig::Instance { prototype, .. } => vec![prototype],
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum MeshFormat {
+    #[default]
+    Obj,
+    Gltf,
+    Stl,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, tag = "type", rename_all = "camelCase")]
+enum RotationConfig {
+    #[serde(rename_all = "camelCase")]
+    AxisAngle { axis: [f64; 3], degrees: f64 },
+    // about z, then x, then y
+    #[serde(rename_all = "camelCase")]
+    EulerYxz { degrees: [f64; 3] },
+    // about x, then y, then z
+    #[serde(rename_all = "camelCase")]
+    EulerXyz { degrees: [f64; 3] },
+}
+
+impl RotationConfig {
+    fn quat(&self) -> Quat {
+        match self {
+            RotationConfig::AxisAngle {
+                axis: [x, y, z],
+                degrees,
+            } => Quat::from_axis_angle(Vec3::new(*x, *y, *z), degrees.to_radians()),
+            RotationConfig::EulerYxz { degrees: [y, x, z] } => {
+                Quat::from_euler_yxz(y.to_radians(), x.to_radians(), z.to_radians())
+            }
+            RotationConfig::EulerXyz { degrees: [x, y, z] } => {
+                Quat::from_euler_xyz(x.to_radians(), y.to_radians(), z.to_radians())
+            }
+        }
+    }
+}
+
+// Shapes are centred on the origin, and can be moved with translate
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, tag = "type", rename_all = "camelCase")]
+enum SdfConfig {
+    #[serde(rename_all = "camelCase")]
+    Sphere { radius: f64 },
+    #[serde(rename_all = "camelCase")]
+    Box { dimensions: [f64; 3] },
+    // lying flat, around the y axis
+    #[serde(rename_all = "camelCase")]
+    Torus {
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    // k is how far the blend between the two reaches
+    #[serde(rename_all = "camelCase")]
+    SmoothUnion {
+        a: Box<SdfConfig>,
+        b: Box<SdfConfig>,
+        k: f64,
+    },
+    #[serde(rename_all = "camelCase")]
+    Translate {
+        offset: [f64; 3],
+        sdf: Box<SdfConfig>,
+    },
+}
+
+impl SdfConfig {
+    // the distance function, and a box that holds its surface
+    fn build(&self) -> (objects::Sdf, AABB) {
+        let around_origin = |x: f64, y: f64, z: f64| AABB {
+            minimum: Point3::new(-x, -y, -z),
+            maximum: Point3::new(x, y, z),
+        };
+        match self {
+            SdfConfig::Sphere { radius } => {
+                let radius = *radius;
+                (
+                    Box::new(move |p: Point3| p.length() - radius),
+                    around_origin(radius, radius, radius),
+                )
+            }
+            SdfConfig::Box {
+                dimensions: [x, y, z],
+            } => {
+                let half = Vec3::new(*x, *y, *z) / 2.0;
+                (
+                    Box::new(move |p: Point3| {
+                        let q = p.abs() - half;
+                        let outside = q.clamp_components(0.0, f64::INFINITY).length();
+                        let inside = q.max_component().min(0.0);
+                        outside + inside
+                    }),
+                    around_origin(half.x, half.y, half.z),
+                )
+            }
+            SdfConfig::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let (major, minor) = (*major_radius, *minor_radius);
+                (
+                    Box::new(move |p: Point3| {
+                        let ring = (p.x * p.x + p.z * p.z).sqrt() - major;
+                        (ring * ring + p.y * p.y).sqrt() - minor
+                    }),
+                    around_origin(major + minor, minor, major + minor),
+                )
+            }
+            SdfConfig::SmoothUnion { a, b, k } => {
+                let (a, a_box) = a.build();
+                let (b, b_box) = b.build();
+                let k = *k;
+                // the blend can pull the surface out by up to k / 4
+                let grow = Vec3::new(k, k, k) / 4.0;
+                let both = surrounding_box(&a_box, &b_box);
+                (
+                    Box::new(move |p: Point3| {
+                        let (da, db) = (a(p), b(p));
+                        let h = (k - (da - db).abs()).max(0.0) / k;
+                        da.min(db) - h * h * k / 4.0
+                    }),
+                    AABB {
+                        minimum: both.minimum - grow,
+                        maximum: both.maximum + grow,
+                    },
+                )
+            }
+            SdfConfig::Translate {
+                offset: [x, y, z],
+                sdf,
+            } => {
+                let offset = Vec3::new(*x, *y, *z);
+                let (sdf, bbox) = sdf.build();
+                (
+                    Box::new(move |p: Point3| sdf(p - offset)),
+                    AABB {
+                        minimum: bbox.minimum + offset,
+                        maximum: bbox.maximum + offset,
+                    },
+                )
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, tag = "type", rename_all = "camelCase")]
+enum TransformOp {
+    #[serde(rename_all = "camelCase")]
+    Translate { offset: [f64; 3] },
+    #[serde(rename_all = "camelCase")]
+    RotateX { degrees: f64 },
+    #[serde(rename_all = "camelCase")]
+    RotateY { degrees: f64 },
+    #[serde(rename_all = "camelCase")]
+    RotateZ { degrees: f64 },
+    #[serde(rename_all = "camelCase")]
+    Rotate { rotation: RotationConfig },
+    #[serde(rename_all = "camelCase")]
+    Scale { factor: [f64; 3] },
+}
+
+impl TransformOp {
+    // the operation on its own, as a matrix
+    fn matrix(&self) -> Mat4 {
+        match self {
+            TransformOp::Translate { offset: [x, y, z] } => {
+                Mat4::translation(Vec3::new(*x, *y, *z))
+            }
+            TransformOp::RotateX { degrees } => Mat4::rotation_x(degrees.to_radians()),
+            TransformOp::RotateY { degrees } => Mat4::rotation_y(degrees.to_radians()),
+            TransformOp::RotateZ { degrees } => Mat4::rotation_z(degrees.to_radians()),
+            TransformOp::Rotate { rotation } => rotation.quat().to_rotation_matrix(),
+            TransformOp::Scale { factor: [x, y, z] } => Mat4::scaling(Vec3::new(*x, *y, *z)),
+        }
+    }
+}
+
+// A config written to a file of its own for a test to load, which is
+// deleted again when the test is done with it, even if the test fails
+#[cfg(test)]
+struct TestConfig {
+    path: PathBuf,
+}
+
+#[cfg(test)]
+impl TestConfig {
+    fn new(contents: &str) -> TestConfig {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "raytracer_test_{}_{}.json5",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, contents).unwrap();
+        TestConfig { path }
+    }
+
+    // The rest of a scene, in front of the usual camera, on black
+    fn scene(fields: &str) -> TestConfig {
+        TestConfig::new(&format!(
+            r#"{{
+                camera: {{
+                    lookFrom: [0, 0, -5], lookAt: [0, 0, 0], directionUp: [0, 1, 0],
+                    fieldOfView: 40, aspectRatio: [1, 1], aperture: 0,
+                    distanceToFocus: 5, startTime: 0, endTime: 1,
+                }},
+                background: {{ type: "plainColour", colour: [0, 0, 0] }},
+                {}
+            }}"#,
+            fields
+        ))
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestConfig {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+fn load_test_scene(fields: &str) -> Result<Scene> {
+    load_config(&TestConfig::scene(fields).path, &[], 0.0, None, None)
+}
+
+#[test]
+fn levenshtein_test() {
+    assert_eq!(levenshtein("kitten", "sitting"), 3);
+    assert_eq!(levenshtein("", "abc"), 3);
+    assert_eq!(levenshtein("white", "white"), 0);
+}
+
+#[test]
+fn config_error_test() {
+    let source = "{\n    camera: {\n        lookFrom: [1, 2,, 3],\n";
+    let err = json5::from_str::<Value>(source).unwrap_err();
+    let formatted = format_config_error(source, &err);
+    let mut lines = formatted.lines().skip(1);
+    let line = lines.next().unwrap();
+    let pointer = lines.next().unwrap();
+    assert_eq!(line, "        lookFrom: [1, 2,, 3],");
+    assert_eq!(&line[pointer.len() - 1..pointer.len()], ",");
+}
+
+#[test]
+fn config_error_kind_test() {
+    let err = load_test_scene(
+        r#"materials: { m: { type: "lambertian", texture: "nothing" } },
+        objects: {}, world: [],"#,
+    )
+    .err()
+    .unwrap();
+    assert!(matches!(err.root(), ConfigError::MissingTexture(_)));
+    let err = load_test_scene(
+        r#"objects: { s: { type: "sphere", centre: [0, 0, 0], radius: 1, material: "nothing" } },
+        world: ["s"],"#,
+    )
+    .err()
+    .unwrap();
+    assert!(matches!(err.root(), ConfigError::MissingMaterial(_)));
+    let err = load_test_scene(r#"objects: {}, world: ["nothing"],"#)
+        .err()
+        .unwrap();
+    assert!(matches!(err.root(), ConfigError::MissingObject(_)));
+    let config = TestConfig::new("{\n  camera: [1,, 2],\n}");
+    let err = load_config(&config.path, &[], 0.0, None, None)
+        .err()
+        .unwrap();
+    assert!(matches!(err.root(), ConfigError::Parse(_, 2, _)));
+    let path = config.path.clone();
+    drop(config);
+    let err = load_config(&path, &[], 0.0, None, None).err().unwrap();
+    assert!(matches!(err.root(), ConfigError::Io(_)));
+}
+
+#[test]
+fn named_views_test() {
+    let camera = |aspect_ratio| {
+        format!(
+            r#"{{
+                lookFrom: [0, 0, 1], lookAt: [0, 0, 0], directionUp: [0, 1, 0], fieldOfView: 40,
+                aspectRatio: [{}, 1], aperture: 0, distanceToFocus: 1, startTime: 0, endTime: 1,
+            }}"#,
+            aspect_ratio
+        )
+    };
+    let config = TestConfig::new(&format!(
+        r#"{{
+            camera: {},
+            namedViews: {{ wide: {}, square: {} }},
+            background: {{ type: "plainColour", colour: [0, 0, 0] }},
+            textures: {{ white: {{ type: "solidColour", colour: [1, 1, 1] }} }},
+            materials: {{ matte: {{ type: "lambertian", texture: "white" }} }},
+            objects: {{ ball: {{ type: "sphere", centre: [0, 0, 0], radius: 1, material: "matte" }} }},
+            world: ["ball"],
+        }}"#,
+        camera(1),
+        camera(2),
+        camera(1)
+    ));
+    let path = &config.path;
+    let aspect_ratio = |view| load_config(path, &[], 0.0, None, view).unwrap().5;
+    assert_eq!(aspect_ratio(None), 1.0);
+    assert_eq!(aspect_ratio(Some("wide")), 2.0);
+    // overrides of the camera apply to the chosen view
+    let taller = [(String::from("camera.aspectRatio.0"), String::from("3"))];
+    let (_, _, _, _, _, aspect_ratio) =
+        load_config(path, &taller, 0.0, None, Some("wide")).unwrap();
+    assert_eq!(aspect_ratio, 3.0);
+    assert!(load_config(path, &[], 0.0, None, Some("nothing")).is_err());
+    let views = list_views(path).unwrap();
+    assert!(views.starts_with("square: from (0, 0, 1) looking at (0, 0, 0)\nwide: "));
+}
+
+#[test]
+fn keyframe_test() {
+    let config: Value = json5::from_str(
+        r#"{ camera: {
+            lookFrom: [{ time: 0, value: [0, 0, 0] }, { time: 2, value: [4, 2, 0] }],
+            lookAt: [1, 2, 3],
+        } }"#,
+    )
+    .unwrap();
+    let look_from = |time| {
+        let mut config = config.clone();
+        evaluate_at_time(&mut config, time).unwrap();
+        assert_eq!(
+            config["camera"]["lookAt"],
+            json5::from_str::<Value>("[1, 2, 3]").unwrap()
+        );
+        serde_json::from_value::<[f64; 3]>(config["camera"]["lookFrom"].take()).unwrap()
+    };
+    assert_eq!(look_from(0.5), [1.0, 0.5, 0.0]);
+    // held still outside the keyframes
+    assert_eq!(look_from(-1.0), [0.0, 0.0, 0.0]);
+    assert_eq!(look_from(3.0), [4.0, 2.0, 0.0]);
+
+    let mut backwards: Value = json5::from_str(
+        r#"{ camera: { lookAt: [{ time: 1, value: [0, 0, 0] }, { time: 0, value: [1, 1, 1] }] } }"#,
+    )
+    .unwrap();
+    assert!(evaluate_at_time(&mut backwards, 0.5).is_err());
+}
+
+#[test]
+fn variables_test() {
+    let mut config: Value = json5::from_str(
+        r#"{
+            variables: { ior: 1.5, glass: "clear", from: [1, 2, 3] },
+            camera: { lookFrom: "$from" },
+            materials: { clear: { type: "dielectric", indexOfRefraction: "$ior" } },
+            world: ["$glass", "$5", "a$glass"],
+        }"#,
+    )
+    .unwrap();
+    substitute_variables(&mut config).unwrap();
+    assert!(config.get("variables").is_none());
+    assert_eq!(config["camera"]["lookFrom"], serde_json::json!([1, 2, 3]));
+    assert_eq!(config["materials"]["clear"]["indexOfRefraction"], 1.5);
+    assert_eq!(
+        config["world"],
+        serde_json::json!(["clear", "$5", "a$glass"])
+    );
+
+    let mut undefined: Value = json5::from_str(r#"{ world: ["$nothing"] }"#).unwrap();
+    let err = substitute_variables(&mut undefined).unwrap_err();
+    assert!(err.to_string().contains("$nothing"));
+}
+
+#[test]
+fn camera_keyframe_test() {
+    let keyframe = |time, x| Keyframe {
+        time,
+        value: [x, 1.0, 0.0],
+    };
+    let keyframes = [keyframe(0.0, 0.0), keyframe(1.0, 2.0), keyframe(3.0, 6.0)];
+    let x_at = |interpolation, time| interpolate(&keyframes, interpolation, time)[0];
+    for &interpolation in &[
+        KeyframeInterpolation::Linear,
+        KeyframeInterpolation::Hermite,
+    ] {
+        // steady motion stays steady either way, and passes through each keyframe
+        for &(time, x) in &[(0.0, 0.0), (0.5, 1.0), (1.0, 2.0), (2.5, 5.0), (3.0, 6.0)] {
+            assert!((x_at(interpolation, time) - x).abs() < 1e-9);
+        }
+        assert_eq!(x_at(interpolation, -1.0), 0.0);
+        assert_eq!(x_at(interpolation, 4.0), 6.0);
+    }
+
+    // a turnaround: linear goes straight there, hermite overshoots smoothly
+    let keyframes = [keyframe(0.0, 0.0), keyframe(1.0, 1.0), keyframe(2.0, 0.0)];
+    let x_at = |interpolation, time| interpolate(&keyframes, interpolation, time)[0];
+    assert!((x_at(KeyframeInterpolation::Linear, 0.5) - 0.5).abs() < 1e-9);
+    assert!(x_at(KeyframeInterpolation::Hermite, 0.5) > 0.5);
+    assert!((x_at(KeyframeInterpolation::Hermite, 1.0) - 1.0).abs() < 1e-9);
+
+    // cameraKeyframes moves lookFrom and lookAt together, over whatever they say
+    let config: Value = json5::from_str(
+        r#"{ camera: {
+            lookFrom: [9, 9, 9],
+            cameraKeyframes: [
+                { time: 0, lookFrom: [0, 0, 0], lookAt: [0, 0, -1] },
+                { time: 1, lookFrom: [1, 0, 0], lookAt: [1, 0, -1] },
+                { time: 2, lookFrom: [0, 0, 0], lookAt: [0, 0, -1] },
+            ],
+            keyframeInterpolation: "hermite",
+        } }"#,
+    )
+    .unwrap();
+    let mut camera = config.clone();
+    evaluate_at_time(&mut camera, 0.5).unwrap();
+    let look_from: [f64; 3] = serde_json::from_value(camera["camera"]["lookFrom"].take()).unwrap();
+    let look_at: [f64; 3] = serde_json::from_value(camera["camera"]["lookAt"].take()).unwrap();
+    assert!(look_from[0] > 0.5);
+    assert_eq!(look_from[0], look_at[0]);
+    assert!(camera["camera"].get("cameraKeyframes").is_none());
+    assert!(camera["camera"].get("keyframeInterpolation").is_none());
+
+    let mut missing: Value =
+        json5::from_str(r#"{ camera: { cameraKeyframes: [{ time: 0, lookFrom: [0, 0, 0] }] } }"#)
+            .unwrap();
+    assert!(evaluate_at_time(&mut missing, 0.0).is_err());
+    let mut empty: Value = json5::from_str(r#"{ camera: { cameraKeyframes: [] } }"#).unwrap();
+    assert!(evaluate_at_time(&mut empty, 0.0).is_err());
+}
+
+#[test]
+fn validate_test() {
+    let Validation { report, warnings } =
+        validate_config("examples/two_spheres.json5", &[], 0.0).unwrap();
+    assert_eq!(warnings, 2);
+    assert!(report.contains("sphere2 (sphere): (-100, 0, -100) to (100, 200, 100)"));
+    assert!(report.contains("World: 6 objects, unbounded"));
+    assert!(report.contains("Warnings:\n  floor has no bounding box"));
+    let typo = [(String::from("objects.sphere2.radiuss"), String::from("1"))];
+    assert!(validate_config("examples/two_spheres.json5", &typo, 0.0).is_err());
+
+    let config = TestConfig::scene(
+        r#"
+        textures: {
+            white: { type: "solidColour", colour: [1, 1, 1] },
+            spare: { type: "solidColour", colour: [1, 0, 0] },
+        },
+        materials: {
+            matte: { type: "lambertian", texture: "white" },
+            shiny: { type: "metal", fuzz: 0, albedo: [1, 1, 1] },
+        },
+        objects: {
+            bubble: { type: "sphere", centre: [0, 0, 0], radius: -1, material: "matte" },
+            sliver: {
+                type: "rect", corner0: [0, 0, 0], corner1: [0, 0, 1],
+                facingForward: true, material: "matte",
+            },
+        },
+        world: ["bubble", "sliver"],
+        "#,
+    );
+    let Validation { report, warnings } = validate_config(&config.path, &[], 0.0).unwrap();
+    for warning in &[
+        "Texture spare is never used",
+        "Material shiny is never used",
+        "The radius of sphere bubble must be positive, not -1",
+        "Rect sliver has no area",
+    ] {
+        assert!(report.contains(warning), "{} not in {}", warning, report);
+    }
+    assert_eq!(warnings, 4);
+    // a backwards shutter can't be rendered at all
+    let backwards = [
+        (String::from("camera.startTime"), String::from("0.8")),
+        (String::from("camera.endTime"), String::from("0.2")),
+    ];
+    assert!(validate_config(&config.path, &backwards, 0.0).is_err());
+}
+
+#[test]
+fn include_test() {
+    let dir = std::env::temp_dir().join(format!("raytracer_include_test_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("parts")).unwrap();
+    let write = |name: &str, contents: &str| std::fs::write(dir.join(name), contents).unwrap();
+    write(
+        "scene.json5",
+        r#"{
+            includes: ["parts/materials.json5", "parts/ball.json5"],
+            camera: {
+                lookFrom: [0, 0, -5], lookAt: [0, 0, 0], directionUp: [0, 1, 0],
+                fieldOfView: 40, aspectRatio: [1, 1], aperture: 0,
+                distanceToFocus: 5, startTime: 0, endTime: 1,
+            },
+            background: { type: "plainColour", colour: [0, 0, 0] },
+            world: [],
+        }"#,
+    );
+    // paths in included files are relative to the included file
+    write(
+        "parts/materials.json5",
+        r#"{ materials: { red: { type: "metal", fuzz: 0, albedo: [1, 0, 0] } } }"#,
+    );
+    write(
+        "parts/ball.json5",
+        r#"{
+            objects: { ball: { type: "sphere", centre: [0, 0, 0], radius: 1, material: "red" } },
+            world: ["ball"],
+        }"#,
+    );
+    let (_, world, _, _, _, _) =
+        load_config(dir.join("scene.json5"), &[], 0.0, None, None).unwrap();
+    let ray = crate::math::Ray::new(Point3::new(0, 0, -5), Vec3::new(0, 0, 1), 0.0);
+    assert!(world.hit(&ray, 0.001, f64::INFINITY).is_some());
+    // overrides apply once the includes are merged, so they can reach into them
+    let moved = [(String::from("objects.ball.centre.0"), String::from("10"))];
+    let (_, world, _, _, _, _) =
+        load_config(dir.join("scene.json5"), &moved, 0.0, None, None).unwrap();
+    assert!(world.hit(&ray, 0.001, f64::INFINITY).is_none());
+    let included = included_files(dir.join("scene.json5")).unwrap();
+    let part = |name: &str| dir.join("parts").join(name).canonicalize().unwrap();
+    assert_eq!(included, [part("materials.json5"), part("ball.json5")]);
+
+    write("parts/ball.json5", r#"{ includes: ["../scene.json5"] }"#);
+    let err = load_config(dir.join("scene.json5"), &[], 0.0, None, None)
+        .err()
+        .unwrap();
+    assert!(err.to_string().starts_with("Circular include"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn isolate_test() {
+    let path = PathBuf::from("examples/two_spheres.json5");
+    let isolate = |names: &[&str], remove_others| Isolate {
+        names: names.iter().map(|&n| String::from(n)).collect(),
+        remove_others,
+    };
+    // straight at sphere2, and nothing else
+    let ray = crate::math::Ray::new(Point3::new(0, 100, -500), Vec3::new(0, 0, 1), 0.0);
+    let hits = |isolate: &Isolate| {
+        let (_, world, _, _, _, _) = load_config(&path, &[], 0.0, Some(isolate), None).unwrap();
+        world.hit(&ray, 0.001, f64::INFINITY)
+    };
+    let blacked_out = hits(&isolate(&["sphere"], false)).unwrap();
+    assert!(blacked_out.material.to_string().starts_with("Lambertian"));
+    assert!(hits(&isolate(&["sphere"], true)).is_none());
+    assert!(hits(&isolate(&["sphere", "sphere2"], true)).is_some());
+    assert!(load_config(&path, &[], 0.0, Some(&isolate(&["nothing"], true)), None).is_err());
+}
+
+#[test]
+fn unbuildable_objects_test() {
+    let scene = r#"
+        objects: {
+            ball: {
+                type: "sphere", centre: [0, 0, 0], radius: 1,
+                material: { type: "metal", fuzz: 0, albedo: [1, 1, 1] },
+            },
+            both: { type: "group", members: ["ball", "up"] },
+            up: { type: "translate", prototype: "down", offset: [0, 1, 0] },
+            down: { type: "translate", prototype: "up", offset: [0, -1, 0] },
+            lost: { type: "rotateY", prototype: "nowhere", degrees: 10 },
+        },
+        world: ["ball"],
+    "#;
+    let message = load_test_scene(scene).err().unwrap().to_string();
+    assert!(
+        message.contains("circular reference: up -> down -> up"),
+        "{}",
+        message
+    );
+    assert!(message.contains("both needs up\n"));
+    assert!(message.contains("lost needs nowhere (not defined)"));
+}
+
+#[test]
+fn nested_group_test() {
+    let scene = r#"
+        materials: { red: { type: "metal", fuzz: 0, albedo: [1, 0, 0] } },
+        objects: {
+            scene: { type: "group", members: ["balls", "floor"] },
+            balls: { type: "group", members: ["left", "right"] },
+            left: { type: "sphere", centre: [-2, 0, 0], radius: 1, material: "red" },
+            right: { type: "sphere", centre: [2, 0, 0], radius: 1, material: "red" },
+            floor: { type: "sphere", centre: [0, -101, 0], radius: 100, material: "red" },
+        },
+        world: ["scene"],
+    "#;
+    let (_, world, _, _, _, _) = load_test_scene(scene).unwrap();
+    let ray = |x| crate::math::Ray::new(Point3::new(x, 0, -5), Vec3::new(0, 0, 1), 0.0);
+    assert!(world.hit(&ray(2.0), 0.001, f64::INFINITY).is_some());
+    assert!(world.hit(&ray(-2.0), 0.001, f64::INFINITY).is_some());
+    assert!(world.hit(&ray(0.0), 0.001, f64::INFINITY).is_none());
+}
+
+#[test]
+fn grid_test() {
+    let scene = r#"
+        objects: {
+            ball: {
+                type: "sphere", centre: [0, 0, 0], radius: 0.5,
+                material: { type: "metal", fuzz: 0, albedo: [1, 1, 1] },
+            },
+            balls: {
+                type: "grid", prototype: "ball", nx: 3, nz: 2, spacingX: 2, spacingZ: 5,
+                yHeightTexture: { type: "solidColour", colour: [1, 2, 3] }, heightScale: 0.5,
+            },
+        },
+        world: ["balls"],
+    "#;
+    let (_, world, _, _, _, _) = load_test_scene(scene).unwrap();
+    let down = |x, z| {
+        let ray = crate::math::Ray::new(Point3::new(x, 10, z), Vec3::new(0, -1, 0), 0.0);
+        world.hit(&ray, 0.001, f64::INFINITY)
+    };
+    // every copy is raised by the texture's brightness of 2, halved
+    let top = down(4.0, 5.0).unwrap();
+    assert!((top.intersection.y - 1.5).abs() < 1e-9);
+    assert!(down(0.0, 0.0).is_some());
+    assert!(down(6.0, 0.0).is_none());
+    assert!(down(0.0, 10.0).is_none());
+
+    assert!(load_test_scene(&scene.replace("nx: 3", "nx: 0")).is_err());
+}
+
+#[test]
+fn visibility_flags_test() {
+    use crate::math::RayType;
+    let scene = r#"
+        materials: { red: { type: "metal", fuzz: 0, albedo: [1, 0, 0] } },
+        objects: {
+            hidden: {
+                type: "sphere", centre: [-2, 0, 0], radius: 1, material: "red",
+                visible: false,
+            },
+            shadowless: {
+                type: "sphere", centre: [2, 0, 0], radius: 1, material: "red",
+                castsShadow: false,
+            },
+        },
+        world: ["hidden", "shadowless"],
+    "#;
+    let (_, world, _, _, _, _) = load_test_scene(scene).unwrap();
+    let ray = |x, kind| {
+        crate::math::Ray::new(Point3::new(x, 0, -5), Vec3::new(0, 0, 1), 0.0).of_kind(kind)
+    };
+    let hits = |x, kind| world.hit(&ray(x, kind), 0.001, f64::INFINITY).is_some();
+    assert!(!hits(-2.0, RayType::Primary));
+    assert!(hits(-2.0, RayType::Indirect));
+    assert!(hits(2.0, RayType::Primary));
+    assert!(!hits(2.0, RayType::Shadow));
+}
+
+#[test]
+fn sdf_test() {
+    let scene = r#"
+        materials: { red: { type: "metal", fuzz: 0, albedo: [1, 0, 0] } },
+        objects: {
+            box: {
+                type: "sdf", material: "red",
+                sdf: {
+                    type: "translate", offset: [-4, 0, 0],
+                    sdf: { type: "box", dimensions: [2, 2, 2] },
+                },
+            },
+            torus: {
+                type: "sdf", material: "red",
+                sdf: {
+                    type: "translate", offset: [4, 0, 0],
+                    sdf: { type: "torus", majorRadius: 1, minorRadius: 0.25 },
+                },
+            },
+            blob: {
+                type: "sdf", material: "red",
+                sdf: {
+                    type: "smoothUnion", k: 0.5,
+                    a: { type: "translate", offset: [0, 0.5, 0], sdf: { type: "sphere", radius: 0.5 } },
+                    b: { type: "translate", offset: [0, -0.5, 0], sdf: { type: "sphere", radius: 0.5 } },
+                },
+            },
+        },
+        world: ["box", "torus", "blob"],
+    "#;
+    let (_, world, _, _, _, _) = load_test_scene(scene).unwrap();
+    let hit = |origin: Point3, direction: Vec3| {
+        let ray = crate::math::Ray::new(origin, direction, 0.0);
+        world
+            .hit(&ray, 0.001, f64::INFINITY)
+            .map(|hit| hit.intersection)
+    };
+    let hit_z = |x, y| hit(Point3::new(x, y, -5), Vec3::new(0, 0, 1)).map(|p| p.z);
+    assert!((hit_z(-4.0, 0.0).unwrap() + 1.0).abs() < 0.001);
+    // down through the hole of the torus, then onto its ring
+    assert!(hit(Point3::new(4, 5, 0), Vec3::new(0, -1, 0)).is_none());
+    let ring = hit(Point3::new(5, 5, 0), Vec3::new(0, -1, 0)).unwrap();
+    assert!((ring.y - 0.25).abs() < 0.001);
+    // where the two spheres meet, the blend fills in the gap between them
+    let waist = hit_z(0.0, 0.0).unwrap();
+    assert!(waist < -0.001);
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn yaml_config_test() {
+    let filename = PathBuf::from("examples/sphere.yaml");
+    let (_, world, _, _, _, aspect_ratio) = load_config(&filename, &[], 0.0, None, None).unwrap();
+    assert_eq!(aspect_ratio, 1.5);
+    let ray = crate::math::Ray::new(Point3::new(0, 1, -5), Vec3::new(0, 0, 1), 0.0);
+    let hit = world.hit(&ray, 0.001, f64::INFINITY).unwrap();
+    assert_eq!(hit.intersection, Point3::new(0, 1, -1));
+}
+
+#[test]
+fn hdr_environment_test() {
+    use image::codecs::hdr::HdrEncoder;
+    use image::Rgb;
+    // one bright pixel in a dim sky
+    let mut pixels = vec![Rgb([0.25f32, 0.25, 0.25]); 8];
+    pixels[5] = Rgb([8.0, 8.0, 8.0]);
+    let image = std::env::temp_dir().join(format!("raytracer_test_{}_sky.hdr", std::process::id()));
+    HdrEncoder::new(File::create(&image).unwrap())
+        .encode(&pixels, 4, 2)
+        .unwrap();
+    let config = TestConfig::new(&format!(
+        r#"{{
+            camera: {{
+                lookFrom: [0, 0, -5], lookAt: [0, 0, 0], directionUp: [0, 1, 0],
+                fieldOfView: 40, aspectRatio: [1, 1], aperture: 0,
+                distanceToFocus: 5, startTime: 0, endTime: 1,
+            }},
+            background: {{ type: "hdrEnvironment", filename: {:?}, exposure: 2 }},
+            materials: {{ red: {{ type: "metal", fuzz: 0, albedo: [1, 0, 0] }} }},
+            objects: {{ ball: {{ type: "sphere", centre: [0, 0, 0], radius: 1, material: "red" }} }},
+            world: ["ball"],
+        }}"#,
+        image.to_str().unwrap()
+    ));
+    let scene = load_config(&config.path, &[], 0.0, None, None);
+    std::fs::remove_file(&image).unwrap();
+    let (_, _, lights, sky, _, _) = scene.unwrap();
+    assert!(lights.is_empty());
+    // the table built at load time picks out the bright pixel, with the exposure
+    assert!(sky.sampled_as_light());
+    let bright = (0..100)
+        .filter(|_| sky.sample().2 == Colour::new(16, 16, 16))
+        .count();
+    assert!(bright > 50);
 }
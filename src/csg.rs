@@ -0,0 +1,313 @@
+// Constructive solid geometry: solids made by combining closed objects
+
+use std::collections::HashSet;
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+
+use crate::hitting::{shared_memory_bytes, surrounding_box, HitRecord, Hittable, AABB};
+use crate::math::{Point3, Ray};
+
+// Solid made up of everything inside either of two closed objects
+pub struct CSGUnion {
+    left: Arc<dyn Hittable>,
+    right: Arc<dyn Hittable>,
+}
+
+impl CSGUnion {
+    pub fn new(left: &Arc<dyn Hittable>, right: &Arc<dyn Hittable>) -> Arc<dyn Hittable> {
+        Arc::new(CSGUnion {
+            left: Arc::clone(left),
+            right: Arc::clone(right),
+        })
+    }
+}
+
+impl Hittable for CSGUnion {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        csg_hit(ray, min_dist, max_dist, &self.left, &self.right, |l, r| {
+            l || r
+        })
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        match (
+            self.left.bounding_box(time0, time1),
+            self.right.bounding_box(time0, time1),
+        ) {
+            (Some(left), Some(right)) => Some(surrounding_box(&left, &right)),
+            _ => None,
+        }
+    }
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self)
+            + shared_memory_bytes(&self.left, seen)
+            + shared_memory_bytes(&self.right, seen)
+    }
+}
+
+impl fmt::Display for CSGUnion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "union ({}, {})", self.left, self.right)
+    }
+}
+
+// Solid made up of everything inside base but not inside cutter
+pub struct CSGDifference {
+    base: Arc<dyn Hittable>,
+    cutter: Arc<dyn Hittable>,
+}
+
+impl CSGDifference {
+    pub fn new(base: &Arc<dyn Hittable>, cutter: &Arc<dyn Hittable>) -> Arc<dyn Hittable> {
+        Arc::new(CSGDifference {
+            base: Arc::clone(base),
+            cutter: Arc::clone(cutter),
+        })
+    }
+}
+
+impl Hittable for CSGDifference {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        // where the cutter's surface is exposed, its inside is the solid's
+        // outside, which csg_hit takes care of by setting front_face
+        csg_hit(ray, min_dist, max_dist, &self.base, &self.cutter, |b, c| {
+            b && !c
+        })
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        self.base.bounding_box(time0, time1)
+    }
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self)
+            + shared_memory_bytes(&self.base, seen)
+            + shared_memory_bytes(&self.cutter, seen)
+    }
+}
+
+impl fmt::Display for CSGDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "difference ({}, {})", self.base, self.cutter)
+    }
+}
+
+// Solid made up of everything inside both of two closed objects
+pub struct CSGIntersection {
+    left: Arc<dyn Hittable>,
+    right: Arc<dyn Hittable>,
+}
+
+impl CSGIntersection {
+    pub fn new(left: &Arc<dyn Hittable>, right: &Arc<dyn Hittable>) -> Arc<dyn Hittable> {
+        Arc::new(CSGIntersection {
+            left: Arc::clone(left),
+            right: Arc::clone(right),
+        })
+    }
+}
+
+impl Hittable for CSGIntersection {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        csg_hit(ray, min_dist, max_dist, &self.left, &self.right, |l, r| {
+            l && r
+        })
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        match (
+            self.left.bounding_box(time0, time1),
+            self.right.bounding_box(time0, time1),
+        ) {
+            (Some(left), Some(right)) => Some(overlapping_box(&left, &right)),
+            (Some(bbox), None) | (None, Some(bbox)) => Some(bbox),
+            (None, None) => None,
+        }
+    }
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self)
+            + shared_memory_bytes(&self.left, seen)
+            + shared_memory_bytes(&self.right, seen)
+    }
+}
+
+impl fmt::Display for CSGIntersection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "intersection ({}, {})", self.left, self.right)
+    }
+}
+
+// If the boxes don't overlap, this is inside out, which AABB treats as empty
+fn overlapping_box(box0: &AABB, box1: &AABB) -> AABB {
+    let minimum = Point3::new(
+        f64::max(box0.minimum.x, box1.minimum.x),
+        f64::max(box0.minimum.y, box1.minimum.y),
+        f64::max(box0.minimum.z, box1.minimum.z),
+    );
+    let maximum = Point3::new(
+        f64::min(box0.maximum.x, box1.maximum.x),
+        f64::min(box0.maximum.y, box1.maximum.y),
+        f64::min(box0.maximum.z, box1.maximum.z),
+    );
+    AABB { minimum, maximum }
+}
+
+// a ray grazing along a surface could otherwise cross it endlessly
+const MAX_CSG_CROSSINGS: usize = 64;
+
+// Every time the ray crosses the object's surface before max_dist, in order.
+// The object has to be closed, so that the crossings alternate between
+// entering and leaving it. That's counted rather than read off front_face,
+// which depends on which way each surface's normal was set up to point.
+fn surface_crossings(object: &Arc<dyn Hittable>, ray: &Ray, max_dist: f64) -> Vec<HitRecord> {
+    let mut crossings = Vec::new();
+    let mut from = f64::NEG_INFINITY;
+    while let Some(hit) = object.hit(ray, from, max_dist) {
+        from = hit.distance + 0.0001;
+        crossings.push(hit);
+        if crossings.len() >= MAX_CSG_CROSSINGS {
+            break;
+        }
+    }
+    crossings
+}
+
+// Finds where the ray first crosses the boundary of the solid made up of all
+// points for which inside(in left, in right) is true
+fn csg_hit<F: Fn(bool, bool) -> bool>(
+    ray: &Ray,
+    min_dist: f64,
+    max_dist: f64,
+    left: &Arc<dyn Hittable>,
+    right: &Arc<dyn Hittable>,
+    inside: F,
+) -> Option<HitRecord> {
+    let mut left_crossings = surface_crossings(left, ray, max_dist)
+        .into_iter()
+        .peekable();
+    let mut right_crossings = surface_crossings(right, ray, max_dist)
+        .into_iter()
+        .peekable();
+    let (mut in_left, mut in_right) = (false, false);
+    loop {
+        let take_left = match (left_crossings.peek(), right_crossings.peek()) {
+            (Some(l), Some(r)) => l.distance < r.distance,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => return None,
+        };
+        let was_inside = inside(in_left, in_right);
+        let hit = if take_left {
+            in_left = !in_left;
+            left_crossings.next()
+        } else {
+            in_right = !in_right;
+            right_crossings.next()
+        }
+        .unwrap();
+        let now_inside = inside(in_left, in_right);
+        if was_inside != now_inside && hit.distance >= min_dist {
+            // the normal already faces the ray, so only front_face can change
+            return Some(HitRecord {
+                front_face: now_inside,
+                ..hit
+            });
+        }
+    }
+}
+
+#[test]
+fn csg_union_test() {
+    use crate::hitting::Colour;
+    use crate::materials::Lambertian;
+    use crate::math::Vec3;
+    use crate::objects::Sphere;
+
+    let material = Lambertian::with_colour(Colour::new(1, 1, 1));
+    let union = CSGUnion::new(
+        &Sphere::new(Point3::new(-0.5, 0, 0), 1.0, &material),
+        &Sphere::new(Point3::new(0.5, 0, 0), 1.0, &material),
+    );
+    let outside = Ray::new(Point3::new(-5, 0, 0), Vec3::new(1, 0, 0), 0.0);
+    let hit = union.hit(&outside, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.intersection - Point3::new(-1.5, 0, 0)).near_zero());
+    assert!(hit.front_face);
+    // the spheres' surfaces inside each other aren't part of the union
+    let inside = Ray::new(Point3::new(-1, 0, 0), Vec3::new(1, 0, 0), 0.0);
+    let hit = union.hit(&inside, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.intersection - Point3::new(1.5, 0, 0)).near_zero());
+    assert!(!hit.front_face);
+}
+
+#[test]
+fn csg_difference_test() {
+    use crate::hitting::Colour;
+    use crate::materials::Lambertian;
+    use crate::math::Vec3;
+    use crate::objects::Sphere;
+
+    let material = Lambertian::with_colour(Colour::new(1, 1, 1));
+    let difference = CSGDifference::new(
+        &Sphere::new(Point3::new(0, 0, 0), 1.0, &material),
+        &Sphere::new(Point3::new(-1, 0, 0), 1.0, &material),
+    );
+    // the ray goes through the bite taken out, and hits the cutter's surface
+    let ray = Ray::new(Point3::new(-5, 0, 0), Vec3::new(1, 0, 0), 0.0);
+    let hit = difference.hit(&ray, 0.001, f64::INFINITY).unwrap();
+    assert!(hit.intersection.near_zero());
+    assert!(hit.front_face);
+    assert!((hit.normal - Vec3::new(-1, 0, 0)).near_zero());
+    // and then leaves through the base
+    let ray = Ray::new(Point3::new(0.5, 0, 0), Vec3::new(1, 0, 0), 0.0);
+    let hit = difference.hit(&ray, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.intersection - Point3::new(1, 0, 0)).near_zero());
+    assert!(!hit.front_face);
+}
+
+#[test]
+fn csg_intersection_test() {
+    use crate::hitting::Colour;
+    use crate::materials::Lambertian;
+    use crate::math::Vec3;
+    use crate::objects::Sphere;
+
+    let material = Lambertian::with_colour(Colour::new(1, 1, 1));
+    let lens = CSGIntersection::new(
+        &Sphere::new(Point3::new(-0.5, 0, 0), 1.0, &material),
+        &Sphere::new(Point3::new(0.5, 0, 0), 1.0, &material),
+    );
+    // enters through the right sphere, leaves through the left
+    let ray = Ray::new(Point3::new(-5, 0, 0), Vec3::new(1, 0, 0), 0.0);
+    let hit = lens.hit(&ray, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.intersection - Point3::new(-0.5, 0, 0)).near_zero());
+    assert!(hit.front_face);
+    let ray = Ray::new(Point3::new(0, 0, 0), Vec3::new(1, 0, 0), 0.0);
+    let hit = lens.hit(&ray, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.intersection - Point3::new(0.5, 0, 0)).near_zero());
+    assert!(!hit.front_face);
+    // only inside one of them
+    let ray = Ray::new(Point3::new(-1.2, 5, 0), Vec3::new(0, -1, 0), 0.0);
+    assert!(lens.hit(&ray, 0.001, f64::INFINITY).is_none());
+    let bbox = lens.bounding_box(0.0, 1.0).unwrap();
+    assert!((bbox.minimum - Point3::new(-0.5, -1, -1)).near_zero());
+    assert!((bbox.maximum - Point3::new(0.5, 1, 1)).near_zero());
+}
+
+#[test]
+fn csg_disjoint_intersection_test() {
+    use crate::hitting::Colour;
+    use crate::materials::Lambertian;
+    use crate::math::Vec3;
+    use crate::objects::Sphere;
+
+    let material = Lambertian::with_colour(Colour::new(1, 1, 1));
+    let nothing = CSGIntersection::new(
+        &Sphere::new(Point3::new(-2, 0, 0), 1.0, &material),
+        &Sphere::new(Point3::new(2, 0, 0), 1.0, &material),
+    );
+    let bbox = nothing.bounding_box(0.0, 1.0).unwrap();
+    for ray in &[
+        Ray::new(Point3::new(-5, 0, 0), Vec3::new(1, 0, 0), 0.0),
+        Ray::new(Point3::new(0, 0, -5), Vec3::new(0, 0, 1), 0.0),
+    ] {
+        assert!(!bbox.intersects(ray, 0.001, f64::INFINITY));
+        assert!(nothing.hit(ray, 0.001, f64::INFINITY).is_none());
+    }
+}
@@ -1,35 +1,201 @@
+use anyhow::{bail, Result};
 use rand::Rng;
 
+use std::cell::Cell;
 use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::fmt;
+use std::mem;
 use std::sync::Arc;
 
-use crate::math::{coeff, dot, Point3, Ray, Vec3};
+use crate::atmosphere::AtmosphericFog;
+use crate::camera::Sky;
+use crate::colour_space::xyz_to_rgb;
+use crate::math::{
+    coeff, dot, power_heuristic, random_cosine_direction, Onb, Point3, Ray, RayType, Vec3,
+};
+use crate::random;
+use crate::stats;
 
 pub type Colour = Vec3;
 
-pub fn cast_ray<T: Fn(&Ray) -> Colour>(
+// Returns the light coming back along the ray, along with the albedo
+// (unlit colour) of the first thing it hits
+pub fn cast_ray(
     ray: &Ray,
+    ray_type: RayType,
     world: &Arc<dyn Hittable>,
-    sky: T,
+    lights: &[Arc<dyn Emitter>],
+    sky: &dyn Sky,
+    atmosphere: Option<&AtmosphericFog>,
     bounces: u32,
-) -> Colour {
+) -> (Colour, Colour) {
+    let ray = &Ray {
+        kind: ray_type,
+        ..*ray
+    };
+    trace(ray, world, lights, sky, atmosphere, bounces, 1.0)
+}
+
+// Light reaching diffuse surfaces is sampled twice, once by scattering and
+// once by picking a point on a light, so whatever a scattered ray finds
+// shining at it is scaled by emission_weight to share it out between the two
+fn trace(
+    ray: &Ray,
+    world: &Arc<dyn Hittable>,
+    lights: &[Arc<dyn Emitter>],
+    sky: &dyn Sky,
+    atmosphere: Option<&AtmosphericFog>,
+    bounces: u32,
+    emission_weight: f64,
+) -> (Colour, Colour) {
     if bounces == 0 {
-        return Colour::new(0, 0, 0);
+        return (Colour::new(0, 0, 0), Colour::new(0, 0, 0));
     }
+    let through_atmosphere = |colour, distance| match atmosphere {
+        Some(fog) => fog.apply(colour, distance),
+        None => colour,
+    };
     // min distance is 0.001, to prevent "shadow acne"
     if let Some(hit) = world.hit(ray, 0.001, f64::INFINITY) {
-        let emitted = hit.material.emitted(&hit);
+        stats::GEOMETRY_HITS.increment();
+        let emitted = emission_weight * hit.material.emitted(ray, &hit);
         if let Some((new_ray, attenuation)) = hit.material.scatter(ray, &hit) {
-            emitted + coeff(attenuation, cast_ray(&new_ray, world, sky, bounces - 1))
+            stats::SECONDARY_RAYS.increment();
+            let new_ray = new_ray.of_kind(RayType::Indirect);
+            let scattering_pdf = hit.material.scattering_pdf(&hit, new_ray.direction);
+            // sampled light stands in for what the next bounce would find, so
+            // only diffuse hits with a bounce left over sample lights
+            let has_lights = !lights.is_empty() || sky.sampled_as_light();
+            let (direct, weight) = if scattering_pdf > 0.0 && bounces > 1 && has_lights {
+                let light_pdf = lights_pdf(lights, sky, hit.intersection, new_ray.direction);
+                (
+                    sample_light(ray, &hit, attenuation, world, lights, sky, atmosphere),
+                    power_heuristic(1.0, scattering_pdf, 1.0, light_pdf),
+                )
+            } else {
+                (Colour::new(0, 0, 0), 1.0)
+            };
+            let (incoming, _) = trace(
+                &new_ray,
+                world,
+                lights,
+                sky,
+                atmosphere,
+                bounces - 1,
+                weight,
+            );
+            let colour = emitted + direct + coeff(attenuation, incoming);
+            (through_atmosphere(colour, hit.distance), attenuation)
         } else {
-            emitted
+            (through_atmosphere(emitted, hit.distance), emitted)
         }
     } else {
-        sky(ray)
+        stats::SKY_HITS.increment();
+        let colour = emission_weight * sky.colour(ray);
+        (through_atmosphere(colour, f64::INFINITY), colour)
     }
 }
 
+// The density of sample_light picking this direction from `from`
+fn lights_pdf(lights: &[Arc<dyn Emitter>], sky: &dyn Sky, from: Point3, direction: Vec3) -> f64 {
+    let mut total = lights
+        .iter()
+        .map(|light| light.pdf_towards(from, direction))
+        .sum::<f64>();
+    let mut count = lights.len();
+    if sky.sampled_as_light() {
+        total += sky.pdf(direction);
+        count += 1;
+    }
+    total / count as f64
+}
+
+// The light reaching a diffuse hit straight from one of the lights, or the sky
+// if it's sampled like one, chosen at random. Whatever the shadow ray runs into
+// first is what it sees, so this and the scattered ray agree on how much light
+// comes from each direction.
+fn sample_light(
+    ray: &Ray,
+    hit: &HitRecord,
+    attenuation: Colour,
+    world: &Arc<dyn Hittable>,
+    lights: &[Arc<dyn Emitter>],
+    sky: &dyn Sky,
+    atmosphere: Option<&AtmosphericFog>,
+) -> Colour {
+    let black = Colour::new(0, 0, 0);
+    let choice = random::rng().gen_range(0..lights.len() + sky.sampled_as_light() as usize);
+    let shadow_ray = match lights.get(choice) {
+        Some(light) => light.sample_towards(hit.intersection, ray.time).0,
+        None => {
+            let (direction, _, _) = sky.sample();
+            Ray::new(hit.intersection, direction, ray.time).of_kind(RayType::Shadow)
+        }
+    };
+    let scattering_pdf = hit.material.scattering_pdf(hit, shadow_ray.direction);
+    let light_pdf = lights_pdf(lights, sky, hit.intersection, shadow_ray.direction);
+    // grazing a rectangle gives an infinite density
+    if scattering_pdf <= 0.0 || !light_pdf.is_normal() {
+        return black;
+    }
+    stats::SECONDARY_RAYS.increment();
+    let transmittance = |distance| atmosphere.map_or(1.0, |fog| fog.transmittance(distance));
+    let radiance = match world.hit(&shadow_ray, 0.001, f64::INFINITY) {
+        Some(shadow_hit) => {
+            transmittance(shadow_hit.distance)
+                * shadow_hit.material.emitted(&shadow_ray, &shadow_hit)
+        }
+        None => transmittance(f64::INFINITY) * sky.colour(&shadow_ray),
+    };
+    // for a diffuse material, the BRDF times the cosine is attenuation * scattering_pdf
+    let weight = power_heuristic(1.0, light_pdf, 1.0, scattering_pdf);
+    scattering_pdf * weight / light_pdf * coeff(attenuation, radiance)
+}
+
+// One sample of the ambient occlusion of whatever the ray hits: white if a
+// cosine-weighted ray from it escapes without hitting anything within radius,
+// black if it doesn't. Materials, lights and the sky are ignored.
+pub fn ambient_occlusion(ray: &Ray, world: &Arc<dyn Hittable>, radius: f64) -> Colour {
+    let open = Colour::new(1, 1, 1);
+    let hit = match world.hit(ray, 0.001, f64::INFINITY) {
+        Some(hit) => hit,
+        None => return open,
+    };
+    stats::GEOMETRY_HITS.increment();
+    let (local, _) = random_cosine_direction();
+    let direction = Onb::from_w(hit.normal).local(local.x, local.y, local.z);
+    let probe = Ray::new(hit.intersection, direction, ray.time).of_kind(RayType::Indirect);
+    stats::SECONDARY_RAYS.increment();
+    if world.hit(&probe, 0.001, radius).is_some() {
+        Colour::new(0, 0, 0)
+    } else {
+        open
+    }
+}
+
+// The colour of a black body at this temperature, e.g. 2700K for an
+// incandescent bulb or 6500K for daylight, scaled so its brightest channel is 1.
+// Uses Kim et al.'s fit of the Planckian locus, which covers 1667K to 25000K.
+pub fn colour_from_kelvin(temp_k: f64) -> Colour {
+    let t = temp_k.clamp(1667.0, 25000.0);
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t.powi(3) - 0.2343589e6 / t.powi(2) + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t.powi(3) + 2.1070379e6 / t.powi(2) + 0.2226347e3 / t + 0.240390
+    };
+    let y = if t <= 2222.0 {
+        -1.1063814 * x.powi(3) - 1.34811020 * x.powi(2) + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x.powi(3) - 1.37418593 * x.powi(2) + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x.powi(3) - 5.87338670 * x.powi(2) + 3.75112997 * x - 0.37001483
+    };
+    let rgb = xyz_to_rgb(Vec3::new(x / y, 1.0, (1.0 - x - y) / y));
+    let rgb = rgb.clamp_components(0.0, f64::INFINITY);
+    rgb / rgb.max_component()
+}
+
 pub struct HitRecord {
     pub intersection: Point3,
     pub normal: Vec3,
@@ -79,15 +245,63 @@ impl HitRecord {
     }
 }
 
-pub trait Hittable: Send + Sync {
+pub trait Hittable: fmt::Display + Send + Sync {
     fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord>;
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB>;
-    fn _print(&self) -> String;
+    // a rough lower bound on the memory used, including anything this contains
+    // that isn't already in `seen`
+    fn estimated_memory_bytes(&self, _seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self)
+    }
+    // adds any lights in this that can be sampled directly; lights under a
+    // transform aren't, as they don't know where they've been moved to
+    fn collect_emitters(self: Arc<Self>, _emitters: &mut Vec<Arc<dyn Emitter>>) {}
 }
 
-impl Hittable for Vec<Arc<dyn Hittable>> {
+// The lights in these objects that can be sampled directly
+pub fn find_emitters(objects: &[Arc<dyn Hittable>]) -> Vec<Arc<dyn Emitter>> {
+    let mut emitters = Vec::new();
+    for object in objects {
+        Arc::clone(object).collect_emitters(&mut emitters);
+    }
+    emitters
+}
+
+// Objects can be shared between many instances, so each one is only counted
+// the first time it turns up
+pub fn shared_memory_bytes(object: &Arc<dyn Hittable>, seen: &mut HashSet<*const ()>) -> usize {
+    if seen.insert(Arc::as_ptr(object) as *const ()) {
+        object.estimated_memory_bytes(seen)
+    } else {
+        0
+    }
+}
+
+// Lights that can be sampled directly, for next-event estimation
+pub trait Emitter: Hittable {
+    // A ray from `from` towards a random point on the light, the probability
+    // density (over solid angle) of picking that direction, and the light
+    // arriving along it
+    fn sample_towards(&self, from: Point3, time: f64) -> (Ray, f64, Colour);
+    // The density sample_towards would give the direction, which is 0 if it
+    // misses the light
+    fn pdf_towards(&self, from: Point3, direction: Vec3) -> f64;
+    // The light arriving from the emitter along the ray
+    fn emitted_along(&self, ray: &Ray) -> Colour {
+        match self.hit(ray, 0.001, f64::INFINITY) {
+            Some(hit) => hit.material.emitted(ray, &hit),
+            None => Colour::new(0, 0, 0),
+        }
+    }
+}
+
+// Objects that are all tested against every ray, like the ones a BVH can't hold
+pub struct HittableList(pub Vec<Arc<dyn Hittable>>);
+
+impl Hittable for HittableList {
     fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
-        self.iter()
+        self.0
+            .iter()
             .map(|x| x.hit(ray, min_dist, max_dist))
             .fold(None, |acc, next| match (acc, next) {
                 (Some(old), Some(new)) => {
@@ -103,16 +317,12 @@ impl Hittable for Vec<Arc<dyn Hittable>> {
             })
     }
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
-        if self.len() == 0 {
+        if self.0.is_empty() {
             return None;
         }
-        let mut working_box = if let Some(b) = self[0].bounding_box(time0, time1) {
-            b
-        } else {
-            return None;
-        };
+        let mut working_box = self.0[0].bounding_box(time0, time1)?;
 
-        for obj in self {
+        for obj in &self.0 {
             if let Some(new_box) = obj.bounding_box(time0, time1) {
                 working_box = surrounding_box(&new_box, &working_box);
             } else {
@@ -121,21 +331,139 @@ impl Hittable for Vec<Arc<dyn Hittable>> {
         }
         Some(working_box)
     }
-    fn _print(&self) -> String {
-        let mut acc = String::from("vec: [");
-        for h in self {
-            acc += &(h._print() + ", ");
-        }
-        acc + "]"
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self)
+            + self.0.capacity() * mem::size_of::<Arc<dyn Hittable>>()
+            + self
+                .0
+                .iter()
+                .map(|x| shared_memory_bytes(x, seen))
+                .sum::<usize>()
+    }
+    fn collect_emitters(self: Arc<Self>, emitters: &mut Vec<Arc<dyn Emitter>>) {
+        emitters.extend(find_emitters(&self.0));
+    }
+}
+impl fmt::Display for HittableList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let objects: Vec<String> = self.0.iter().map(|h| h.to_string()).collect();
+        write!(f, "list: [{}]", objects.join(", "))
     }
 }
 
-pub trait Material: Send + Sync {
+pub trait Material: fmt::Display + Send + Sync {
     fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Ray, Colour)>;
-    fn emitted(&self, _hit: &HitRecord) -> Colour {
+    fn emitted(&self, _ray: &Ray, _hit: &HitRecord) -> Colour {
+        Colour::new(0, 0, 0)
+    }
+    // The probability density (over solid angle) of scatter picking this
+    // direction, for weighing it against light sampling; 0 for materials that
+    // don't scatter diffusely, which don't sample lights
+    fn scattering_pdf(&self, _hit: &HitRecord, _direction: Vec3) -> f64 {
+        0.0
+    }
+    // roughly how much light it gives off, to tell lights worth sampling
+    // from everything else
+    fn average_emission(&self) -> Colour {
         Colour::new(0, 0, 0)
     }
-    fn _print(&self) -> String;
+}
+
+// Hides an object from some kinds of rays. Light reaches diffuse surfaces along
+// shadow rays towards lights and along indirect rays, so both count as shadow
+// rays here.
+pub struct VisibilityFlags {
+    original: Arc<dyn Hittable>,
+    // seen by the camera
+    visible: bool,
+    // blocks light, and shows up in reflections
+    casts_shadow: bool,
+}
+
+impl VisibilityFlags {
+    pub fn new(
+        original: &Arc<dyn Hittable>,
+        visible: bool,
+        casts_shadow: bool,
+    ) -> Arc<dyn Hittable> {
+        Arc::new(VisibilityFlags {
+            original: Arc::clone(original),
+            visible,
+            casts_shadow,
+        })
+    }
+}
+
+impl Hittable for VisibilityFlags {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        let shown = match ray.kind {
+            RayType::Primary => self.visible,
+            RayType::Indirect | RayType::Shadow => self.casts_shadow,
+        };
+        if shown {
+            self.original.hit(ray, min_dist, max_dist)
+        } else {
+            None
+        }
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        self.original.bounding_box(time0, time1)
+    }
+    // a hidden light still lights things up
+    fn collect_emitters(self: Arc<Self>, emitters: &mut Vec<Arc<dyn Emitter>>) {
+        Arc::clone(&self.original).collect_emitters(emitters);
+    }
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + shared_memory_bytes(&self.original, seen)
+    }
+}
+
+impl fmt::Display for VisibilityFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "visibility flags (visible: {}, casts shadow: {}) {}",
+            self.visible, self.casts_shadow, self.original
+        )
+    }
+}
+
+// Shows an object with a different material, e.g. to tone it down while debugging
+pub struct MaterialOverride {
+    original: Arc<dyn Hittable>,
+    material: Arc<dyn Material>,
+}
+
+impl MaterialOverride {
+    pub fn new(original: &Arc<dyn Hittable>, material: &Arc<dyn Material>) -> Arc<dyn Hittable> {
+        Arc::new(MaterialOverride {
+            original: Arc::clone(original),
+            material: Arc::clone(material),
+        })
+    }
+}
+
+impl Hittable for MaterialOverride {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        self.original
+            .hit(ray, min_dist, max_dist)
+            .map(|hit| HitRecord {
+                material: Arc::clone(&self.material),
+                ..hit
+            })
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        self.original.bounding_box(time0, time1)
+    }
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + shared_memory_bytes(&self.original, seen)
+    }
+}
+
+impl fmt::Display for MaterialOverride {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "material override ({}) {}", self.material, self.original)
+    }
 }
 
 pub struct BVHNode {
@@ -145,33 +473,32 @@ pub struct BVHNode {
 }
 
 impl BVHNode {
-    pub fn from_vec(objects: Vec<Arc<dyn Hittable>>, time0: f64, time1: f64) -> Arc<dyn Hittable> {
-        let mut no_bbox: Vec<Arc<dyn Hittable>> = objects
-            .iter()
-            .filter(|x| x.bounding_box(time0, time1).is_none())
-            .map(|x| Arc::clone(x))
-            .collect();
-        let mut objects: Vec<Arc<dyn Hittable>> = objects
+    pub fn from_vec(
+        objects: Vec<Arc<dyn Hittable>>,
+        time0: f64,
+        time1: f64,
+    ) -> Result<Arc<dyn Hittable>> {
+        if objects.is_empty() {
+            bail!("Can't build a BVH out of no objects");
+        }
+        // unbounded objects like infinite planes can't go in the tree, so
+        // they're kept in a list beside it
+        let (mut objects, mut no_bbox): (Vec<_>, Vec<_>) = objects
             .into_iter()
-            .filter(|x| x.bounding_box(time0, time1).is_some())
-            .collect();
-        let axis = rand::thread_rng().gen_range(0..3);
-        objects.sort_by(|a, b| bbox_compare(a, b, axis));
+            .partition(|x| x.bounding_box(time0, time1).is_some());
+        let axis = random::rng().gen_range(0..3);
+        objects.sort_by(|a, b| bbox_compare(a, b, axis, time0, time1));
         if objects.len() == 0 {
-            if no_bbox.len() > 0 {
-                return Arc::new(no_bbox);
-            } else {
-                panic!("BVHNode cannot be created from empty slice");
-            }
+            Ok(Arc::new(HittableList(no_bbox)))
         } else if objects.len() == 1 {
             no_bbox.push(objects.pop().unwrap());
-            return Arc::new(no_bbox);
+            Ok(Arc::new(HittableList(no_bbox)))
         } else {
             let halfway = objects.len() / 2;
             let right_objects = objects.split_off(halfway);
             let left_objects = objects;
-            let left = Self::from_vec(left_objects, time0, time1);
-            let right = Self::from_vec(right_objects, time0, time1);
+            let left = Self::from_vec(left_objects, time0, time1)?;
+            let right = Self::from_vec(right_objects, time0, time1)?;
             let left_bbox = left
                 .bounding_box(time0, time1)
                 .expect("BHVNode unable to find bbox of subtree");
@@ -186,33 +513,54 @@ impl BVHNode {
             });
 
             if no_bbox.len() == 0 {
-                bvh_result
+                Ok(bvh_result)
             } else {
                 let mut result: Vec<Arc<dyn Hittable>> = vec![bvh_result];
                 result.append(&mut no_bbox);
-                Arc::new(result)
+                Ok(Arc::new(HittableList(result)))
             }
         }
     }
 }
 
-fn bbox_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>, axis: usize) -> Ordering {
-    a.bounding_box(0.0, 0.0)
+fn bbox_compare(
+    a: &Arc<dyn Hittable>,
+    b: &Arc<dyn Hittable>,
+    axis: usize,
+    time0: f64,
+    time1: f64,
+) -> Ordering {
+    a.bounding_box(time0, time1)
         .expect("Unable to find bbox to compare")
         .minimum[axis]
         .partial_cmp(
-            &b.bounding_box(0.0, 0.0)
+            &b.bounding_box(time0, time1)
                 .expect("Unable to find bbox to compare")
                 .minimum[axis],
         )
         .expect("Bounding boxes were incomparable")
 }
 
+thread_local! {
+    // BVH nodes this thread has visited, for count_bvh_nodes
+    static NODES_VISITED: Cell<u32> = const { Cell::new(0) };
+}
+
+// Runs f, also returning how many BVH nodes it visited on this thread
+pub fn count_bvh_nodes<T>(f: impl FnOnce() -> T) -> (T, u32) {
+    let before = NODES_VISITED.with(Cell::get);
+    let result = f();
+    (result, NODES_VISITED.with(Cell::get).wrapping_sub(before))
+}
+
 impl Hittable for BVHNode {
     fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        stats::BVH_NODES_VISITED.increment();
+        NODES_VISITED.with(|n| n.set(n.get().wrapping_add(1)));
         if !self.bbox.intersects(ray, min_dist, max_dist) {
             None
         } else {
+            stats::BVH_NODES_HIT.increment();
             if let Some(hit_left) = self.left.hit(ray, min_dist, max_dist) {
                 if let result_right @ Some(_) =
                     self.right
@@ -230,12 +578,20 @@ impl Hittable for BVHNode {
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
         Some(self.bbox)
     }
-    fn _print(&self) -> String {
-        format!(
-            "bvhNode: ({}), ({})",
-            self.left._print(),
-            self.right._print()
-        )
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self)
+            + shared_memory_bytes(&self.left, seen)
+            + shared_memory_bytes(&self.right, seen)
+    }
+    fn collect_emitters(self: Arc<Self>, emitters: &mut Vec<Arc<dyn Emitter>>) {
+        Arc::clone(&self.left).collect_emitters(emitters);
+        Arc::clone(&self.right).collect_emitters(emitters);
+    }
+}
+
+impl fmt::Display for BVHNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bvhNode: ({}), ({})", self.left, self.right)
     }
 }
 
@@ -247,8 +603,12 @@ pub struct AABB {
 }
 
 impl AABB {
+    // An inside-out box, with its minimum past its maximum, is empty and never hit
     pub fn intersects(&self, ray: &Ray, mut min_dist: f64, mut max_dist: f64) -> bool {
         for a in 0..3 {
+            if self.minimum[a] > self.maximum[a] {
+                return false;
+            }
             let t0 = f64::min(
                 (self.minimum[a] - ray.origin[a]) / ray.direction[a],
                 (self.maximum[a] - ray.origin[a]) / ray.direction[a],
@@ -265,6 +625,23 @@ impl AABB {
         }
         true
     }
+    // The stretch of the ray between min_dist and max_dist that's inside the
+    // box, if any
+    pub fn span(&self, ray: &Ray, mut min_dist: f64, mut max_dist: f64) -> Option<(f64, f64)> {
+        for a in 0..3 {
+            if self.minimum[a] > self.maximum[a] {
+                return None;
+            }
+            let ta = (self.minimum[a] - ray.origin[a]) / ray.direction[a];
+            let tb = (self.maximum[a] - ray.origin[a]) / ray.direction[a];
+            min_dist = min_dist.max(ta.min(tb));
+            max_dist = max_dist.min(ta.max(tb));
+            if max_dist <= min_dist {
+                return None;
+            }
+        }
+        Some((min_dist, max_dist))
+    }
 }
 
 pub fn surrounding_box(box0: &AABB, box1: &AABB) -> AABB {
@@ -280,3 +657,159 @@ pub fn surrounding_box(box0: &AABB, box1: &AABB) -> AABB {
     };
     AABB { minimum, maximum }
 }
+
+#[test]
+fn colour_from_kelvin_test() {
+    let candle = colour_from_kelvin(1900.0);
+    assert!(candle.x == 1.0 && candle.x > candle.y && candle.y > candle.z);
+    let daylight = colour_from_kelvin(6500.0);
+    assert!(daylight.x > 0.9 && daylight.y > 0.9 && daylight.z > 0.9);
+    let sky = colour_from_kelvin(15000.0);
+    assert!(sky.z == 1.0 && sky.x < sky.z);
+}
+
+#[test]
+fn estimated_memory_test() {
+    use crate::materials::Lambertian;
+    use crate::objects::Sphere;
+    use crate::transforms::Translate;
+    let material = Lambertian::with_colour(Colour::new(0.5, 0.5, 0.5));
+    let spheres: Vec<Arc<dyn Hittable>> = (0..10)
+        .map(|i| Sphere::new(Point3::new(i, 0, 0), 0.5, &material))
+        .collect();
+    let sphere_bytes = shared_memory_bytes(&spheres[0], &mut HashSet::new());
+    assert!(sphere_bytes >= mem::size_of::<Point3>());
+    let list = HittableList(spheres.clone());
+    assert!(list.estimated_memory_bytes(&mut HashSet::new()) > 10 * sphere_bytes);
+    let bvh = BVHNode::from_vec(spheres.clone(), 0.0, 1.0).unwrap();
+    assert!(shared_memory_bytes(&bvh, &mut HashSet::new()) > 10 * sphere_bytes);
+
+    // a prototype placed many times is only counted once
+    let instances = HittableList(
+        (0..10)
+            .map(|i| Translate::translate(&spheres[0], Vec3::new(0, i, 0)))
+            .collect(),
+    );
+    let translate_bytes = shared_memory_bytes(&instances.0[0], &mut HashSet::new()) - sphere_bytes;
+    assert_eq!(
+        instances.estimated_memory_bytes(&mut HashSet::new()),
+        mem::size_of::<HittableList>()
+            + instances.0.capacity() * mem::size_of::<Arc<dyn Hittable>>()
+            + 10 * translate_bytes
+            + sphere_bytes
+    );
+}
+
+#[test]
+fn light_sampling_test() {
+    use crate::camera::{HdriSky, SolidSky};
+    use crate::materials::{DiffuseLight, Lambertian};
+    use crate::objects::{Sphere, XZRect};
+    use crate::transforms::Translate;
+    let floor = XZRect::new(
+        -100,
+        100,
+        -100,
+        100,
+        0,
+        &Lambertian::with_colour(Colour::new(0.5, 0.5, 0.5)),
+        true,
+    );
+    let light = DiffuseLight::with_colour(Colour::new(1, 1, 1), 1.0, false);
+    let bulb = Sphere::new(Point3::new(0, 2, 0), 0.5, &light);
+    let moved = Translate::translate(&bulb, Vec3::new(5, 0, 0));
+    let objects = vec![floor, Arc::clone(&bulb), moved];
+    // lights under a transform are left to be found by scattering
+    let lights = find_emitters(&objects);
+    assert_eq!(lights.len(), 1);
+    let world = BVHNode::from_vec(objects, 0.0, 1.0).unwrap();
+    let sky = SolidSky {
+        colour: Colour::new(0, 0, 0),
+    };
+
+    // both ways of finding the light should see the same amount of it
+    let ray = Ray::new(Point3::new(0.5, 1, 0), Vec3::new(0, -1, 0), 0.0);
+    let samples = 40000;
+    let mean = |lights: &[Arc<dyn Emitter>]| {
+        (0..samples)
+            .map(|_| {
+                cast_ray(&ray, RayType::Primary, &world, lights, &sky, None, 3)
+                    .0
+                    .x
+            })
+            .sum::<f64>()
+            / samples as f64
+    };
+    // a sphere of radius r, d away, shines like a point light in its centre
+    let shine = |d: Vec3| 0.5 * 0.25 * d.y / d.length() / d.length_squared();
+    let expected = shine(Vec3::new(-0.5, 2, 0)) + shine(Vec3::new(4.5, 2, 0));
+    let sampled = mean(&lights);
+    let scattered = mean(&[]);
+    assert!((sampled - expected).abs() < 0.03 * expected);
+    assert!((scattered - expected).abs() < 0.1 * expected);
+
+    // the same goes for a sky sampled like a light, with a bright patch
+    struct Unsampled<'a>(&'a dyn Sky);
+    impl Sky for Unsampled<'_> {
+        fn colour(&self, ray: &Ray) -> Colour {
+            self.0.colour(ray)
+        }
+    }
+    let pixels = (0..64)
+        .map(|k| Colour::new(1, 1, 1) * if k == 21 { 50.0 } else { 0.5 })
+        .collect::<Vec<_>>();
+    let hdri = HdriSky::new(8, 8, pixels, 30.0, 1.0);
+    let mean = |sky: &dyn Sky| {
+        (0..samples)
+            .map(|_| {
+                cast_ray(&ray, RayType::Primary, &world, &[], sky, None, 3)
+                    .0
+                    .x
+            })
+            .sum::<f64>()
+            / samples as f64
+    };
+    let sampled = mean(&hdri);
+    let scattered = mean(&Unsampled(&hdri));
+    assert!((sampled - scattered).abs() < 0.1 * sampled);
+}
+
+#[test]
+fn ambient_occlusion_test() {
+    use crate::materials::Lambertian;
+    use crate::objects::Block;
+    let material = Lambertian::with_colour(Colour::new(0.5, 0.5, 0.5));
+    let room = Block::new(Point3::new(-1, -1, -1), Point3::new(1, 1, 1), &material);
+    let ray = Ray::new(Point3::new(0, 0, 0), Vec3::new(0, 0, -1), 0.0);
+    for _ in 0..20 {
+        // from inside a closed box, every direction is blocked
+        assert_eq!(ambient_occlusion(&ray, &room, f64::INFINITY).x, 0.0);
+        // unless the other walls are out of reach
+        assert_eq!(ambient_occlusion(&ray, &room, 0.5).x, 1.0);
+    }
+    let outward = Ray::new(Point3::new(0, 0, 0), Vec3::new(0, 0, 1), 0.0);
+    let nothing: Arc<dyn Hittable> = Arc::new(HittableList(Vec::new()));
+    assert_eq!(ambient_occlusion(&outward, &nothing, 1.0).x, 1.0);
+}
+
+#[test]
+fn count_bvh_nodes_test() {
+    use crate::materials::Lambertian;
+    use crate::objects::Sphere;
+    let material = Lambertian::with_colour(Colour::new(0.5, 0.5, 0.5));
+    let spheres: Vec<Arc<dyn Hittable>> = (0..8)
+        .map(|i| Sphere::new(Point3::new(3 * i, 0, 0), 1.0, &material))
+        .collect();
+    let bvh = BVHNode::from_vec(spheres, 0.0, 1.0).unwrap();
+    // a miss is turned away at the root
+    let away = Ray::new(Point3::new(0, 5, 0), Vec3::new(0, 1, 0), 0.0);
+    assert_eq!(
+        count_bvh_nodes(|| bvh.hit(&away, 0.001, f64::INFINITY)).1,
+        1
+    );
+    // a hit goes down to at least one leaf
+    let down = Ray::new(Point3::new(0, 5, 0), Vec3::new(0, -1, 0), 0.0);
+    let (hit, nodes) = count_bvh_nodes(|| bvh.hit(&down, 0.001, f64::INFINITY));
+    assert!(hit.is_some());
+    assert!(nodes >= 3);
+}
@@ -0,0 +1,18 @@
+pub mod atmosphere;
+// for embedding the renderer; not used by the command line tool
+pub mod builder;
+pub mod camera;
+pub mod colour_space;
+pub mod config;
+pub mod csg;
+pub mod hitting;
+pub mod materials;
+pub mod math;
+pub mod objects;
+pub mod part_file;
+pub mod progress;
+pub mod random;
+pub mod sky;
+pub mod stats;
+pub mod textures;
+pub mod transforms;
@@ -1,33 +1,31 @@
-use anyhow::{Context, Result};
-use image::{ImageBuffer, RgbImage};
+use anyhow::{anyhow, bail, Context, Result};
+use image::{GrayImage, ImageBuffer, ImageResult, RgbImage};
+use notify::{RecursiveMode, Watcher};
 use rand::Rng;
 use rayon::prelude::*;
 use structopt::StructOpt;
 use terminal_size::{terminal_size, Height, Width};
 
-use std::fs::remove_file;
+use std::collections::HashSet;
+use std::fs::{self, remove_file};
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Instant;
-
-mod camera;
-mod config;
-mod hitting;
-mod materials;
-mod math;
-mod objects;
-mod part_file;
-mod progress;
-mod textures;
-mod transforms;
-
-use config::load_config;
-use hitting::{cast_ray, Colour};
-use math::{clamp, Vec3};
-use part_file::PartFile;
-use progress::{Progress, TimedProgressBar};
+use std::time::{Duration, Instant};
+
+use raytracer::colour_space::{linear_to_srgb, srgb_to_linear};
+use raytracer::config::{included_files, list_views, load_config, validate_config, Isolate};
+use raytracer::hitting::{
+    ambient_occlusion, cast_ray, count_bvh_nodes, shared_memory_bytes, Colour,
+};
+use raytracer::math::{clamp, RayType, Vec3};
+use raytracer::part_file::PartFile;
+use raytracer::progress::{Progress, TimedProgressBar};
+use raytracer::{random, stats};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "raytracer", about = "Raytracing in a weekend!")]
@@ -36,14 +34,20 @@ struct Opt {
     #[structopt(parse(from_os_str))]
     input_file: PathBuf,
     /// Output file
-    #[structopt(parse(from_os_str))]
-    output_file: PathBuf,
+    #[structopt(parse(from_os_str), required_unless_one = &["validate", "bvh-depth-vis", "list-views"])]
+    output_file: Option<PathBuf>,
     /// Output image width
     #[structopt(short, long, default_value = "600")]
     width: u32,
-    /// Rays per pixel
-    #[structopt(short = "s", long, default_value = "100")]
-    ray_samples: u32,
+    /// Rays per pixel (sets both --min-samples and --max-samples) [default: 100]
+    #[structopt(short = "s", long, conflicts_with_all = &["min-samples", "max-samples"])]
+    ray_samples: Option<u32>,
+    /// Fewest rays per pixel; noisy pixels get more, up to --max-samples
+    #[structopt(long)]
+    min_samples: Option<u32>,
+    /// Most rays for any pixel [default: 100]
+    #[structopt(long)]
+    max_samples: Option<u32>,
     /// Maximum number of bounces for any ray
     #[structopt(short, long, default_value = "50")]
     max_bounces: u32,
@@ -53,8 +57,8 @@ struct Opt {
     /// Do not use non-ASCII symbols
     #[structopt(long)]
     ascii_symbols_only: bool,
-    /// Recover from part file
-    #[structopt(short, long)]
+    /// Resume rendering from a part file, continuing to write to it
+    #[structopt(short, long, visible_alias = "resume", conflicts_with = "frames")]
     recover_from: Option<PathBuf>,
     /// Try to read as much of a corrupted part file as possible
     #[structopt(long)]
@@ -68,32 +72,390 @@ struct Opt {
     /// Don't save partial progress in a part file in case of a crash
     #[structopt(long)]
     no_part_file: bool,
+    /// Also save the unlit colour of the first surface hit, e.g. for denoising
+    #[structopt(long, parse(from_os_str))]
+    output_albedo: Option<PathBuf>,
+    /// Also save how many rays each pixel took, as a grayscale image out of --max-samples
+    #[structopt(long, parse(from_os_str))]
+    output_sample_count: Option<PathBuf>,
+    /// Override a config field, e.g. --set camera.fieldOfView=40 (repeatable)
+    #[structopt(long = "set", number_of_values = 1, parse(try_from_str = parse_override))]
+    overrides: Vec<(String, String)>,
+    /// Print statistics about the render once it's done (needs the stats feature)
+    #[structopt(long)]
+    stats: bool,
+    /// Number of threads to render with (0 uses all CPUs)
+    #[structopt(long, default_value = "0")]
+    threads: usize,
+    /// Seed the random number generator, making the render reproducible
+    #[structopt(long)]
+    seed: Option<u64>,
+    /// Gamma to encode the image with, overriding the scene's [default: 2.0]
+    #[structopt(long)]
+    gamma: Option<f64>,
+    /// Encode the image with the sRGB transfer curve instead of a plain gamma
+    #[structopt(long, conflicts_with = "gamma")]
+    srgb: bool,
+    /// Render these frames of an animation, e.g. 1-48, numbering each output file
+    #[structopt(long, parse(try_from_str = parse_frames))]
+    frames: Option<(u32, u32)>,
+    /// Frames per second of the animation; frame n is at n / fps seconds
+    #[structopt(long, default_value = "24")]
+    fps: f64,
+    /// Check the config and describe the scene, without rendering anything
+    #[structopt(long)]
+    validate: bool,
+    /// Only render pixels from x0,y0 up to but not including x1,y1, leaving the rest black
+    #[structopt(long, value_name = "x0,y0,x1,y1", parse(try_from_str = parse_crop))]
+    crop: Option<Crop>,
+    /// Stop after this many seconds of rendering and save what's done, leaving the rest black
+    #[structopt(long, parse(try_from_str = parse_seconds))]
+    time_limit: Option<Duration>,
+    /// Print more about the scene before rendering it
+    #[structopt(short, long)]
+    verbose: bool,
+    /// Warn before rendering if the scene looks like it needs more memory than this
+    #[structopt(long)]
+    max_memory_gb: Option<f64>,
+    /// Render ambient occlusion instead of lighting: white where surfaces are open, black where occluded
+    #[structopt(long)]
+    ao_mode: bool,
+    /// How far away geometry can be and still occlude, in ambient occlusion mode [default: unlimited]
+    #[structopt(long, requires = "ao-mode")]
+    ao_radius: Option<f64>,
+    /// Instead of rendering, save how many BVH nodes each pixel's ray visits, from blue (few) to red (many)
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["output-file", "frames"])]
+    bvh_depth_vis: Option<PathBuf>,
+    /// Only show these objects from the world, e.g. --isolate table,lamp; the rest turn matte black
+    #[structopt(long, use_delimiter = true)]
+    isolate: Vec<String>,
+    /// Leave out the objects that aren't isolated, rather than blacking them out
+    #[structopt(long, requires = "isolate")]
+    isolate_remove: bool,
+    /// Save the image so far as <output>.checkpoint.png after every n rows, with unrendered rows black
+    #[structopt(long, value_name = "n")]
+    checkpoint_every: Option<u32>,
+    /// Keep running, rendering again whenever the config file or anything it includes changes
+    #[structopt(long, conflicts_with_all = &["frames", "validate", "bvh-depth-vis", "recover-from"])]
+    watch: bool,
+    /// Image width to use while watching, for quicker previews [default: --width]
+    #[structopt(long, requires = "watch")]
+    preview_width: Option<u32>,
+    /// Make pixels brighter than this glow, where 1.0 is as bright as the image can show
+    #[structopt(long)]
+    bloom_threshold: Option<f64>,
+    /// How far the glow from --bloom-threshold spreads, in pixels [default: 10]
+    #[structopt(long, requires = "bloom-threshold")]
+    bloom_radius: Option<f64>,
+    /// Look through one of the config's namedViews instead of its camera
+    #[structopt(long)]
+    view: Option<String>,
+    /// Print the config's namedViews and exit
+    #[structopt(long)]
+    list_views: bool,
+    /// Add film grain: noise with this standard deviation, out of 255, the same each render
+    #[structopt(long)]
+    grain: Option<f64>,
+}
+
+impl Opt {
+    fn isolate(&self) -> Option<Isolate> {
+        if self.isolate.is_empty() {
+            None
+        } else {
+            Some(Isolate {
+                names: self.isolate.clone(),
+                remove_others: self.isolate_remove,
+            })
+        }
+    }
+}
+
+// A rectangle of the image, in pixels from the top left
+#[derive(Clone, Copy, Debug)]
+struct Crop {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+}
+
+impl Crop {
+    fn contains(self, x: u32, y: u32) -> bool {
+        (self.x0..self.x1).contains(&x) && (self.y0..self.y1).contains(&y)
+    }
 }
 
 fn main() -> Result<()> {
     // cli args
     let opt = Opt::from_args();
 
+    // without the counters the report would quietly leave out the ray counts
+    if opt.stats && !cfg!(feature = "stats") {
+        bail!("--stats needs the ray and BVH counters; rebuild with --features stats");
+    }
+    if opt.threads > 1024 {
+        bail!(
+            "Can't use more than 1024 threads, but {} were asked for",
+            opt.threads
+        );
+    }
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(opt.threads)
+        .build_global()
+        .context("Setting up render threads")?;
+
+    if opt.validate {
+        let time = opt.frames.map_or(0.0, |(first, _)| first as f64 / opt.fps);
+        let validation = validate_config(&opt.input_file, &opt.overrides, time)?;
+        print!("{}", validation.report);
+        // errors have already exited with 1; warnings alone get their own code
+        if validation.warnings > 0 {
+            io::stdout().flush()?;
+            process::exit(2);
+        }
+        return Ok(());
+    }
+    if opt.list_views {
+        print!("{}", list_views(&opt.input_file)?);
+        return Ok(());
+    }
+    if let Some(vis_file) = &opt.bvh_depth_vis {
+        return render_bvh_depth(&opt, vis_file);
+    }
+    let outputs = Outputs {
+        // structopt requires an output file unless validating or visualising
+        image: opt.output_file.clone().unwrap(),
+        albedo: opt.output_albedo.clone(),
+        sample_count: opt.output_sample_count.clone(),
+    };
+
+    if opt.watch {
+        return watch(&opt, &outputs);
+    }
+
+    match opt.frames {
+        None => render(&opt, &outputs, 0.0, None),
+        Some((first, last)) => {
+            if opt.fps <= 0.0 {
+                bail!("Frames per second must be positive, not {}", opt.fps);
+            }
+            // each frame is rendered on its own, so frames can be split up
+            // between machines
+            for frame in first..=last {
+                if !opt.quiet {
+                    eprintln!("Frame {} of {}-{}", frame, first, last);
+                }
+                render(
+                    &opt,
+                    &outputs.for_frame(frame),
+                    frame as f64 / opt.fps,
+                    None,
+                )?;
+            }
+            Ok(())
+        }
+    }
+}
+
+// The rendered image, and the other images that can be saved alongside it
+struct Outputs {
+    image: PathBuf,
+    albedo: Option<PathBuf>,
+    sample_count: Option<PathBuf>,
+}
+
+impl Outputs {
+    fn for_frame(&self, frame: u32) -> Outputs {
+        Outputs {
+            image: frame_file(&self.image, frame),
+            albedo: self.albedo.as_ref().map(|f| frame_file(f, frame)),
+            sample_count: self.sample_count.as_ref().map(|f| frame_file(f, frame)),
+        }
+    }
+}
+
+// Renders the config file again each time it's saved, until interrupted
+fn watch(opt: &Opt, outputs: &Outputs) -> Result<()> {
+    let config_path = opt
+        .input_file
+        .canonicalize()
+        .with_context(|| format!("Finding config file {}", opt.input_file.display()))?;
+    // the config and everything it includes
+    let files = Arc::new(Mutex::new(vec![config_path.clone()]));
+    let changed = Arc::new(AtomicBool::new(false));
+    let (change_sender, change_receiver) = mpsc::channel();
+    let mut watcher = {
+        let (files, changed) = (files.clone(), changed.clone());
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event {
+                let files = files.lock().unwrap();
+                if !event.kind.is_access() && event.paths.iter().any(|p| files.contains(p)) {
+                    changed.store(true, Ordering::Relaxed);
+                    let _ = change_sender.send(());
+                }
+            }
+        })?
+    };
+    // editors often save by replacing the file, which a watch on the file
+    // itself wouldn't survive, so directories are watched instead
+    let mut directories = Vec::new();
+    // Ctrl+C waits for any image being saved, so it never leaves a temporary
+    // file beside the output
+    ctrlc::set_handler(|| {
+        let _saving = SAVING.lock();
+        process::exit(130);
+    })?;
+
+    loop {
+        // includes can come and go as the config is edited, but a broken
+        // config keeps watching what it had before
+        if let Ok(included) = included_files(&config_path) {
+            let mut files = files.lock().unwrap();
+            files.truncate(1);
+            files.extend(included);
+        }
+        // not holding the lock, which the watcher's events need
+        let watching = files.lock().unwrap().clone();
+        for file in &watching {
+            let directory = file.parent().unwrap().to_path_buf();
+            if !directories.contains(&directory) {
+                watcher
+                    .watch(&directory, RecursiveMode::NonRecursive)
+                    .with_context(|| format!("Watching {}", directory.display()))?;
+                directories.push(directory);
+            }
+        }
+        changed.store(false, Ordering::Relaxed);
+        // a half-edited config shouldn't end the session
+        if let Err(e) = render(opt, outputs, 0.0, Some(&changed)) {
+            eprintln!("Error: {:?}", e);
+        }
+        if !changed.load(Ordering::Relaxed) {
+            if !opt.quiet {
+                eprintln!("Watching {} for changes", opt.input_file.display());
+            }
+            change_receiver.recv()?;
+        }
+        // one save can come as several events, so let them all arrive first
+        thread::sleep(Duration::from_millis(100));
+        while change_receiver.try_recv().is_ok() {}
+        if !opt.quiet {
+            eprintln!("Config changed, rendering again");
+        }
+    }
+}
+
+// Renders the scene as it is at a time in the animation, in seconds. Setting
+// cancel abandons the render without saving anything.
+fn render(opt: &Opt, outputs: &Outputs, time: f64, cancel: Option<&AtomicBool>) -> Result<()> {
+    let output_file = &outputs.image;
     // Camera & World
-    let (camera, world, sky, aspect_ratio) = load_config(&opt.input_file)?;
+    let setup_start = Instant::now();
+    if let Some(seed) = opt.seed {
+        // scene setup isn't entirely deterministic either, e.g. BVH construction
+        random::reseed(seed, 0);
+    }
+    let (camera, world, lights, sky, atmosphere, aspect_ratio) = load_config(
+        &opt.input_file,
+        &opt.overrides,
+        time,
+        opt.isolate().as_ref(),
+        opt.view.as_deref(),
+    )?;
+    let memory_gb = shared_memory_bytes(&world, &mut HashSet::new()) as f64 / 1e9;
+    if opt.verbose {
+        eprintln!("Estimated scene memory: {:.1} MB", memory_gb * 1e3);
+    }
+    if let Some(max_memory_gb) = opt.max_memory_gb.filter(|&max| memory_gb > max) {
+        eprintln!(
+            "Warning: the scene needs about {:.2} GB, more than the limit of {} GB",
+            memory_gb, max_memory_gb
+        );
+    }
 
     // Image
-    let image_width = opt.width;
+    let image_width = opt.preview_width.unwrap_or(opt.width);
     let image_height = (image_width as f64 / aspect_ratio).round() as u32;
+    if let Some(crop) = opt.crop {
+        if crop.x1 > image_width || crop.y1 > image_height {
+            bail!(
+                "Crop goes to {},{}, outside the {}x{} image",
+                crop.x1,
+                crop.y1,
+                image_width,
+                image_height
+            );
+        }
+    }
 
     let render_start = Instant::now();
 
-    let samples_per_pixel = opt.ray_samples;
+    let (min_samples, max_samples) = if let Some(n) = opt.ray_samples {
+        (n, n)
+    } else {
+        let max_samples = opt.max_samples.unwrap_or(DEFAULT_SAMPLES);
+        (opt.min_samples.unwrap_or(max_samples), max_samples)
+    };
+    if min_samples == 0 || min_samples > max_samples {
+        bail!(
+            "Need 0 < min samples <= max samples, but min is {} and max is {}",
+            min_samples,
+            max_samples
+        );
+    }
+    if opt.checkpoint_every == Some(0) {
+        bail!("Can't checkpoint every 0 rows");
+    }
     let max_bounces = opt.max_bounces;
+    let ao_radius = opt.ao_radius.unwrap_or(f64::INFINITY);
+    if ao_radius.is_nan() || ao_radius <= 0.0 {
+        bail!(
+            "Ambient occlusion radius must be positive, not {}",
+            ao_radius
+        );
+    }
+    let bloom_radius = opt.bloom_radius.unwrap_or(10.0);
+    if bloom_radius.is_nan() || bloom_radius <= 0.0 {
+        bail!("Bloom radius must be positive, not {}", bloom_radius);
+    }
+    let grain = match opt.grain {
+        Some(sigma) if sigma.is_nan() || sigma < 0.0 => {
+            bail!("Grain can't have a standard deviation of {}", sigma)
+        }
+        Some(sigma) => Some(Grain {
+            sigma,
+            seed: opt.seed.unwrap_or(0),
+        }),
+        None => None,
+    };
+    let noise_at = |x: u32, y: u32| grain.map_or(0.0, |g| g.noise_at(x, y));
+    // bloom needs the colours from before they're clamped for the image
+    let keep_hdr = opt.bloom_threshold.is_some();
+    let curve = if opt.srgb {
+        TransferCurve::Srgb
+    } else {
+        let gamma = opt.gamma.or_else(|| camera.gamma()).unwrap_or(2.0);
+        if gamma <= 0.0 {
+            bail!("Gamma must be positive, not {}", gamma);
+        }
+        TransferCurve::Gamma(gamma)
+    };
 
     // Channels to communicate progress
     let (progress_sender, progress_receiver): (Sender<(u32, Vec<u8>)>, Receiver<(u32, Vec<u8>)>) =
         mpsc::channel();
     let (done_sender, done_receiver): (Sender<Result<()>>, Receiver<Result<()>>) = mpsc::channel();
 
+    let base = if let Some(part_file) = &opt.recover_from {
+        PartFile::read(part_file, image_height, image_width, opt.recover_corrupt)?
+    } else {
+        vec![None; image_height as usize]
+    };
+
     // Start a separate thread to run the progress bar and manage the part file
     let progress_info = ProgressInfo {
-        output_file_name: opt.output_file.clone(),
+        output_file_name: output_file.to_path_buf(),
         image_width,
         image_height,
         ascii_symbols_only: opt.ascii_symbols_only,
@@ -101,66 +463,193 @@ fn main() -> Result<()> {
         progress_bar_samples: opt.progress_bar_samples,
         progress_receiver,
         quiet: opt.quiet,
-        no_part_file: opt.no_part_file,
+        // a cancelled render isn't worth resuming
+        no_part_file: opt.no_part_file || cancel.is_some(),
+        // a corrupted part file can't be appended to, so its rows go in a new one
+        resume_from: if opt.recover_corrupt {
+            None
+        } else {
+            opt.recover_from.clone()
+        },
+        recovered_lines: base.iter().map(Option::is_some).collect(),
+        checkpoint_every: opt.checkpoint_every,
     };
     thread::spawn(move || {
         done_sender.send(monitor_progress(progress_info)).unwrap();
     });
 
-    let base = if let Some(part_file) = opt.recover_from {
-        PartFile::read(&part_file, image_height, image_width, opt.recover_corrupt)?
-    } else {
-        vec![None; image_height as usize]
-    };
-
     // Render in parallel
-    let pixels = base
-        .into_iter()
-        .enumerate()
-        .rev()
-        .map(|(j, v)| (j as u32, v, progress_sender.clone()))
-        .collect::<Vec<(u32, Option<Vec<u8>>, mpsc::Sender<(u32, Vec<u8>)>)>>()
-        .into_par_iter()
-        .map(|(j, from_part_file, sender)| {
-            match from_part_file {
-                None => {
-                    let mut rng = rand::thread_rng();
-                    let mut row = Vec::with_capacity(3 * image_width as usize);
-                    for i in 0..image_width {
-                        let mut colour = Vec3::new(0, 0, 0);
-                        for _ in 0..samples_per_pixel {
-                            let u = (i as f64 + rng.gen_range(0.0..1.0)) / (image_width - 1) as f64;
-                            let v =
-                                (j as f64 + rng.gen_range(0.0..1.0)) / (image_height - 1) as f64;
-                            let r = camera.find_ray(u, v);
-                            colour += cast_ray(&r, &world, &sky, max_bounces);
+    let unfinished_rows = AtomicU32::new(0);
+    let (rows, (albedo_rows, (sample_count_rows, hdr_rows))): (Vec<_>, (Vec<_>, (Vec<_>, Vec<_>))) =
+        base.into_iter()
+            .enumerate()
+            .rev()
+            .map(|(j, v)| (j as u32, v, progress_sender.clone()))
+            .collect::<Vec<(u32, Option<Vec<u8>>, mpsc::Sender<(u32, Vec<u8>)>)>>()
+            .into_par_iter()
+            .map(|(j, from_part_file, sender)| {
+                match from_part_file {
+                    None if cancel.is_some_and(|c| c.load(Ordering::Relaxed))
+                        || opt
+                            .time_limit
+                            .is_some_and(|limit| render_start.elapsed() > limit) =>
+                    {
+                        // left out of the part file, so --recover-from can finish it
+                        unfinished_rows.fetch_add(1, Ordering::Relaxed);
+                        let black = vec![0; 3 * image_width as usize];
+                        let hdr_black = if keep_hdr {
+                            vec![Colour::new(0, 0, 0); image_width as usize]
+                        } else {
+                            Vec::new()
+                        };
+                        (
+                            black.clone(),
+                            (black, (vec![0; image_width as usize], hdr_black)),
+                        )
+                    }
+                    None => {
+                        if let Some(seed) = opt.seed {
+                            random::reseed(seed, j as u64 + 1);
                         }
-                        colour /= samples_per_pixel as f64;
-                        // correct for gamma=2.0 (raise to the power of 1/gamma, i.e. sqrt)
-                        let gamma_corrected =
-                            Colour::new(colour.x.sqrt(), colour.y.sqrt(), colour.z.sqrt());
-                        row.append(&mut colour_to_raw(gamma_corrected));
+                        let mut rng = random::rng();
+                        let mut row = Vec::with_capacity(3 * image_width as usize);
+                        let mut albedo_row = Vec::with_capacity(3 * image_width as usize);
+                        let mut sample_count_row = vec![0; image_width as usize];
+                        let mut hdr_row = Vec::new();
+                        for i in 0..image_width {
+                            // rows are numbered from the bottom, but crops from the top
+                            if !opt.crop.is_none_or(|c| c.contains(i, image_height - 1 - j)) {
+                                row.extend_from_slice(&[0, 0, 0]);
+                                albedo_row.extend_from_slice(&[0, 0, 0]);
+                                if keep_hdr {
+                                    hdr_row.push(Colour::new(0, 0, 0));
+                                }
+                                continue;
+                            }
+                            let mut colour = Vec3::new(0, 0, 0);
+                            let mut albedo = Colour::new(0, 0, 0);
+                            let mut samples = 0;
+                            let (mut brightness, mut brightness_squared) = (0.0, 0.0);
+                            while samples < max_samples {
+                                let u =
+                                    (i as f64 + rng.gen_range(0.0..1.0)) / (image_width - 1) as f64;
+                                let v = (j as f64 + rng.gen_range(0.0..1.0))
+                                    / (image_height - 1) as f64;
+                                let r = camera.find_ray(u, v);
+                                stats::PRIMARY_RAYS.increment();
+                                let (sample, sample_albedo) = if opt.ao_mode {
+                                    let occlusion = ambient_occlusion(&r, &world, ao_radius);
+                                    (occlusion, occlusion)
+                                } else {
+                                    cast_ray(
+                                        &r,
+                                        RayType::Primary,
+                                        &world,
+                                        &lights,
+                                        sky.as_ref(),
+                                        atmosphere.as_ref(),
+                                        max_bounces,
+                                    )
+                                };
+                                colour += sample;
+                                albedo += sample_albedo;
+                                samples += 1;
+                                let b = (sample.x + sample.y + sample.z) / 3.0;
+                                brightness += b;
+                                brightness_squared += b * b;
+                                if samples >= min_samples
+                                    && converged(samples, brightness, brightness_squared)
+                                {
+                                    break;
+                                }
+                            }
+                            colour /= samples as f64;
+                            albedo /= samples as f64;
+                            sample_count_row[i as usize] = samples;
+                            if keep_hdr {
+                                hdr_row.push(colour);
+                            }
+                            let noise = noise_at(i, image_height - 1 - j);
+                            row.append(&mut colour_to_raw(colour, curve, noise));
+                            albedo_row.append(&mut colour_to_raw(albedo, curve, 0.0));
+                        }
+                        sender.send((j, row.clone())).unwrap();
+                        (row, (albedo_row, (sample_count_row, hdr_row)))
+                    }
+                    Some(row) => {
+                        sender.send((j, row.clone())).unwrap();
+                        // part files only have the final colours, so anything
+                        // brighter than white glows as if it were white
+                        let hdr_row = if keep_hdr {
+                            row.chunks(3).map(|p| raw_to_colour(p, curve)).collect()
+                        } else {
+                            Vec::new()
+                        };
+                        // albedo and sample counts aren't stored in part files, so
+                        // recovered rows are left black
+                        (
+                            row,
+                            (
+                                vec![0; 3 * image_width as usize],
+                                (vec![0; image_width as usize], hdr_row),
+                            ),
+                        )
                     }
-                    sender.send((j, row.clone())).unwrap();
-                    return row;
-                }
-                Some(row) => {
-                    sender.send((j, row.clone())).unwrap();
-                    return row;
                 }
-            }
-        })
-        .flatten()
-        .collect::<Vec<u8>>();
+            })
+            .unzip();
 
-    if !opt.quiet {
-        // join with the progress bar
-        done_receiver.recv()??;
+    // join with the progress bar, which also cleans up the part file
+    drop(progress_sender);
+    done_receiver.recv()??;
+    if cancel.is_some_and(|c| c.load(Ordering::Relaxed)) {
+        return Ok(());
     }
 
+    let pixels = if let Some(threshold) = opt.bloom_threshold {
+        bloom(
+            &hdr_rows.concat(),
+            image_width as usize,
+            threshold,
+            bloom_radius,
+        )
+        .into_iter()
+        .enumerate()
+        .flat_map(|(n, c)| {
+            let (x, y) = (n as u32 % image_width, n as u32 / image_width);
+            colour_to_raw(c, curve, noise_at(x, y))
+        })
+        .collect()
+    } else {
+        rows.concat()
+    };
     let img: RgbImage = ImageBuffer::from_raw(image_width, image_height, pixels).unwrap();
-    img.save(opt.output_file)?;
+    save_replacing(output_file, |path| img.save(path))?;
+    if let Some(albedo_file) = &outputs.albedo {
+        let img: RgbImage =
+            ImageBuffer::from_raw(image_width, image_height, albedo_rows.concat()).unwrap();
+        save_replacing(albedo_file, |path| img.save(path))?;
+    }
+    if let Some(sample_count_file) = &outputs.sample_count {
+        let pixels = sample_count_rows
+            .concat()
+            .into_iter()
+            .map(|samples| (255 * samples / max_samples) as u8)
+            .collect();
+        let img: GrayImage = ImageBuffer::from_raw(image_width, image_height, pixels).unwrap();
+        save_replacing(sample_count_file, |path| img.save(path))?;
+    }
 
+    let unfinished_rows = unfinished_rows.into_inner();
+    if unfinished_rows > 0 {
+        eprintln!(
+            "Reached the time limit with {} of {} rows left unrendered",
+            unfinished_rows, image_height
+        );
+    }
+    if opt.stats {
+        stats::print_report(render_start - setup_start, render_start.elapsed());
+    }
     if !opt.quiet {
         let elapsed = render_start.elapsed().as_secs();
         eprintln!("Completed in {}:{:02}", elapsed / 60, elapsed % 60,);
@@ -169,13 +658,248 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn colour_to_raw(c: Colour) -> Vec<u8> {
-    let r = (255.0 * clamp(c.x.abs(), 0.0, 0.999)).floor() as u8;
-    let g = (255.0 * clamp(c.y.abs(), 0.0, 0.999)).floor() as u8;
-    let b = (255.0 * clamp(c.z.abs(), 0.0, 0.999)).floor() as u8;
+// Held while an image is being saved, so Ctrl+C in watch mode can wait for it
+static SAVING: Mutex<()> = Mutex::new(());
+
+// Saves beside the file and then renames over it, so nothing watching the file
+// ever sees half an image
+fn save_replacing(path: &Path, save: impl FnOnce(&Path) -> ImageResult<()>) -> Result<()> {
+    let _saving = SAVING.lock();
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} isn't a file name", path.display()))?;
+    // keeps the extension, which picks the image format
+    let temp_path = path.with_file_name(format!(".{}", file_name.to_string_lossy()));
+    save(&temp_path).with_context(|| format!("Saving {}", path.display()))?;
+    fs::rename(&temp_path, path).with_context(|| format!("Saving {}", path.display()))?;
+    Ok(())
+}
+
+// One ray through the middle of each pixel, coloured by how many BVH nodes it
+// visits finding the first thing it hits, scaled so the busiest pixel is red
+fn render_bvh_depth(opt: &Opt, vis_file: &Path) -> Result<()> {
+    let (camera, world, _, _, _, aspect_ratio) = load_config(
+        &opt.input_file,
+        &opt.overrides,
+        0.0,
+        opt.isolate().as_ref(),
+        opt.view.as_deref(),
+    )?;
+    let image_width = opt.width;
+    let image_height = (image_width as f64 / aspect_ratio).round() as u32;
+    let counts: Vec<u32> = (0..image_height)
+        .into_par_iter()
+        .rev()
+        .flat_map_iter(|j| {
+            let (camera, world) = (&camera, &world);
+            (0..image_width).map(move |i| {
+                let u = (i as f64 + 0.5) / (image_width - 1) as f64;
+                let v = (j as f64 + 0.5) / (image_height - 1) as f64;
+                let ray = camera.find_ray(u, v);
+                count_bvh_nodes(|| world.hit(&ray, 0.001, f64::INFINITY)).1
+            })
+        })
+        .collect();
+    let most = counts.iter().copied().max().unwrap_or(0).max(1);
+    let pixels = counts
+        .iter()
+        .flat_map(|&count| {
+            // hue from blue round to red
+            let hue = 4.0 * (1.0 - count as f64 / most as f64);
+            let channel = |offset: f64| clamp(2.0 - (hue - offset).abs(), 0.0, 1.0);
+            colour_to_raw(
+                Colour::new(channel(0.0), channel(2.0), channel(4.0)),
+                TransferCurve::Gamma(1.0),
+                0.0,
+            )
+        })
+        .collect();
+    let img: RgbImage = ImageBuffer::from_raw(image_width, image_height, pixels).unwrap();
+    img.save(vis_file)?;
+    if !opt.quiet {
+        eprintln!("Most BVH nodes visited by one ray, shown in red: {}", most);
+    }
+    Ok(())
+}
+
+const DEFAULT_SAMPLES: u32 = 100;
+// how close a pixel's mean brightness must (probably) be to the true value
+// before adaptive sampling stops casting rays for it
+const ADAPTIVE_TOLERANCE: f64 = 0.05;
+
+// 95% confidence interval of the mean, relative to the mean, with a floor so
+// that near-black pixels don't need endless samples
+fn converged(samples: u32, sum: f64, sum_of_squares: f64) -> bool {
+    let n = samples as f64;
+    let mean = sum / n;
+    let variance = (sum_of_squares / n - mean * mean).max(0.0);
+    1.96 * (variance / n).sqrt() <= ADAPTIVE_TOLERANCE * mean.max(0.05)
+}
+
+fn parse_crop(s: &str) -> Result<Crop> {
+    let numbers = s
+        .split(',')
+        .map(|n| n.trim().parse())
+        .collect::<Result<Vec<u32>, _>>()
+        .context("Reading crop")?;
+    let (x0, y0, x1, y1) = match numbers[..] {
+        [x0, y0, x1, y1] => (x0, y0, x1, y1),
+        _ => bail!("Expected a crop like x0,y0,x1,y1, found {}", s),
+    };
+    if x0 >= x1 || y0 >= y1 {
+        bail!("Crop {} is empty; x1 and y1 must be past x0 and y0", s);
+    }
+    Ok(Crop { x0, y0, x1, y1 })
+}
+
+fn parse_seconds(s: &str) -> Result<Duration> {
+    let seconds: f64 = s.parse().context("Reading seconds")?;
+    if !(seconds >= 0.0 && seconds.is_finite()) {
+        bail!("Expected a number of seconds, found {}", s);
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+fn parse_frames(s: &str) -> Result<(u32, u32)> {
+    let (first, last) = match s.find('-') {
+        Some(split) => (&s[..split], &s[split + 1..]),
+        None => (s, s),
+    };
+    let first = first.trim().parse().context("Reading first frame")?;
+    let last = last.trim().parse().context("Reading last frame")?;
+    if first > last {
+        bail!(
+            "Frames go from first to last, but {} is after {}",
+            first,
+            last
+        );
+    }
+    Ok((first, last))
+}
+
+// out.png becomes out_0001.png for frame 1
+fn frame_file(path: &Path, frame: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}_{:04}.{}", stem, frame, extension.to_string_lossy()),
+        None => format!("{}_{:04}", stem, frame),
+    };
+    path.with_file_name(name)
+}
+
+// out.png checkpoints to out.checkpoint.png, whatever format the output is in
+fn checkpoint_file(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.checkpoint.png", stem))
+}
+
+fn parse_override(s: &str) -> Result<(String, String)> {
+    let split = s
+        .find('=')
+        .ok_or_else(|| anyhow!("Expected key=value, found {}", s))?;
+    Ok((String::from(&s[..split]), String::from(&s[split + 1..])))
+}
+
+// How linear colours are encoded in the saved image
+#[derive(Clone, Copy)]
+enum TransferCurve {
+    // raise to the power of 1/gamma
+    Gamma(f64),
+    // the piecewise IEC 61966-2-1 curve
+    Srgb,
+}
+
+impl TransferCurve {
+    fn encode(self, c: Colour) -> Colour {
+        match self {
+            TransferCurve::Gamma(2.0) => Colour::new(c.x.sqrt(), c.y.sqrt(), c.z.sqrt()),
+            TransferCurve::Gamma(gamma) => Colour::new(
+                c.x.powf(1.0 / gamma),
+                c.y.powf(1.0 / gamma),
+                c.z.powf(1.0 / gamma),
+            ),
+            TransferCurve::Srgb => linear_to_srgb(c),
+        }
+    }
+
+    fn decode(self, c: Colour) -> Colour {
+        match self {
+            TransferCurve::Gamma(gamma) => {
+                Colour::new(c.x.powf(gamma), c.y.powf(gamma), c.z.powf(gamma))
+            }
+            TransferCurve::Srgb => srgb_to_linear(c),
+        }
+    }
+}
+
+// Film grain, the same for a pixel every time with the same seed
+#[derive(Clone, Copy)]
+struct Grain {
+    // out of 255
+    sigma: f64,
+    seed: u64,
+}
+
+impl Grain {
+    fn noise_at(self, x: u32, y: u32) -> f64 {
+        self.sigma * random::fixed_normal(self.seed, ((y as u64) << 32) | x as u64)
+    }
+}
+
+// noise is added to every channel after encoding, out of 255
+fn colour_to_raw(c: Colour, curve: TransferCurve, noise: f64) -> Vec<u8> {
+    let c = curve.encode(c.abs()) + Colour::new(noise, noise, noise) / 255.0;
+    let r = (255.0 * clamp(c.x, 0.0, 0.999)).floor() as u8;
+    let g = (255.0 * clamp(c.y, 0.0, 0.999)).floor() as u8;
+    let b = (255.0 * clamp(c.z, 0.0, 0.999)).floor() as u8;
     vec![r, g, b]
 }
 
+fn raw_to_colour(raw: &[u8], curve: TransferCurve) -> Colour {
+    curve.decode(Colour::new(raw[0], raw[1], raw[2]) / 255.0)
+}
+
+// Adds a blurred copy of the pixels brighter than threshold back onto the
+// image, so that bright lights glow. The blur is a gaussian reaching out about
+// radius pixels, done across and then down.
+fn bloom(pixels: &[Colour], width: usize, threshold: f64, radius: f64) -> Vec<Colour> {
+    let height = pixels.len() / width;
+    let bright: Vec<_> = pixels
+        .iter()
+        .map(|&c| {
+            if (c.x + c.y + c.z) / 3.0 > threshold {
+                c
+            } else {
+                Colour::new(0, 0, 0)
+            }
+        })
+        .collect();
+    // the kernel is cut off at three standard deviations
+    let sigma = radius / 3.0;
+    let reach = radius.ceil() as isize;
+    let weights: Vec<f64> = (-reach..=reach)
+        .map(|d| (-(d * d) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f64 = weights.iter().sum();
+    let blur = |source: &[Colour], step: (isize, isize)| -> Vec<Colour> {
+        (0..pixels.len())
+            .map(|n| {
+                let (x, y) = ((n % width) as isize, (n / width) as isize);
+                let mut sum = Colour::new(0, 0, 0);
+                for (d, weight) in (-reach..=reach).zip(&weights) {
+                    // past the edge, the edge pixels carry on
+                    let sx = (x + d * step.0).clamp(0, width as isize - 1) as usize;
+                    let sy = (y + d * step.1).clamp(0, height as isize - 1) as usize;
+                    sum += source[sy * width + sx] * *weight;
+                }
+                sum / total
+            })
+            .collect()
+    };
+    let blurred = blur(&blur(&bright, (1, 0)), (0, 1));
+    pixels.iter().zip(blurred).map(|(&c, b)| c + b).collect()
+}
+
 struct ProgressInfo {
     output_file_name: PathBuf,
     image_width: u32,
@@ -186,18 +910,23 @@ struct ProgressInfo {
     progress_receiver: mpsc::Receiver<(u32, Vec<u8>)>,
     quiet: bool,
     no_part_file: bool,
+    resume_from: Option<PathBuf>,
+    recovered_lines: Vec<bool>,
+    checkpoint_every: Option<u32>,
 }
 
 fn monitor_progress(info: ProgressInfo) -> Result<()> {
-    let mut part_file = if !info.no_part_file {
-        Some(PartFile::new(&info.output_file_name)?)
-    } else {
+    let mut part_file = if info.no_part_file {
         None
+    } else if let Some(path) = &info.resume_from {
+        Some(PartFile::append_to(path)?)
+    } else {
+        Some(PartFile::new(
+            &info.output_file_name,
+            info.image_width,
+            info.image_height,
+        )?)
     };
-    if let Some(file) = &mut part_file {
-        file.file
-            .write_all(format!("{} {}\n", info.image_width, info.image_height).as_bytes())?;
-    }
     let bar_symbols = if info.ascii_symbols_only {
         " .:"
     } else {
@@ -220,13 +949,37 @@ fn monitor_progress(info: ProgressInfo) -> Result<()> {
         info.progress_bar_samples,
         info.image_height,
     );
+    // the part file is what --resume reads; the checkpoint is just for looking at
+    let checkpoint_path = checkpoint_file(&info.output_file_name);
+    let mut checkpoint = info
+        .checkpoint_every
+        .map(|_| vec![0; 3 * (info.image_width * info.image_height) as usize]);
+    let mut lines_received = 0;
     for _ in 0..info.image_height {
         let received = info.progress_receiver.recv().context("Rendering progress");
         if let Ok((line_number, part)) = received {
+            lines_received += 1;
             if !info.quiet {
                 progress.update()?;
             }
-            if let Some(file) = &mut part_file {
+            if let (Some(pixels), Some(every)) = (checkpoint.as_mut(), info.checkpoint_every) {
+                // line numbers count up from the bottom of the image
+                let row_len = 3 * info.image_width as usize;
+                let start = (info.image_height - 1 - line_number) as usize * row_len;
+                pixels[start..start + row_len].copy_from_slice(&part);
+                if lines_received % every == 0 {
+                    let saved =
+                        RgbImage::from_raw(info.image_width, info.image_height, pixels.clone())
+                            .unwrap()
+                            .save(&checkpoint_path);
+                    if let Err(e) = saved {
+                        eprintln!("\rError saving checkpoint: {}", e);
+                    }
+                }
+            }
+            let already_in_file =
+                info.resume_from.is_some() && info.recovered_lines[line_number as usize];
+            if let Some(file) = part_file.as_mut().filter(|_| !already_in_file) {
                 if let Err(e) = file.write_part(line_number, part) {
                     eprintln!(
                         "\rError writing to part file: {}\nPart file may be corrupted",
@@ -236,11 +989,19 @@ fn monitor_progress(info: ProgressInfo) -> Result<()> {
             }
         }
     }
-    if let Some(file) = part_file {
-        remove_file(&file.path)?;
-    }
     if !info.quiet {
         let _ = progress.clear();
     }
+    if let Some(file) = part_file {
+        // an unfinished render keeps its part file so it can be resumed
+        if lines_received == info.image_height {
+            remove_file(&file.path)?;
+        } else {
+            eprintln!("Partial render saved in {}", file.path.display());
+        }
+    }
+    if checkpoint.is_some() && lines_received == info.image_height && checkpoint_path.exists() {
+        remove_file(&checkpoint_path)?;
+    }
     Ok(())
 }
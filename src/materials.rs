@@ -1,10 +1,14 @@
 use rand::Rng;
 
 use std::f64::consts::PI;
+use std::fmt;
 use std::sync::Arc;
 
 use crate::hitting::{Colour, HitRecord, Material};
-use crate::math::{dot, random_in_unit_sphere, random_unit_vector, reflect, refract, Ray};
+use crate::math::{
+    dot, random_cosine_direction, random_in_unit_sphere, reflect, refract, Onb, Ray, Vec3,
+};
+use crate::random;
 use crate::textures::{SolidColour, Texture};
 
 pub struct Lambertian {
@@ -26,22 +30,24 @@ impl Lambertian {
 
 impl Material for Lambertian {
     fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Ray, Colour)> {
-        let scatter_direction = hit.normal + random_unit_vector();
-        // catch degenerate scatter direction
-        let scatter_direction = if scatter_direction.near_zero() {
-            hit.normal
-        } else {
-            scatter_direction
-        };
+        let (local, _pdf) = random_cosine_direction();
+        let scatter_direction = Onb::from_w(hit.normal).local(local.x, local.y, local.z);
         let scattered = Ray::new(hit.intersection, scatter_direction, ray.time);
         Some((
             scattered,
             self.albedo
-                .value(hit.surface_u, hit.surface_v, hit.intersection),
+                .value(hit.surface_u, hit.surface_v, hit.intersection, hit.normal),
         ))
     }
-    fn _print(&self) -> String {
-        format!("Lambertian: {}", self.albedo._print())
+    fn scattering_pdf(&self, hit: &HitRecord, direction: Vec3) -> f64 {
+        let cosine = dot(hit.normal, direction.unit_vector());
+        cosine.max(0.0) / PI
+    }
+}
+
+impl fmt::Display for Lambertian {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Lambertian: {}", self.albedo)
     }
 }
 
@@ -64,8 +70,11 @@ impl Material for Metal {
             None
         }
     }
-    fn _print(&self) -> String {
-        format!("Metal: albedo {}, fuzz {}", self.albedo, self.fuzz)
+}
+
+impl fmt::Display for Metal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Metal: albedo {}, fuzz {}", self.albedo, self.fuzz)
     }
 }
 
@@ -85,7 +94,7 @@ impl Material for Dielectric {
         let cos_theta = f64::min(dot(-unit_direction, hit.normal), 1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let random_fraction = rand::thread_rng().gen_range(0.0..1.0);
+        let random_fraction = random::rng().gen_range(0.0..1.0);
         let direction =
             if cannot_refract || reflectance(cos_theta, refraction_ratio) > random_fraction {
                 // cannot refract
@@ -99,25 +108,34 @@ impl Material for Dielectric {
             Colour::new(1.0, 1.0, 1.0),
         ))
     }
-    fn _print(&self) -> String {
-        format!("Dielectric: ior {}", self.index_of_refraction)
+}
+
+impl fmt::Display for Dielectric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Dielectric: ior {}", self.index_of_refraction)
     }
 }
 
 fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
-    let r0 = (1.1 - ref_idx) / (1.0 + ref_idx);
+    let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
     let r0 = r0 * r0;
     r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
 }
 
 pub struct DiffuseLight {
     pub emit: Arc<dyn Texture>,
+    // multiplies the texture, to make the light brighter or dimmer
+    pub strength: f64,
+    // shine from the back of surfaces as well as the front
+    pub double_sided: bool,
 }
 
 impl DiffuseLight {
-    pub fn with_colour(colour: Colour) -> Arc<dyn Material> {
+    pub fn with_colour(colour: Colour, strength: f64, double_sided: bool) -> Arc<dyn Material> {
         Arc::new(DiffuseLight {
             emit: Arc::new(SolidColour { colour }),
+            strength,
+            double_sided,
         })
     }
 }
@@ -126,16 +144,75 @@ impl Material for DiffuseLight {
     fn scatter(&self, _ray: &Ray, _hit: &HitRecord) -> Option<(Ray, Colour)> {
         None
     }
-    fn emitted(&self, hit: &HitRecord) -> Colour {
-        if hit.front_face {
-            self.emit
-                .value(hit.surface_u, hit.surface_v, hit.intersection)
+    fn emitted(&self, _ray: &Ray, hit: &HitRecord) -> Colour {
+        if hit.front_face || self.double_sided {
+            self.strength
+                * self
+                    .emit
+                    .value(hit.surface_u, hit.surface_v, hit.intersection, hit.normal)
         } else {
             Colour::new(0, 0, 0)
         }
     }
-    fn _print(&self) -> String {
-        format!("Diffuse light: {}", self.emit._print())
+    fn average_emission(&self) -> Colour {
+        self.strength * self.emit.average_colour()
+    }
+}
+
+impl fmt::Display for DiffuseLight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Diffuse light: {}", self.emit)
+    }
+}
+
+// Diffuse light that only shines in a cone around axis, fading out over the
+// penumbra at its edge
+pub struct ConeLight {
+    colour: Colour,
+    axis: Vec3,
+    cos_cone: f64,
+    cos_penumbra: f64,
+}
+
+impl ConeLight {
+    pub fn new(
+        colour: Colour,
+        axis: Vec3,
+        cone_angle_degrees: f64,
+        penumbra_angle_degrees: f64,
+    ) -> Arc<dyn Material> {
+        Arc::new(ConeLight {
+            colour,
+            axis: axis.unit_vector(),
+            cos_cone: cone_angle_degrees.to_radians().cos(),
+            cos_penumbra: (cone_angle_degrees + penumbra_angle_degrees)
+                .to_radians()
+                .cos(),
+        })
+    }
+}
+
+impl Material for ConeLight {
+    fn scatter(&self, _ray: &Ray, _hit: &HitRecord) -> Option<(Ray, Colour)> {
+        None
+    }
+    fn emitted(&self, ray: &Ray, hit: &HitRecord) -> Colour {
+        // the light goes back the way the ray came
+        let cos_angle = dot(-ray.direction, self.axis);
+        if !hit.front_face || cos_angle <= self.cos_penumbra {
+            Colour::new(0, 0, 0)
+        } else if cos_angle >= self.cos_cone {
+            self.colour
+        } else {
+            let t = (cos_angle - self.cos_penumbra) / (self.cos_cone - self.cos_penumbra);
+            t * t * (3.0 - 2.0 * t) * self.colour
+        }
+    }
+}
+
+impl fmt::Display for ConeLight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cone light: {} around {}", self.colour, self.axis)
     }
 }
 
@@ -148,45 +225,160 @@ impl Material for Isotropic {
         Some((
             Ray::new(hit.intersection, random_in_unit_sphere(), ray.time),
             self.albedo
-                .value(hit.surface_u, hit.surface_v, hit.intersection),
+                .value(hit.surface_u, hit.surface_v, hit.intersection, hit.normal),
         ))
     }
-    fn _print(&self) -> String {
-        format!("Isotropic: {}", self.albedo._print())
+}
+
+impl fmt::Display for Isotropic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Isotropic: {}", self.albedo)
     }
 }
 
+// Subsurface scattering, e.g. for skin, wax or marble. Light goes into the
+// surface and takes a random walk, scattering after distances averaging
+// mean_free_path, until it finds its way out or runs out of bounces. Each
+// scatter picks up the albedo. g is as in the Henyey-Greenstein phase
+// function: from -1 (back the way it came) through 0 (any direction) to 1
+// (straight on).
+pub struct RandomWalkSss {
+    pub albedo: Arc<dyn Texture>,
+    pub mean_free_path: f64,
+    pub g: f64,
+}
+
+impl Material for RandomWalkSss {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Ray, Colour)> {
+        if hit.front_face {
+            // into the surface, diffusely
+            let (local, _) = random_cosine_direction();
+            let inward = Onb::from_w(-hit.normal).local(local.x, local.y, local.z);
+            return Some((
+                Ray::new(hit.intersection, inward, ray.time),
+                Colour::new(1, 1, 1),
+            ));
+        }
+        // On reaching the surface from inside: the distance to the next
+        // scatter is memoryless, so whether the walk scattered on the way here
+        // can be decided now
+        let direction = ray.direction.unit_vector();
+        let travelled = (hit.intersection - ray.origin).length();
+        let distance = -self.mean_free_path * (1.0 - random::rng().gen_range(0.0..1.0f64)).ln();
+        if distance >= travelled {
+            return Some((
+                Ray::new(hit.intersection, direction, ray.time),
+                Colour::new(1, 1, 1),
+            ));
+        }
+        let scatter_point = ray.origin + distance * direction;
+        Some((
+            Ray::new(
+                scatter_point,
+                henyey_greenstein(direction, self.g),
+                ray.time,
+            ),
+            self.albedo
+                .value(hit.surface_u, hit.surface_v, scatter_point, hit.normal),
+        ))
+    }
+}
+
+impl fmt::Display for RandomWalkSss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Random walk SSS: {}, mean free path {}, g {}",
+            self.albedo, self.mean_free_path, self.g
+        )
+    }
+}
+
+// A direction scattered away from the incoming one by the Henyey-Greenstein
+// phase function, whose mean cosine is g
+fn henyey_greenstein(direction: Vec3, g: f64) -> Vec3 {
+    let mut rng = random::rng();
+    let xi = rng.gen_range(0.0..1.0);
+    let phi = 2.0 * PI * rng.gen_range(0.0..1.0);
+    let cos_theta = if g.abs() < 1e-3 {
+        1.0 - 2.0 * xi
+    } else {
+        let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+        (1.0 + g * g - s * s) / (2.0 * g)
+    };
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    Onb::from_w(direction).local(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
 pub struct Checkered {
     pub odd: Arc<dyn Material>,
     pub even: Arc<dyn Material>,
     pub tile_density: f64,
 }
 
-impl Material for Checkered {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Ray, Colour)> {
+impl Checkered {
+    // The material of the tile the hit landed on
+    fn tile(&self, hit: &HitRecord) -> &Arc<dyn Material> {
         let sines = (self.tile_density * PI * hit.surface_u).sin()
             * (self.tile_density * PI * hit.surface_v).sin();
         if sines < 0.0 {
-            self.odd.scatter(ray, hit)
+            &self.odd
         } else {
-            self.even.scatter(ray, hit)
+            &self.even
         }
     }
-    fn emitted(&self, hit: &HitRecord) -> Colour {
+}
+
+impl Material for Checkered {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Ray, Colour)> {
+        self.tile(hit).scatter(ray, hit)
+    }
+    fn scattering_pdf(&self, hit: &HitRecord, direction: Vec3) -> f64 {
+        self.tile(hit).scattering_pdf(hit, direction)
+    }
+    fn emitted(&self, ray: &Ray, hit: &HitRecord) -> Colour {
         let sines =
             (self.tile_density * hit.surface_u).sin() * (self.tile_density * hit.surface_v).sin();
         if sines < 0.0 {
-            self.odd.emitted(hit)
+            self.odd.emitted(ray, hit)
         } else {
-            self.even.emitted(hit)
+            self.even.emitted(ray, hit)
         }
     }
-    fn _print(&self) -> String {
-        format!(
+}
+
+impl fmt::Display for Checkered {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
             "Checkered: tile size {}, tiles ({}, {})",
-            self.tile_density,
-            self.odd._print(),
-            self.even._print()
+            self.tile_density, self.odd, self.even
         )
     }
 }
+
+#[test]
+fn reflectance_test() {
+    // glass reflects about 4% of light head-on
+    assert!((reflectance(1.0, 1.5) - 0.04).abs() < 1e-9);
+    // and all of it at grazing angles
+    assert!((reflectance(0.0, 1.5) - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn henyey_greenstein_test() {
+    let direction = Vec3::new(0, 0, 1);
+    for &g in &[-0.5, 0.0, 0.8] {
+        let n = 100_000;
+        let mean_cosine = (0..n)
+            .map(|_| dot(henyey_greenstein(direction, g), direction))
+            .sum::<f64>()
+            / n as f64;
+        assert!(
+            (mean_cosine - g).abs() < 0.01,
+            "g {} gave {}",
+            g,
+            mean_cosine
+        );
+    }
+}
@@ -3,6 +3,8 @@ use rand::Rng;
 use std::f64::consts::PI;
 use std::fmt;
 
+use crate::random;
+
 pub type Point3 = Vec3;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -31,17 +33,39 @@ impl Vec3 {
     pub fn length(&self) -> f64 {
         self.length_squared().sqrt()
     }
+    // a zero vector has no direction, so it gets straight up rather than NaNs
     pub fn unit_vector(&self) -> Vec3 {
+        let length = self.length();
+        if length < f64::EPSILON {
+            return Vec3::new(0, 1, 0);
+        }
         Vec3 {
-            x: self.x / self.length(),
-            y: self.y / self.length(),
-            z: self.z / self.length(),
+            x: self.x / length,
+            y: self.y / length,
+            z: self.z / length,
         }
     }
     pub fn near_zero(&self) -> bool {
         let d = 1e-8;
         self.x.abs() < d && self.y.abs() < d && self.z.abs() < d
     }
+    // self at t = 0, other at t = 1
+    pub fn lerp(self, other: Vec3, t: f64) -> Vec3 {
+        (1.0 - t) * self + t * other
+    }
+    pub fn clamp_components(self, min: f64, max: f64) -> Vec3 {
+        Vec3::new(
+            self.x.max(min).min(max),
+            self.y.max(min).min(max),
+            self.z.max(min).min(max),
+        )
+    }
+    pub fn max_component(self) -> f64 {
+        self.x.max(self.y).max(self.z)
+    }
+    pub fn abs(self) -> Vec3 {
+        Vec3::new(self.x.abs(), self.y.abs(), self.z.abs())
+    }
 }
 
 pub fn cross(lhs: Vec3, rhs: Vec3) -> Vec3 {
@@ -196,12 +220,24 @@ impl fmt::Display for Vec3 {
     }
 }
 
+// What a ray is for, so objects can choose not to show up in some of them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RayType {
+    // from the camera
+    Primary,
+    // bounced off a surface
+    Indirect,
+    // from a surface towards a light
+    Shadow,
+}
+
 // direction must be a unit vector
 #[derive(Debug)]
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vec3,
     pub time: f64,
+    pub kind: RayType,
 }
 
 impl Ray {
@@ -210,13 +246,308 @@ impl Ray {
             origin,
             direction: direction.unit_vector(),
             time,
+            kind: RayType::Primary,
         }
     }
+    pub fn of_kind(mut self, kind: RayType) -> Ray {
+        self.kind = kind;
+        self
+    }
     pub fn at(&self, t: f64) -> Point3 {
         self.origin + t * self.direction
     }
 }
 
+// Affine transformation matrix, applied to column vectors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn identity() -> Mat4 {
+        Mat4 {
+            m: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+    pub fn translation(offset: Vec3) -> Mat4 {
+        let mut result = Mat4::identity();
+        for i in 0..3 {
+            result.m[i][3] = offset[i];
+        }
+        result
+    }
+    pub fn scaling(factor: Vec3) -> Mat4 {
+        let mut result = Mat4::identity();
+        for i in 0..3 {
+            result.m[i][i] = factor[i];
+        }
+        result
+    }
+    // rotations go the same way as RotateX, RotateY and RotateZ
+    pub fn rotation_x(radians: f64) -> Mat4 {
+        let (sin, cos) = radians.sin_cos();
+        let mut result = Mat4::identity();
+        result.m[1][1] = cos;
+        result.m[1][2] = -sin;
+        result.m[2][1] = sin;
+        result.m[2][2] = cos;
+        result
+    }
+    pub fn rotation_y(radians: f64) -> Mat4 {
+        let (sin, cos) = radians.sin_cos();
+        let mut result = Mat4::identity();
+        result.m[0][0] = cos;
+        result.m[0][2] = sin;
+        result.m[2][0] = -sin;
+        result.m[2][2] = cos;
+        result
+    }
+    pub fn rotation_z(radians: f64) -> Mat4 {
+        let (sin, cos) = radians.sin_cos();
+        let mut result = Mat4::identity();
+        result.m[0][0] = cos;
+        result.m[0][1] = -sin;
+        result.m[1][0] = sin;
+        result.m[1][1] = cos;
+        result
+    }
+    // Gauss-Jordan elimination with partial pivoting; None if the matrix is
+    // singular, e.g. it scales something down to nothing
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut m = self.m;
+        let mut result = Mat4::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&a, &b| m[a][col].abs().partial_cmp(&m[b][col].abs()).unwrap())
+                .unwrap();
+            if m[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            m.swap(col, pivot);
+            result.swap(col, pivot);
+            let scale = 1.0 / m[col][col];
+            for j in 0..4 {
+                m[col][j] *= scale;
+                result[col][j] *= scale;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let factor = m[row][col];
+                    for j in 0..4 {
+                        m[row][j] -= factor * m[col][j];
+                        result[row][j] -= factor * result[col][j];
+                    }
+                }
+            }
+        }
+        Some(Mat4 { m: result })
+    }
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        self.transform_vector(p) + Vec3::new(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+    // like transform_point, but ignoring translation
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+    // Normals need the inverse-transpose to stay perpendicular to the surface.
+    // The cofactor matrix is that times the determinant, so this skips the
+    // inverse; the result isn't normalized.
+    pub fn transform_normal(&self, n: Vec3) -> Vec3 {
+        let m = &self.m;
+        let cofactor = |i: usize, j: usize| {
+            let (i1, i2) = ((i + 1) % 3, (i + 2) % 3);
+            let (j1, j2) = ((j + 1) % 3, (j + 2) % 3);
+            m[i1][j1] * m[i2][j2] - m[i1][j2] * m[i2][j1]
+        };
+        let det = m[0][0] * cofactor(0, 0) + m[0][1] * cofactor(0, 1) + m[0][2] * cofactor(0, 2);
+        // a mirroring transform would otherwise turn the normal inside out
+        let sign = if det < 0.0 { -1.0 } else { 1.0 };
+        sign * Vec3::new(
+            cofactor(0, 0) * n.x + cofactor(0, 1) * n.y + cofactor(0, 2) * n.z,
+            cofactor(1, 0) * n.x + cofactor(1, 1) * n.y + cofactor(1, 2) * n.z,
+            cofactor(2, 0) * n.x + cofactor(2, 1) * n.y + cofactor(2, 2) * n.z,
+        )
+    }
+}
+
+impl std::ops::Mul for Mat4 {
+    type Output = Mat4;
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut result = [[0.0; 4]; 4];
+        for (i, row) in result.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
+                *cell = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Mat4 { m: result }
+    }
+}
+
+// Unit quaternion representing a rotation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quat {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quat {
+    // same direction as Mat4's rotations for the x, y and z axes
+    pub fn from_axis_angle(axis: Vec3, radians: f64) -> Quat {
+        let axis = axis.unit_vector();
+        let (sin, cos) = (radians / 2.0).sin_cos();
+        Quat {
+            w: cos,
+            x: axis.x * sin,
+            y: axis.y * sin,
+            z: axis.z * sin,
+        }
+    }
+    // rotates about z first, then x, then y
+    pub fn from_euler_yxz(y: f64, x: f64, z: f64) -> Quat {
+        Quat::from_axis_angle(Vec3::new(0, 1, 0), y)
+            * Quat::from_axis_angle(Vec3::new(1, 0, 0), x)
+            * Quat::from_axis_angle(Vec3::new(0, 0, 1), z)
+    }
+    // rotates about x first, then y, then z
+    pub fn from_euler_xyz(x: f64, y: f64, z: f64) -> Quat {
+        Quat::from_axis_angle(Vec3::new(0, 0, 1), z)
+            * Quat::from_axis_angle(Vec3::new(0, 1, 0), y)
+            * Quat::from_axis_angle(Vec3::new(1, 0, 0), x)
+    }
+    pub fn conjugate(&self) -> Quat {
+        Quat {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+    pub fn rotate(&self, v: Vec3) -> Vec3 {
+        let axis = Vec3::new(self.x, self.y, self.z);
+        let t = 2.0 * cross(axis, v);
+        v + self.w * t + cross(axis, t)
+    }
+    // spherical interpolation, going the short way round
+    pub fn slerp(&self, other: Quat, t: f64) -> Quat {
+        let mut other = other;
+        let mut cos_theta =
+            self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z;
+        if cos_theta < 0.0 {
+            other = Quat {
+                w: -other.w,
+                x: -other.x,
+                y: -other.y,
+                z: -other.z,
+            };
+            cos_theta = -cos_theta;
+        }
+        // nearly the same rotation: the sines below would be close to 0
+        let (a, b) = if cos_theta > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (
+                ((1.0 - t) * theta).sin() / sin_theta,
+                (t * theta).sin() / sin_theta,
+            )
+        };
+        Quat {
+            w: a * self.w + b * other.w,
+            x: a * self.x + b * other.x,
+            y: a * self.y + b * other.y,
+            z: a * self.z + b * other.z,
+        }
+        .normalised()
+    }
+    pub fn to_rotation_matrix(self) -> Mat4 {
+        let Quat { w, x, y, z } = self;
+        let mut result = Mat4::identity();
+        result.m[0][0] = 1.0 - 2.0 * (y * y + z * z);
+        result.m[0][1] = 2.0 * (x * y - w * z);
+        result.m[0][2] = 2.0 * (x * z + w * y);
+        result.m[1][0] = 2.0 * (x * y + w * z);
+        result.m[1][1] = 1.0 - 2.0 * (x * x + z * z);
+        result.m[1][2] = 2.0 * (y * z - w * x);
+        result.m[2][0] = 2.0 * (x * z - w * y);
+        result.m[2][1] = 2.0 * (y * z + w * x);
+        result.m[2][2] = 1.0 - 2.0 * (x * x + y * y);
+        result
+    }
+    fn normalised(&self) -> Quat {
+        let length = (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+        Quat {
+            w: self.w / length,
+            x: self.x / length,
+            y: self.y / length,
+            z: self.z / length,
+        }
+    }
+}
+
+// applies rhs first, then self
+impl std::ops::Mul for Quat {
+    type Output = Quat;
+    fn mul(self, rhs: Quat) -> Quat {
+        Quat {
+            w: self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            x: self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            y: self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            z: self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+        }
+    }
+}
+
+// Orthonormal basis around w, e.g. a tangent frame for a surface normal
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    // Duff et al., "Building an Orthonormal Basis, Revisited" (2017); w must
+    // be a unit vector
+    pub fn from_w(w: Vec3) -> Onb {
+        let sign = 1.0f64.copysign(w.z);
+        let a = -1.0 / (sign + w.z);
+        let b = w.x * w.y * a;
+        Onb {
+            u: Vec3::new(1.0 + sign * w.x * w.x * a, sign * b, -sign * w.x),
+            v: Vec3::new(b, sign + w.y * w.y * a, -w.y),
+            w,
+        }
+    }
+    pub fn local(&self, a: f64, b: f64, c: f64) -> Vec3 {
+        a * self.u + b * self.v + c * self.w
+    }
+}
+
+// Veach's power heuristic with beta = 2: the weight for a sample drawn n_f
+// times from the density f, when g could also have produced it. cast_ray uses
+// it to combine scattered rays with rays towards lights.
+pub fn power_heuristic(n_f: f64, f_pdf: f64, n_g: f64, g_pdf: f64) -> f64 {
+    let f = n_f * f_pdf;
+    let g = n_g * g_pdf;
+    if f == 0.0 {
+        return 0.0;
+    }
+    f * f / (f * f + g * g)
+}
+
 pub fn clamp(a: f64, min: f64, max: f64) -> f64 {
     if a < min {
         min
@@ -227,12 +558,70 @@ pub fn clamp(a: f64, min: f64, max: f64) -> f64 {
     }
 }
 
-pub fn random_unit_vector() -> Vec3 {
+// Picks indices in proportion to their weights in constant time, by Vose's
+// alias method: each slot holds one index's share and, if that's short of a
+// full slot, the index that tops it up
+pub struct AliasTable {
+    share: Vec<f64>,
+    alias: Vec<usize>,
+    probabilities: Vec<f64>,
+}
+
+impl AliasTable {
+    // None if nothing has any weight
+    pub fn new(weights: &[f64]) -> Option<AliasTable> {
+        let total: f64 = weights.iter().sum();
+        if !(total > 0.0 && total.is_finite()) {
+            return None;
+        }
+        let probabilities = weights.iter().map(|w| w / total).collect::<Vec<_>>();
+        let n = weights.len();
+        let mut share = probabilities
+            .iter()
+            .map(|p| p * n as f64)
+            .collect::<Vec<_>>();
+        let mut alias = (0..n).collect::<Vec<_>>();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+            (0..n).partition(|&i| share[i] < 1.0);
+        while let (Some(s), Some(&l)) = (small.pop(), large.last()) {
+            alias[s] = l;
+            share[l] -= 1.0 - share[s];
+            if share[l] < 1.0 {
+                large.pop();
+                small.push(l);
+            }
+        }
+        // whatever's left is a full slot, give or take rounding
+        for i in small.into_iter().chain(large) {
+            share[i] = 1.0;
+        }
+        Some(AliasTable {
+            share,
+            alias,
+            probabilities,
+        })
+    }
+    pub fn sample(&self) -> usize {
+        let mut rng = random::rng();
+        let slot = rng.gen_range(0..self.share.len());
+        if rng.gen_range(0.0..1.0) < self.share[slot] {
+            slot
+        } else {
+            self.alias[slot]
+        }
+    }
+    pub fn probability(&self, index: usize) -> f64 {
+        self.probabilities[index]
+    }
+}
+
+#[cfg(test)]
+fn random_unit_vector() -> Vec3 {
     random_in_unit_sphere().unit_vector()
 }
 
 pub fn random_in_unit_sphere() -> Vec3 {
-    let mut rng = rand::thread_rng();
+    let mut rng = random::rng();
     loop {
         let p = Vec3::new(
             rng.gen_range(-1.0..1.0),
@@ -245,8 +634,27 @@ pub fn random_in_unit_sphere() -> Vec3 {
     }
 }
 
+// Cosine-weighted over the hemisphere around +z, by Malley's method: a uniform
+// point on the unit disc, lifted onto the hemisphere. Also returns the
+// probability density of the direction, cos(theta) / pi.
+pub fn random_cosine_direction() -> (Vec3, f64) {
+    let disc = random_in_unit_disc();
+    let z = (1.0 - disc.length_squared()).max(0.0).sqrt();
+    (Vec3::new(disc.x, disc.y, z), z / PI)
+}
+
+// Uniform over the directions within the cone around axis, which has a solid
+// angle of 2 pi (1 - cos_theta_max)
+pub fn random_in_cone(axis: Vec3, cos_theta_max: f64) -> Vec3 {
+    let mut rng = random::rng();
+    let cos_theta = 1.0 + rng.gen_range(0.0..1.0) * (cos_theta_max - 1.0);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * rng.gen_range(0.0..1.0);
+    Onb::from_w(axis.unit_vector()).local(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
 pub fn random_in_unit_disc() -> Vec3 {
-    let mut rng = rand::thread_rng();
+    let mut rng = random::rng();
     loop {
         let p = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
         if p.length_squared() <= 1.0 {
@@ -316,6 +724,59 @@ pub fn line_plane_collision(
     }
 }
 
+#[test]
+fn test_random_in_cone() {
+    // the axis needn't be a unit vector
+    let axis = Vec3::new(0, 3, 4);
+    let cos_theta_max = 0.8;
+    let mut widest: f64 = 1.0;
+    for _ in 0..1000 {
+        let direction = random_in_cone(axis, cos_theta_max);
+        assert!((direction.length() - 1.0).abs() < 1e-9);
+        let cos_theta = dot(direction, axis.unit_vector());
+        assert!(cos_theta >= cos_theta_max - 1e-9);
+        widest = widest.min(cos_theta);
+    }
+    assert!(widest < cos_theta_max + 0.01);
+}
+
+#[test]
+fn test_alias_table() {
+    let weights = [1.0, 0.0, 3.0, 4.0];
+    let table = AliasTable::new(&weights).unwrap();
+    let mut counts = [0; 4];
+    let samples = 80000;
+    for _ in 0..samples {
+        counts[table.sample()] += 1;
+    }
+    for (i, &w) in weights.iter().enumerate() {
+        assert_eq!(table.probability(i), w / 8.0);
+        let fraction = counts[i] as f64 / samples as f64;
+        assert!((fraction - w / 8.0).abs() < 0.01);
+    }
+    assert_eq!(counts[1], 0);
+    assert!(AliasTable::new(&[0.0, 0.0]).is_none());
+}
+
+#[test]
+fn test_vec3_helpers() {
+    let a = Vec3::new(-1, 2, 4);
+    let b = Vec3::new(3, 2, 0);
+    assert_eq!(a.lerp(b, 0.25), Vec3::new(0, 2, 3));
+    assert_eq!(a.clamp_components(0.0, 3.0), Vec3::new(0, 2, 3));
+    assert_eq!(a.max_component(), 4.0);
+    assert_eq!(a.abs(), Vec3::new(1, 2, 4));
+}
+
+#[test]
+fn test_unit_vector() {
+    let unit = Vec3::new(3, 0, 4).unit_vector();
+    assert!((unit - Vec3::new(0.6, 0, 0.8)).length() < 1e-12);
+    let zero = Vec3::new(0, 0, 0).unit_vector();
+    assert!(zero.x.is_finite() && zero.y.is_finite() && zero.z.is_finite());
+    assert_eq!(zero, Vec3::new(0, 1, 0));
+}
+
 #[test]
 fn test_cross_product() {
     assert_eq!(
@@ -354,3 +815,81 @@ fn test_line_plane() {
         }
     }
 }
+
+#[test]
+fn test_mat4_rotation() {
+    // a quarter turn about y takes x to -z, like RotateY
+    let rotated = Mat4::rotation_y(PI / 2.0).transform_point(Vec3::new(1, 0, 0));
+    assert!((rotated - Vec3::new(0, 0, -1)).near_zero());
+    let m = Mat4::translation(Vec3::new(1, 2, 3)) * Mat4::rotation_x(0.3);
+    let back = Mat4::rotation_x(-0.3) * Mat4::translation(Vec3::new(-1, -2, -3));
+    let p = Vec3::new(4, -5, 6);
+    assert!((back.transform_point(m.transform_point(p)) - p).near_zero());
+    let inverse = m.inverse().unwrap();
+    assert!((inverse.transform_point(p) - back.transform_point(p)).near_zero());
+    assert!(Mat4::scaling(Vec3::new(1, 0, 1)).inverse().is_none());
+    // a slope squashed in x gets steeper, so its normal leans further towards x
+    let squash = Mat4::scaling(Vec3::new(0.5, 1, 1));
+    let normal = squash.transform_normal(Vec3::new(1, 1, 0)).unit_vector();
+    assert!((normal - Vec3::new(2, 1, 0).unit_vector()).near_zero());
+    let mirror = Mat4::scaling(Vec3::new(-1, 1, 1));
+    assert!((mirror.transform_normal(Vec3::new(1, 0, 0)) - Vec3::new(-1, 0, 0)).near_zero());
+}
+
+#[test]
+fn test_quat() {
+    let q = Quat::from_axis_angle(Vec3::new(0, 1, 0), 0.7);
+    let m = Mat4::rotation_y(0.7);
+    let v = Vec3::new(1, 2, 3);
+    assert!((q.rotate(v) - m.transform_vector(v)).near_zero());
+    assert!((q.to_rotation_matrix().transform_vector(v) - m.transform_vector(v)).near_zero());
+    let euler = Quat::from_euler_yxz(0.7, 0.2, -0.4);
+    let m = Mat4::rotation_y(0.7) * Mat4::rotation_x(0.2) * Mat4::rotation_z(-0.4);
+    assert!((euler.rotate(v) - m.transform_vector(v)).near_zero());
+    let euler = Quat::from_euler_xyz(0.7, 0.2, -0.4);
+    let m = Mat4::rotation_z(-0.4) * Mat4::rotation_y(0.2) * Mat4::rotation_x(0.7);
+    assert!((euler.rotate(v) - m.transform_vector(v)).near_zero());
+    let none = Quat::from_axis_angle(Vec3::new(0, 0, 1), 0.0);
+    let halfway = none.slerp(Quat::from_axis_angle(Vec3::new(0, 0, 1), 1.0), 0.5);
+    let expected = Mat4::rotation_z(0.5).transform_vector(v);
+    assert!((halfway.rotate(v) - expected).near_zero());
+}
+
+#[test]
+fn test_random_cosine_direction() {
+    for _ in 0..100 {
+        let (direction, pdf) = random_cosine_direction();
+        assert!((direction.length() - 1.0).abs() < 1e-9 && direction.z >= 0.0);
+        assert!((pdf - direction.z / PI).abs() < 1e-12);
+    }
+}
+
+#[test]
+fn test_power_heuristic() {
+    let mut rng = random::rng();
+    for _ in 0..1000 {
+        let n_f = rng.gen_range(1..10) as f64;
+        let n_g = rng.gen_range(1..10) as f64;
+        let f_pdf = rng.gen_range(1e-6..100.0);
+        let g_pdf = rng.gen_range(1e-6..100.0);
+        assert!((power_heuristic(n_f, f_pdf, n_f, f_pdf) - 0.5).abs() < 1e-12);
+        let sum = power_heuristic(n_f, f_pdf, n_g, g_pdf) + power_heuristic(n_g, g_pdf, n_f, f_pdf);
+        assert!((sum - 1.0).abs() < 1e-12);
+    }
+    assert_eq!(power_heuristic(1.0, 0.0, 1.0, 0.0), 0.0);
+}
+
+#[test]
+fn test_onb() {
+    for w in &[
+        Vec3::new(0, 0, 1),
+        Vec3::new(0, 0, -1),
+        Vec3::new(1, 2, -3).unit_vector(),
+    ] {
+        let onb = Onb::from_w(*w);
+        assert!((onb.u.length() - 1.0).abs() < 1e-9 && (onb.v.length() - 1.0).abs() < 1e-9);
+        assert!(dot(onb.u, onb.v).abs() < 1e-9 && dot(onb.u, *w).abs() < 1e-9);
+        assert!((cross(onb.u, onb.v) - *w).near_zero());
+        assert!((onb.local(0.0, 0.0, 2.0) - 2.0 * *w).near_zero());
+    }
+}
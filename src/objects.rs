@@ -1,16 +1,32 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
+use gltf::image::Format;
+use gltf::mesh::Mode;
+use image::{GrayImage, ImageBuffer, Luma};
 use rand::Rng;
 use wavefront_obj::obj::{self, Primitive};
 
-use std::fs::read_to_string;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs::{read, read_to_string};
+use std::mem;
 use std::sync::Arc;
+use std::time::Instant;
 
 use crate::camera::{TIME_MAX, TIME_MIN};
-use crate::hitting::{surrounding_box, BVHNode, Colour, HitRecord, Hittable, Material, AABB};
-use crate::materials::{DiffuseLight, Lambertian};
+use crate::hitting::{
+    shared_memory_bytes, surrounding_box, BVHNode, Colour, Emitter, HitRecord, Hittable,
+    HittableList, Material, AABB,
+};
+use crate::materials::{ConeLight, DiffuseLight, Lambertian};
 use crate::math::{
-    cross, distance_to_sphere, dot, get_sphere_uv, line_plane_collision, Point3, Ray, Vec3,
+    coeff, cross, distance_to_sphere, dot, get_sphere_uv, line_plane_collision, random_in_cone,
+    Mat4, Point3, Ray, RayType, Vec3,
 };
+use crate::random;
+use crate::stats;
+use crate::textures::{ImageTexture, SolidColour, Texture};
 use crate::transforms::{RotateY, RotateZ, Translate};
 
 pub struct Sphere {
@@ -27,6 +43,12 @@ impl Sphere {
             material: Arc::clone(material),
         })
     }
+    // cosine of the angle from the centre to the edge, seen from distance_squared away
+    fn cos_theta_max(&self, distance_squared: f64) -> f64 {
+        (1.0 - self.radius * self.radius / distance_squared)
+            .max(0.0)
+            .sqrt()
+    }
 }
 
 impl Hittable for Sphere {
@@ -53,16 +75,45 @@ impl Hittable for Sphere {
             maximum: self.centre + Vec3::new(self.radius, self.radius, self.radius),
         })
     }
-    fn _print(&self) -> String {
-        format!(
+    fn collect_emitters(self: Arc<Self>, emitters: &mut Vec<Arc<dyn Emitter>>) {
+        if !self.material.average_emission().near_zero() {
+            emitters.push(self);
+        }
+    }
+}
+
+impl fmt::Display for Sphere {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
             "Sphere (centre: {}, radius: {}, material: {})",
-            self.centre,
-            self.radius,
-            self.material._print()
+            self.centre, self.radius, self.material
         )
     }
 }
 
+impl Emitter for Sphere {
+    // uniformly over the cone of directions the sphere covers
+    fn sample_towards(&self, from: Point3, time: f64) -> (Ray, f64, Colour) {
+        let to_centre = self.centre - from;
+        let cos_theta_max = self.cos_theta_max(to_centre.length_squared());
+        let direction = random_in_cone(to_centre, cos_theta_max);
+        let ray = Ray::new(from, direction, time).of_kind(RayType::Shadow);
+        let colour = self.emitted_along(&ray);
+        (ray, 1.0 / (2.0 * PI * (1.0 - cos_theta_max)), colour)
+    }
+    fn pdf_towards(&self, from: Point3, direction: Vec3) -> f64 {
+        if self
+            .hit(&Ray::new(from, direction, TIME_MIN), 0.001, f64::INFINITY)
+            .is_none()
+        {
+            return 0.0;
+        }
+        let cos_theta_max = self.cos_theta_max((self.centre - from).length_squared());
+        1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+    }
+}
+
 pub struct MovingSphere {
     centre0: Point3,
     centre1: Point3,
@@ -91,7 +142,10 @@ impl MovingSphere {
         })
     }
 
+    // holds still before time0 and after time1, so the bounding box for any
+    // window only covers where the sphere actually goes
     fn centre(&self, time: f64) -> Point3 {
+        let time = time.clamp(self.time0.min(self.time1), self.time0.max(self.time1));
         self.centre0
             + ((time - self.time0) / (self.time1 - self.time0)) * (self.centre1 - self.centre0)
     }
@@ -126,10 +180,14 @@ impl Hittable for MovingSphere {
         };
         Some(surrounding_box(&box0, &box1))
     }
-    fn _print(&self) -> String {
-        format!(
+}
+
+impl fmt::Display for MovingSphere {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
             "Moving Sphere (centre0: {:?}, centre1: {:?}, time0: {}, time1: {}, radius: {}, material: {})",
-            self.centre0, self.centre1, self.time0, self.time1, self.radius, self.material._print()
+            self.centre0, self.centre1, self.time0, self.time1, self.radius, self.material
         )
     }
 }
@@ -137,7 +195,7 @@ impl Hittable for MovingSphere {
 pub struct Block {
     minimum: Point3,
     maximum: Point3,
-    sides: Vec<Arc<dyn Hittable>>,
+    sides: HittableList,
 }
 
 impl Block {
@@ -146,34 +204,89 @@ impl Block {
         maximum: Point3,
         material: &Arc<dyn Material>,
     ) -> Arc<dyn Hittable> {
-        let sides = vec![
-            XYRect::new(
-                minimum.x, maximum.x, minimum.y, maximum.y, minimum.z, material, true,
-            ),
-            XYRect::new(
-                minimum.x, maximum.x, minimum.y, maximum.y, maximum.z, material, false,
-            ),
-            XZRect::new(
-                minimum.x, maximum.x, minimum.z, maximum.z, minimum.y, material, true,
-            ),
-            XZRect::new(
-                minimum.x, maximum.x, minimum.z, maximum.z, maximum.y, material, false,
-            ),
-            YZRect::new(
-                minimum.y, maximum.y, minimum.z, maximum.z, minimum.x, material, true,
-            ),
-            YZRect::new(
-                minimum.y, maximum.y, minimum.z, maximum.z, maximum.x, material, false,
-            ),
-        ];
         Arc::new(Block {
             minimum,
             maximum,
-            sides,
+            sides: HittableList(block_sides(minimum, maximum, [material; 6], false)),
+        })
+    }
+
+    // A block whose faces say which of them was hit in their UVs, for
+    // textures::BoxUnwrapTexture to wrap one texture around the whole block
+    pub fn unwrapped(
+        minimum: Point3,
+        maximum: Point3,
+        material: &Arc<dyn Material>,
+    ) -> Arc<dyn Hittable> {
+        Arc::new(Block {
+            minimum,
+            maximum,
+            sides: HittableList(block_sides(minimum, maximum, [material; 6], true)),
         })
     }
 }
 
+// A face of an unwrapped block adds 2 * (id + 1) to u, so the whole-number
+// part says which face it is, and textures that repeat are unaffected
+fn face_u(face_id: Option<u8>, u: f64) -> f64 {
+    face_id.map_or(u, |id| u + 2.0 * (id as f64 + 1.0))
+}
+
+// faces are in the order -z, +z, -y, +y, -x, +x, which are also their ids
+fn block_sides(
+    minimum: Point3,
+    maximum: Point3,
+    materials: [&Arc<dyn Material>; 6],
+    face_ids: bool,
+) -> Vec<Arc<dyn Hittable>> {
+    let id = |i| if face_ids { Some(i) } else { None };
+    let xy = |k, material: &Arc<dyn Material>, facing_positive, face_id| -> Arc<dyn Hittable> {
+        Arc::new(XYRect {
+            x0: minimum.x,
+            x1: maximum.x,
+            y0: minimum.y,
+            y1: maximum.y,
+            k,
+            material: Arc::clone(material),
+            facing_positive,
+            face_id,
+        })
+    };
+    let xz = |k, material: &Arc<dyn Material>, facing_positive, face_id| -> Arc<dyn Hittable> {
+        Arc::new(XZRect {
+            x0: minimum.x,
+            x1: maximum.x,
+            z0: minimum.z,
+            z1: maximum.z,
+            k,
+            material: Arc::clone(material),
+            facing_positive,
+            face_id,
+        })
+    };
+    let yz = |k, material: &Arc<dyn Material>, facing_positive, face_id| -> Arc<dyn Hittable> {
+        Arc::new(YZRect {
+            y0: minimum.y,
+            y1: maximum.y,
+            z0: minimum.z,
+            z1: maximum.z,
+            k,
+            material: Arc::clone(material),
+            facing_positive,
+            face_id,
+        })
+    };
+    let [back, front, bottom, top, left, right] = materials;
+    vec![
+        xy(minimum.z, back, true, id(0)),
+        xy(maximum.z, front, false, id(1)),
+        xz(minimum.y, bottom, true, id(2)),
+        xz(maximum.y, top, false, id(3)),
+        yz(minimum.x, left, true, id(4)),
+        yz(maximum.x, right, false, id(5)),
+    ]
+}
+
 impl Hittable for Block {
     fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
         self.sides.hit(ray, min_dist, max_dist)
@@ -184,8 +297,67 @@ impl Hittable for Block {
             maximum: self.maximum,
         })
     }
-    fn _print(&self) -> String {
-        format!("Block (min: {}, max: {})", self.minimum, self.maximum)
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + self.sides.estimated_memory_bytes(seen)
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Block (min: {}, max: {})", self.minimum, self.maximum)
+    }
+}
+
+// A block with a different material on each face, in the order
+// -z, +z, -y, +y, -x, +x
+pub struct MultiMaterialBlock {
+    minimum: Point3,
+    maximum: Point3,
+    sides: HittableList,
+}
+
+impl MultiMaterialBlock {
+    pub fn new(
+        minimum: Point3,
+        maximum: Point3,
+        materials: &[Arc<dyn Material>; 6],
+    ) -> Arc<dyn Hittable> {
+        let [back, front, bottom, top, left, right] = materials;
+        Arc::new(MultiMaterialBlock {
+            minimum,
+            maximum,
+            sides: HittableList(block_sides(
+                minimum,
+                maximum,
+                [back, front, bottom, top, left, right],
+                false,
+            )),
+        })
+    }
+}
+
+impl Hittable for MultiMaterialBlock {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        self.sides.hit(ray, min_dist, max_dist)
+    }
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        Some(AABB {
+            minimum: self.minimum,
+            maximum: self.maximum,
+        })
+    }
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + self.sides.estimated_memory_bytes(seen)
+    }
+}
+
+impl fmt::Display for MultiMaterialBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "MultiMaterialBlock (min: {}, max: {})",
+            self.minimum, self.maximum
+        )
     }
 }
 
@@ -197,6 +369,8 @@ pub struct XYRect {
     k: f64,
     material: Arc<dyn Material>,
     facing_positive: bool,
+    // set on the faces of an unwrapped block
+    face_id: Option<u8>,
 }
 
 impl XYRect {
@@ -217,6 +391,7 @@ impl XYRect {
             k: k.into(),
             material: Arc::clone(material),
             facing_positive,
+            face_id: None,
         })
     }
 }
@@ -232,7 +407,7 @@ impl Hittable for XYRect {
         if x < self.x0 || x > self.x1 || y < self.y0 || y > self.y1 {
             return None;
         }
-        let u = (x - self.x0) / (self.x1 - self.x0);
+        let u = face_u(self.face_id, (x - self.x0) / (self.x1 - self.x0));
         let v = (y - self.y0) / (self.y1 - self.y0);
         Some(HitRecord::new(
             ray,
@@ -248,8 +423,11 @@ impl Hittable for XYRect {
             maximum: Point3::new(self.x1, self.y1, self.k + 0.0001),
         })
     }
-    fn _print(&self) -> String {
-        String::from("rect")
+}
+
+impl fmt::Display for XYRect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rect")
     }
 }
 
@@ -261,6 +439,8 @@ pub struct XZRect {
     k: f64,
     material: Arc<dyn Material>,
     facing_positive: bool,
+    // set on the faces of an unwrapped block
+    face_id: Option<u8>,
 }
 
 impl XZRect {
@@ -281,6 +461,7 @@ impl XZRect {
             k: k.into(),
             material: Arc::clone(material),
             facing_positive,
+            face_id: None,
         })
     }
 }
@@ -296,7 +477,7 @@ impl Hittable for XZRect {
         if x < self.x0 || x > self.x1 || z < self.z0 || z > self.z1 {
             return None;
         }
-        let u = (x - self.x0) / (self.x1 - self.x0);
+        let u = face_u(self.face_id, (x - self.x0) / (self.x1 - self.x0));
         let v = (z - self.z0) / (self.z1 - self.z0);
         Some(HitRecord::new(
             ray,
@@ -312,8 +493,43 @@ impl Hittable for XZRect {
             maximum: Point3::new(self.x1, self.k + 0.0001, self.z1),
         })
     }
-    fn _print(&self) -> String {
-        String::from("rect")
+    fn collect_emitters(self: Arc<Self>, emitters: &mut Vec<Arc<dyn Emitter>>) {
+        if !self.material.average_emission().near_zero() {
+            emitters.push(self);
+        }
+    }
+}
+
+impl fmt::Display for XZRect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rect")
+    }
+}
+
+impl Emitter for XZRect {
+    // uniformly over the rectangle's area
+    fn sample_towards(&self, from: Point3, time: f64) -> (Ray, f64, Colour) {
+        let mut rng = random::rng();
+        let point = Point3::new(
+            rng.gen_range(self.x0..=self.x1),
+            self.k,
+            rng.gen_range(self.z0..=self.z1),
+        );
+        let direction = point - from;
+        let ray = Ray::new(from, direction, time).of_kind(RayType::Shadow);
+        let colour = self.emitted_along(&ray);
+        (ray, self.pdf_towards(from, direction), colour)
+    }
+    fn pdf_towards(&self, from: Point3, direction: Vec3) -> f64 {
+        let ray = Ray::new(from, direction, TIME_MIN);
+        match self.hit(&ray, 0.001, f64::INFINITY) {
+            Some(hit) => {
+                let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+                let cosine = ray.direction.y.abs();
+                hit.distance * hit.distance / (cosine * area)
+            }
+            None => 0.0,
+        }
     }
 }
 
@@ -325,6 +541,8 @@ pub struct YZRect {
     k: f64,
     material: Arc<dyn Material>,
     facing_positive: bool,
+    // set on the faces of an unwrapped block
+    face_id: Option<u8>,
 }
 
 impl YZRect {
@@ -345,6 +563,7 @@ impl YZRect {
             k: k.into(),
             material: Arc::clone(material),
             facing_positive,
+            face_id: None,
         })
     }
 }
@@ -360,7 +579,7 @@ impl Hittable for YZRect {
         if y < self.y0 || y > self.y1 || z < self.z0 || z > self.z1 {
             return None;
         }
-        let u = (y - self.y0) / (self.y1 - self.y0);
+        let u = face_u(self.face_id, (y - self.y0) / (self.y1 - self.y0));
         let v = (z - self.z0) / (self.z1 - self.z0);
         Some(HitRecord::new(
             ray,
@@ -376,8 +595,11 @@ impl Hittable for YZRect {
             maximum: Point3::new(self.k + 0.0001, self.y1, self.z1),
         })
     }
-    fn _print(&self) -> String {
-        String::from("rect")
+}
+
+impl fmt::Display for YZRect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rect")
     }
 }
 
@@ -401,60 +623,155 @@ impl ConstantMedium {
     }
 }
 
+// The stretch of the ray between min_dist and max_dist that's inside the
+// boundary, if any
+fn span_inside(
+    boundary: &dyn Hittable,
+    ray: &Ray,
+    min_dist: f64,
+    max_dist: f64,
+) -> Option<(f64, f64)> {
+    let entry = boundary.hit(ray, f64::NEG_INFINITY, f64::INFINITY)?;
+    let exit = boundary.hit(ray, entry.distance + 0.0001, f64::INFINITY)?;
+    let entry = entry.distance.max(min_dist).max(0.0);
+    let exit = exit.distance.min(max_dist);
+    if entry >= exit {
+        None
+    } else {
+        Some((entry, exit))
+    }
+}
+
+fn scattering_event(ray: &Ray, distance: f64, phase_function: &Arc<dyn Material>) -> HitRecord {
+    HitRecord {
+        distance,
+        intersection: ray.at(distance),
+        normal: Vec3::new(1, 0, 0), // arbitrary.
+        front_face: true,           // also arbitrary.
+        material: Arc::clone(phase_function),
+        surface_u: 0.0, // (u, v) is meaningless here
+        surface_v: 0.0, //
+    }
+}
+
 impl Hittable for ConstantMedium {
     fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
-        if let Some(mut hit1) = self.boundary.hit(ray, f64::NEG_INFINITY, f64::INFINITY) {
-            if let Some(mut hit2) = self
-                .boundary
-                .hit(ray, hit1.distance + 0.0001, f64::INFINITY)
-            {
-                if hit1.distance < min_dist {
-                    hit1.distance = min_dist;
-                }
-                if hit2.distance > max_dist {
-                    hit2.distance = max_dist;
-                }
-                if hit1.distance >= hit2.distance {
-                    None
-                } else {
-                    if hit1.distance < 0.0 {
-                        hit1.distance = 0.0;
-                    }
-                    let ray_length = ray.direction.length();
-                    let distance_inside_boundary = (hit2.distance - hit1.distance) * ray_length;
-                    let hit_distance = self.neg_inv_density
-                        * rand::thread_rng().gen_range::<f64, _>(0.0..1.0).ln();
-                    if hit_distance > distance_inside_boundary {
-                        None
-                    } else {
-                        let distance = hit1.distance + hit_distance / ray_length;
-                        Some(HitRecord {
-                            distance,
-                            intersection: ray.at(distance),
-                            normal: Vec3::new(1, 0, 0), // arbitrary.
-                            front_face: true,           // also arbitrary.
-                            material: Arc::clone(&self.phase_function),
-                            surface_u: 0.0, // (u, v) is meaningless here
-                            surface_v: 0.0, //
-                        })
+        let (entry, exit) = span_inside(self.boundary.as_ref(), ray, min_dist, max_dist)?;
+        let ray_length = ray.direction.length();
+        let distance_inside_boundary = (exit - entry) * ray_length;
+        let hit_distance = self.neg_inv_density * random::rng().gen_range::<f64, _>(0.0..1.0).ln();
+        if hit_distance > distance_inside_boundary {
+            None
+        } else {
+            let distance = entry + hit_distance / ray_length;
+            Some(scattering_event(ray, distance, &self.phase_function))
+        }
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        self.boundary.bounding_box(time0, time1)
+    }
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + shared_memory_bytes(&self.boundary, seen)
+    }
+}
+
+impl fmt::Display for ConstantMedium {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "constant medium (boundary: {}, NID: {}, phase function: {})",
+            self.boundary, self.neg_inv_density, self.phase_function,
+        )
+    }
+}
+
+// A medium whose density varies through space, taken from a texture (the mean
+// of its channels). Uses delta tracking: free-flight distances are drawn as if
+// the whole medium were at max_density, and each candidate is kept with
+// probability density / max_density.
+pub struct HeterogeneousMedium {
+    boundary: Arc<dyn Hittable>,
+    phase_function: Arc<dyn Material>,
+    density_texture: Arc<dyn Texture>,
+    max_density: f64,
+}
+
+impl HeterogeneousMedium {
+    // Without a max_density, it's estimated from a grid of samples over the
+    // boundary's box, which can miss small dense spots
+    pub fn new(
+        boundary: &Arc<dyn Hittable>,
+        phase_function: &Arc<dyn Material>,
+        density_texture: &Arc<dyn Texture>,
+        max_density: Option<f64>,
+    ) -> Arc<dyn Hittable> {
+        let max_density = max_density.unwrap_or_else(|| {
+            let bbox = match boundary.bounding_box(TIME_MIN, TIME_MAX) {
+                Some(bbox) => bbox,
+                None => return 0.0,
+            };
+            let steps = 16;
+            let mut max: f64 = 0.0;
+            for i in 0..=steps {
+                for j in 0..=steps {
+                    for k in 0..=steps {
+                        let t = Vec3::new(i, j, k) / steps as f64;
+                        let p = bbox.minimum + coeff(t, bbox.maximum - bbox.minimum);
+                        max = max.max(density_at(density_texture.as_ref(), p));
                     }
                 }
-            } else {
-                None
             }
-        } else {
-            None
+            max
+        });
+        Arc::new(HeterogeneousMedium {
+            boundary: Arc::clone(boundary),
+            phase_function: Arc::clone(phase_function),
+            density_texture: Arc::clone(density_texture),
+            max_density,
+        })
+    }
+}
+
+fn density_at(texture: &dyn Texture, p: Point3) -> f64 {
+    let colour = texture.value(0.0, 0.0, p, Vec3::new(1, 0, 0));
+    (colour.x + colour.y + colour.z) / 3.0
+}
+
+impl Hittable for HeterogeneousMedium {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        if self.max_density <= 0.0 {
+            return None;
+        }
+        let (entry, exit) = span_inside(self.boundary.as_ref(), ray, min_dist, max_dist)?;
+        let ray_length = ray.direction.length();
+        let mut rng = random::rng();
+        let mut distance = entry;
+        loop {
+            let step = -(1.0 - rng.gen_range::<f64, _>(0.0..1.0)).ln() / self.max_density;
+            distance += step / ray_length;
+            if distance >= exit {
+                return None;
+            }
+            let density = density_at(self.density_texture.as_ref(), ray.at(distance));
+            if rng.gen_range::<f64, _>(0.0..1.0) * self.max_density < density {
+                return Some(scattering_event(ray, distance, &self.phase_function));
+            }
         }
     }
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
         self.boundary.bounding_box(time0, time1)
     }
-    fn _print(&self) -> String {
-        format!(
-            "constant medium (boundary: {}, NID: {}, phase function: {})",
-            self.boundary._print(),
-            self.neg_inv_density,
-            self.phase_function._print(),
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + shared_memory_bytes(&self.boundary, seen)
+    }
+}
+
+impl fmt::Display for HeterogeneousMedium {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "heterogeneous medium (boundary: {}, max density: {}, phase function: {})",
+            self.boundary, self.max_density, self.phase_function,
         )
     }
 }
@@ -464,6 +781,12 @@ pub struct Triangle {
     vec1: Vec3,
     vec2: Vec3,
     normal: Vec3,
+    // per-vertex normals, for smooth shading
+    na: Option<Vec3>,
+    nb: Option<Vec3>,
+    nc: Option<Vec3>,
+    // texture coordinates of each vertex
+    uvs: Option<[(f64, f64); 3]>,
     bbox: AABB,
     material: Arc<dyn Material>,
 }
@@ -479,6 +802,10 @@ impl Triangle {
             vec1,
             vec2,
             normal,
+            na: None,
+            nb: None,
+            nc: None,
+            uvs: None,
             bbox: vertices_to_bbox(vec![a, b, c]),
             material: Arc::clone(material),
         })
@@ -488,6 +815,7 @@ impl Triangle {
         b: Point3,
         c: Point3,
         normal: Vec3,
+        uvs: Option<[(f64, f64); 3]>,
         material: &Arc<dyn Material>,
     ) -> Arc<dyn Hittable> {
         let vec1 = b - a;
@@ -503,6 +831,33 @@ impl Triangle {
             } else {
                 normal
             },
+            na: None,
+            nb: None,
+            nc: None,
+            uvs,
+            bbox: vertices_to_bbox(vec![a, b, c]),
+            material: Arc::clone(material),
+        })
+    }
+    pub fn with_vertex_normals(
+        (a, na): (Point3, Vec3),
+        (b, nb): (Point3, Vec3),
+        (c, nc): (Point3, Vec3),
+        uvs: Option<[(f64, f64); 3]>,
+        material: &Arc<dyn Material>,
+    ) -> Arc<dyn Hittable> {
+        let vec1 = b - a;
+        let vec2 = c - a;
+
+        Arc::new(Triangle {
+            point: a,
+            vec1,
+            vec2,
+            normal: cross(vec1, vec2).unit_vector(),
+            na: Some(na.unit_vector()),
+            nb: Some(nb.unit_vector()),
+            nc: Some(nc.unit_vector()),
+            uvs,
             bbox: vertices_to_bbox(vec![a, b, c]),
             material: Arc::clone(material),
         })
@@ -510,7 +865,7 @@ impl Triangle {
 }
 
 fn vertices_to_bbox(vertices: Vec<Vec3>) -> AABB {
-    let minimum = vertices.iter().fold(
+    let mut minimum = vertices.iter().fold(
         Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
         |acc, vec| {
             Vec3::new(
@@ -520,7 +875,7 @@ fn vertices_to_bbox(vertices: Vec<Vec3>) -> AABB {
             )
         },
     );
-    let maximum = vertices.iter().fold(
+    let mut maximum = vertices.iter().fold(
         Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
         |acc, vec| {
             Vec3::new(
@@ -530,6 +885,14 @@ fn vertices_to_bbox(vertices: Vec<Vec3>) -> AABB {
             )
         },
     );
+    // a box with no thickness never intersects a ray, so pad flat axes the
+    // same way the axis-aligned rects do
+    for axis in 0..3 {
+        if maximum[axis] - minimum[axis] < 0.0001 {
+            minimum[axis] -= 0.0001;
+            maximum[axis] += 0.0001;
+        }
+    }
     AABB { minimum, maximum }
 }
 
@@ -544,12 +907,26 @@ impl Hittable for Triangle {
             } else if solution[1] < 0.0 || solution[2] < 0.0 || solution[1] + solution[2] > 1.0 {
                 None
             } else {
+                let (u, v) = (solution[1], solution[2]);
+                let normal = if let (Some(na), Some(nb), Some(nc)) = (self.na, self.nb, self.nc) {
+                    ((1.0 - u - v) * na + u * nb + v * nc).unit_vector()
+                } else {
+                    self.normal
+                };
+                let uv = if let Some([ta, tb, tc]) = self.uvs {
+                    (
+                        (1.0 - u - v) * ta.0 + u * tb.0 + v * tc.0,
+                        (1.0 - u - v) * ta.1 + u * tb.1 + v * tc.1,
+                    )
+                } else {
+                    (v, u)
+                };
                 Some(HitRecord::new(
                     ray,
                     distance,
-                    self.normal,
+                    normal,
                     Arc::clone(&self.material),
-                    (solution[2], solution[1]),
+                    uv,
                 ))
             }
         } else {
@@ -559,86 +936,314 @@ impl Hittable for Triangle {
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
         Some(self.bbox)
     }
-    fn _print(&self) -> String {
-        format!("triangle ({}, {}, {})", self.point, self.vec1, self.vec2)
+}
+
+impl fmt::Display for Triangle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "triangle ({}, {}, {})", self.point, self.vec1, self.vec2)
     }
 }
 
-pub struct Plane {
-    point: Point3,
-    vec1: Vec3,
-    vec2: Vec3,
-    uv_repeat: f64,
-    normal: Vec3,
+// A bicubic Bezier surface. Rays are intersected numerically, by running
+// Newton's method from several points on a grid over the patch.
+pub struct BezierPatch {
+    control_points: [[Point3; 4]; 4],
     material: Arc<dyn Material>,
+    bbox: AABB,
 }
 
-impl Plane {
+// starting points per side for the Newton search
+const BEZIER_GRID: usize = 4;
+const BEZIER_ITERATIONS: usize = 12;
+const BEZIER_TOLERANCE: f64 = 1e-9;
+
+impl BezierPatch {
+    // control_points[i][j] is row i (along v), column j (along u)
     pub fn new(
-        a: Point3,
-        b: Point3,
-        c: Point3,
-        uv_repeat: f64,
+        control_points: [[Point3; 4]; 4],
         material: &Arc<dyn Material>,
     ) -> Arc<dyn Hittable> {
-        let vec1 = (b - a).unit_vector();
-        let normal = cross(vec1, c - a).unit_vector();
-        let vec2 = cross(normal, vec1);
-        Arc::new(Plane {
-            point: a,
-            vec1,
-            vec2,
-            uv_repeat,
-            normal,
+        // the patch lies inside the convex hull of its control points
+        let bbox = vertices_to_bbox(control_points.iter().flatten().copied().collect());
+        Arc::new(BezierPatch {
+            control_points,
             material: Arc::clone(material),
+            bbox,
         })
     }
+
+    // the point at (u, v), and its derivatives along u and v
+    fn evaluate(&self, u: f64, v: f64) -> (Point3, Vec3, Vec3) {
+        let (bu, du) = bernstein(u);
+        let (bv, dv) = bernstein(v);
+        let mut point = Point3::new(0, 0, 0);
+        let mut along_u = Vec3::new(0, 0, 0);
+        let mut along_v = Vec3::new(0, 0, 0);
+        for (i, row) in self.control_points.iter().enumerate() {
+            for (j, &p) in row.iter().enumerate() {
+                point += bv[i] * bu[j] * p;
+                along_u += bv[i] * du[j] * p;
+                along_v += dv[i] * bu[j] * p;
+            }
+        }
+        (point, along_u, along_v)
+    }
+
+    // solves P(u, v) = ray.at(t) starting from a guess, returning (t, u, v)
+    fn newton(&self, ray: &Ray, mut u: f64, mut v: f64) -> Option<(f64, f64, f64)> {
+        let (point, _, _) = self.evaluate(u, v);
+        let mut t = dot(point - ray.origin, ray.direction) / ray.direction.length_squared();
+        for _ in 0..BEZIER_ITERATIONS {
+            let (point, along_u, along_v) = self.evaluate(u, v);
+            let error = point - ray.at(t);
+            if error.length_squared() < BEZIER_TOLERANCE {
+                return if (0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v) {
+                    Some((t, u, v))
+                } else {
+                    None
+                };
+            }
+            // Cramer's rule on [along_u, along_v, -direction] * step = -error
+            let det = dot(along_u, cross(along_v, -ray.direction));
+            if det.abs() < 1e-12 {
+                return None;
+            }
+            u -= dot(error, cross(along_v, -ray.direction)) / det;
+            v -= dot(along_u, cross(error, -ray.direction)) / det;
+            t -= dot(along_u, cross(along_v, error)) / det;
+            // wandering far outside the patch means this start won't converge
+            if !(-1.0..=2.0).contains(&u) || !(-1.0..=2.0).contains(&v) {
+                return None;
+            }
+        }
+        None
+    }
 }
 
-impl Hittable for Plane {
+// cubic Bernstein polynomials at t, and their derivatives
+fn bernstein(t: f64) -> ([f64; 4], [f64; 4]) {
+    let s = 1.0 - t;
+    (
+        [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t],
+        [
+            -3.0 * s * s,
+            3.0 * s * s - 6.0 * t * s,
+            6.0 * t * s - 3.0 * t * t,
+            3.0 * t * t,
+        ],
+    )
+}
+
+impl Hittable for BezierPatch {
     fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
-        if let Some(solution) =
-            line_plane_collision(ray.origin, ray.direction, self.point, self.vec1, self.vec2)
-        {
-            let distance = solution[0];
-            if distance < min_dist || distance > max_dist {
-                None
-            } else {
-                let u = (solution[1] % self.uv_repeat) / self.uv_repeat;
-                let v = (-solution[2] % self.uv_repeat) / self.uv_repeat;
-                let u = if u < 0.0 { 1.0 + u } else { u };
-                let v = if v < 0.0 { 1.0 + v } else { v };
-                Some(HitRecord::new(
-                    ray,
-                    distance,
-                    self.normal,
-                    Arc::clone(&self.material),
-                    (u, v),
-                ))
+        if !self.bbox.intersects(ray, min_dist, max_dist) {
+            return None;
+        }
+        let mut closest: Option<(f64, f64, f64)> = None;
+        for i in 0..BEZIER_GRID {
+            for j in 0..BEZIER_GRID {
+                let u = (j as f64 + 0.5) / BEZIER_GRID as f64;
+                let v = (i as f64 + 0.5) / BEZIER_GRID as f64;
+                if let Some((t, u, v)) = self.newton(ray, u, v) {
+                    if t >= min_dist && t <= max_dist && closest.is_none_or(|c| t < c.0) {
+                        closest = Some((t, u, v));
+                    }
+                }
             }
-        } else {
-            None
         }
+        let (t, u, v) = closest?;
+        let (_, along_u, along_v) = self.evaluate(u, v);
+        Some(HitRecord::new(
+            ray,
+            t,
+            cross(along_u, along_v),
+            Arc::clone(&self.material),
+            (u, v),
+        ))
     }
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
-        None
+        Some(self.bbox)
     }
-    fn _print(&self) -> String {
-        format!("plane ({}, {}, {})", self.point, self.vec1, self.vec2)
+}
+
+impl fmt::Display for BezierPatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bezier patch ({} to {})",
+            self.control_points[0][0], self.control_points[3][3]
+        )
     }
 }
 
-pub struct Spotlight {
-    minimum: Point3,
-    maximum: Point3,
-    panes: Vec<Arc<dyn Hittable>>,
+// A signed distance function: negative inside the surface, positive outside
+pub type Sdf = Box<dyn Fn(Point3) -> f64 + Send + Sync>;
+
+// An implicit surface, found by sphere marching the ray through its bounding
+// box. The SDF must never overestimate the distance to the surface.
+pub struct SDFObject {
+    sdf: Sdf,
+    bbox: AABB,
+    material: Arc<dyn Material>,
+    max_steps: u32,
+    epsilon: f64,
 }
 
-impl Spotlight {
-    pub fn new_primitive(minimum: Point3, maximum: Point3, light: Colour) -> Arc<dyn Hittable> {
+impl SDFObject {
+    pub fn new(
+        sdf: Sdf,
+        bbox: AABB,
+        material: &Arc<dyn Material>,
+        max_steps: u32,
+        epsilon: f64,
+    ) -> Arc<dyn Hittable> {
+        Arc::new(SDFObject {
+            sdf,
+            bbox,
+            material: Arc::clone(material),
+            max_steps,
+            epsilon,
+        })
+    }
+
+    // central differences
+    fn gradient(&self, p: Point3) -> Vec3 {
+        let h = self.epsilon;
+        let axis = |offset: Vec3| (self.sdf)(p + offset) - (self.sdf)(p - offset);
+        Vec3::new(
+            axis(Vec3::new(h, 0, 0)),
+            axis(Vec3::new(0, h, 0)),
+            axis(Vec3::new(0, 0, h)),
+        )
+    }
+}
+
+impl Hittable for SDFObject {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        let (start, end) = self.bbox.span(ray, min_dist, max_dist)?;
+        // the SDF is in world units, but t is in units of the ray's direction
+        let speed = ray.direction.length();
+        let mut t = start;
+        for _ in 0..self.max_steps {
+            // the absolute value lets rays that start inside find their way out
+            let distance = (self.sdf)(ray.at(t)).abs();
+            if distance < self.epsilon {
+                let normal = self.gradient(ray.at(t));
+                let (u, v) = get_sphere_uv(normal.unit_vector());
+                return Some(HitRecord::new(
+                    ray,
+                    t,
+                    normal,
+                    Arc::clone(&self.material),
+                    (u, v),
+                ));
+            }
+            t += distance / speed;
+            if t > end {
+                return None;
+            }
+        }
+        None
+    }
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        Some(self.bbox)
+    }
+}
+
+impl fmt::Display for SDFObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sdf object (min: {}, max: {})",
+            self.bbox.minimum, self.bbox.maximum
+        )
+    }
+}
+
+// Has no bounding box, so BVHNode keeps it in a plain list beside the tree
+pub struct InfinitePlane {
+    point: Point3,
+    vec1: Vec3,
+    vec2: Vec3,
+    uv_repeat: f64,
+    normal: Vec3,
+    material: Arc<dyn Material>,
+}
+
+impl InfinitePlane {
+    pub fn new(
+        a: Point3,
+        b: Point3,
+        c: Point3,
+        uv_repeat: f64,
+        material: &Arc<dyn Material>,
+    ) -> Arc<dyn Hittable> {
+        let vec1 = (b - a).unit_vector();
+        let normal = cross(vec1, c - a).unit_vector();
+        let vec2 = cross(normal, vec1);
+        Arc::new(InfinitePlane {
+            point: a,
+            vec1,
+            vec2,
+            uv_repeat,
+            normal,
+            material: Arc::clone(material),
+        })
+    }
+}
+
+impl Hittable for InfinitePlane {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        if let Some(solution) =
+            line_plane_collision(ray.origin, ray.direction, self.point, self.vec1, self.vec2)
+        {
+            let distance = solution[0];
+            if distance < min_dist || distance > max_dist {
+                None
+            } else {
+                let u = solution[1].rem_euclid(self.uv_repeat) / self.uv_repeat;
+                let v = (-solution[2]).rem_euclid(self.uv_repeat) / self.uv_repeat;
+                Some(HitRecord::new(
+                    ray,
+                    distance,
+                    self.normal,
+                    Arc::clone(&self.material),
+                    (u, v),
+                ))
+            }
+        } else {
+            None
+        }
+    }
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        None
+    }
+}
+
+impl fmt::Display for InfinitePlane {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "infinite plane ({}, {}, {})",
+            self.point, self.vec1, self.vec2
+        )
+    }
+}
+
+pub struct Spotlight {
+    minimum: Point3,
+    maximum: Point3,
+    panes: HittableList,
+}
+
+impl Spotlight {
+    pub fn new_primitive(
+        minimum: Point3,
+        maximum: Point3,
+        light: &Arc<dyn Material>,
+    ) -> Arc<dyn Hittable> {
         let dark = Lambertian::with_colour(Colour::new(0, 0, 0));
         //let dark = Lambertian::with_colour(Colour::new(0.3, 0.3, 0.3));
-        let light = DiffuseLight::with_colour(light);
         let panes = vec![
             XYRect::new(
                 minimum.x, maximum.x, minimum.y, maximum.y, minimum.z, &dark, true,
@@ -647,7 +1252,7 @@ impl Spotlight {
                 minimum.x, maximum.x, minimum.y, maximum.y, maximum.z, &dark, false,
             ),
             XZRect::new(
-                minimum.x, maximum.x, minimum.z, maximum.z, minimum.y, &light, true,
+                minimum.x, maximum.x, minimum.z, maximum.z, minimum.y, light, true,
             ),
             YZRect::new(
                 minimum.y, maximum.y, minimum.z, maximum.z, minimum.x, &dark, true,
@@ -659,7 +1264,7 @@ impl Spotlight {
         Arc::new(Spotlight {
             minimum,
             maximum,
-            panes,
+            panes: HittableList(panes),
         })
     }
     pub fn new(
@@ -668,13 +1273,21 @@ impl Spotlight {
         length: f64,
         width: f64,
         light: Colour,
+        cone_angle_degrees: f64,
+        penumbra_angle_degrees: f64,
     ) -> Arc<dyn Hittable> {
+        let direction = looking_at - looking_from;
+        let axis = if direction.length() != 0.0 {
+            direction
+        } else {
+            Vec3::new(0, 1, 0)
+        };
+        let light = ConeLight::new(light, axis, cone_angle_degrees, penumbra_angle_degrees);
         let spotlight = Spotlight::new_primitive(
             Point3::new(-width / 2.0, -length, -width / 2.0),
             Point3::new(width / 2.0, 0, width / 2.0),
-            light,
+            &light,
         );
-        let direction = looking_at - looking_from;
         let direction_x = Vec3::new(direction.x, 0, 0);
         let direction_z = Vec3::new(0, 0, direction.z);
         // theta1 is the amount to rotate around the z axis
@@ -711,8 +1324,157 @@ impl Hittable for Spotlight {
             maximum: self.maximum,
         })
     }
-    fn _print(&self) -> String {
-        format!("spotlight ({}, {})", self.minimum, self.maximum)
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + self.panes.estimated_memory_bytes(seen)
+    }
+}
+
+impl fmt::Display for Spotlight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "spotlight ({}, {})", self.minimum, self.maximum)
+    }
+}
+
+// Small glowing sphere. Its brightness is divided by radius^2, which keeps the
+// light it gives off the same however small it's made.
+pub struct PointLight {
+    sphere: Arc<dyn Hittable>,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, colour: Colour, radius: f64) -> Arc<dyn Hittable> {
+        let light = DiffuseLight::with_colour(colour / (radius * radius), 1.0, false);
+        Arc::new(PointLight {
+            sphere: Sphere::new(position, radius, &light),
+        })
+    }
+}
+
+impl Hittable for PointLight {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        self.sphere.hit(ray, min_dist, max_dist)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        self.sphere.bounding_box(time0, time1)
+    }
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + shared_memory_bytes(&self.sphere, seen)
+    }
+    fn collect_emitters(self: Arc<Self>, emitters: &mut Vec<Arc<dyn Emitter>>) {
+        Arc::clone(&self.sphere).collect_emitters(emitters);
+    }
+}
+
+impl fmt::Display for PointLight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "point light ({})", self.sphere)
+    }
+}
+
+// far enough that anything in the scene is in front of it
+const DIRECTIONAL_LIGHT_DISTANCE: f64 = 1e12;
+
+// Light so far away that only its direction matters, like the sun. Any ray
+// pointing within angular_radius of it hits it, behind everything else.
+pub struct DirectionalLight {
+    direction: Vec3,
+    cos_angular_radius: f64,
+    material: Arc<dyn Material>,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, colour: Colour, angular_radius_degrees: f64) -> Arc<dyn Hittable> {
+        Arc::new(DirectionalLight {
+            direction: direction.unit_vector(),
+            cos_angular_radius: angular_radius_degrees.to_radians().cos(),
+            material: DiffuseLight::with_colour(colour, 1.0, false),
+        })
+    }
+}
+
+impl Hittable for DirectionalLight {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        if dot(ray.direction, self.direction) < self.cos_angular_radius
+            || DIRECTIONAL_LIGHT_DISTANCE < min_dist
+            || DIRECTIONAL_LIGHT_DISTANCE > max_dist
+        {
+            return None;
+        }
+        Some(HitRecord::new(
+            ray,
+            DIRECTIONAL_LIGHT_DISTANCE,
+            -self.direction,
+            Arc::clone(&self.material),
+            (0.0, 0.0),
+        ))
+    }
+    // it has no place in the BVH, which keeps it in its list of unbounded objects
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        None
+    }
+    // with no size at all, neither scattered rays nor sampling could find it
+    fn collect_emitters(self: Arc<Self>, emitters: &mut Vec<Arc<dyn Emitter>>) {
+        if self.cos_angular_radius < 1.0 {
+            emitters.push(self);
+        }
+    }
+}
+
+impl fmt::Display for DirectionalLight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "directional light ({})", self.direction)
+    }
+}
+
+impl Emitter for DirectionalLight {
+    // uniformly over the cone of directions it covers, which is the same from anywhere
+    fn sample_towards(&self, from: Point3, time: f64) -> (Ray, f64, Colour) {
+        let direction = random_in_cone(self.direction, self.cos_angular_radius);
+        let ray = Ray::new(from, direction, time).of_kind(RayType::Shadow);
+        let colour = self.emitted_along(&ray);
+        (ray, self.pdf_towards(from, direction), colour)
+    }
+    fn pdf_towards(&self, _from: Point3, direction: Vec3) -> f64 {
+        if dot(direction.unit_vector(), self.direction) < self.cos_angular_radius {
+            0.0
+        } else {
+            1.0 / (2.0 * PI * (1.0 - self.cos_angular_radius))
+        }
+    }
+}
+
+// Several objects sharing a BVH of their own, which sits in the scene's BVH
+// as one object
+pub struct ObjectGroup {
+    bvh: Arc<dyn Hittable>,
+}
+
+impl ObjectGroup {
+    pub fn new(members: Vec<Arc<dyn Hittable>>) -> Result<Arc<dyn Hittable>> {
+        Ok(Arc::new(ObjectGroup {
+            bvh: BVHNode::from_vec(members, TIME_MIN, TIME_MAX)?,
+        }))
+    }
+}
+
+impl Hittable for ObjectGroup {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        self.bvh.hit(ray, min_dist, max_dist)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        self.bvh.bounding_box(time0, time1)
+    }
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + shared_memory_bytes(&self.bvh, seen)
+    }
+    fn collect_emitters(self: Arc<Self>, emitters: &mut Vec<Arc<dyn Emitter>>) {
+        Arc::clone(&self.bvh).collect_emitters(emitters);
+    }
+}
+
+impl fmt::Display for ObjectGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "group: {}", self.bvh)
     }
 }
 
@@ -720,6 +1482,7 @@ pub fn load_mesh(
     filename: &str,
     name: &str,
     material: &Arc<dyn Material>,
+    smooth_normals: bool,
 ) -> Result<Arc<dyn Hittable>> {
     let obj_file = read_to_string(filename)?;
     let objects = obj::parse(&obj_file)?.objects;
@@ -734,37 +1497,400 @@ pub fn load_mesh(
         object_names,
     ))?;
     let v = object.vertices;
+    let tex_vertices = object.tex_vertices;
     let normals = object.normals;
     let mut triangles = Vec::new();
     for g in object.geometry {
         for s in g.shapes {
             if let Primitive::Triangle(a, b, c) = s.primitive {
-                if let Some(normal) = a.2.or(b.2).or(c.2) {
-                    triangles.push(Triangle::with_normal(
-                        Point3::new(v[a.0].x, v[a.0].y, v[a.0].z),
-                        Point3::new(v[b.0].x, v[b.0].y, v[b.0].z),
-                        Point3::new(v[c.0].x, v[c.0].y, v[c.0].z),
-                        Vec3::new(normals[normal].x, normals[normal].y, normals[normal].z),
+                let uvs =
+                    a.1.and_then(|ta| Some((ta, b.1?, c.1?)))
+                        .map(|(ta, tb, tc)| {
+                            let uv = |t: usize| (tex_vertices[t].u, tex_vertices[t].v);
+                            [uv(ta), uv(tb), uv(tc)]
+                        });
+                let vertex_normals = if smooth_normals {
+                    a.2.and_then(|na| Some((na, b.2?, c.2?)))
+                } else {
+                    None
+                };
+                if let Some((na, nb, nc)) = vertex_normals {
+                    let normal = |n: usize| Vec3::new(normals[n].x, normals[n].y, normals[n].z);
+                    triangles.push(Triangle::with_vertex_normals(
+                        (Point3::new(v[a.0].x, v[a.0].y, v[a.0].z), normal(na)),
+                        (Point3::new(v[b.0].x, v[b.0].y, v[b.0].z), normal(nb)),
+                        (Point3::new(v[c.0].x, v[c.0].y, v[c.0].z), normal(nc)),
+                        uvs,
                         material,
                     ));
                 } else {
-                    triangles.push(Triangle::new(
-                        Point3::new(v[a.0].x, v[a.0].y, v[a.0].z),
-                        Point3::new(v[b.0].x, v[b.0].y, v[b.0].z),
-                        Point3::new(v[c.0].x, v[c.0].y, v[c.0].z),
+                    let points = [a.0, b.0, c.0].map(|i| Point3::new(v[i].x, v[i].y, v[i].z));
+                    let normal = if let Some(n) = a.2.or(b.2).or(c.2) {
+                        Vec3::new(normals[n].x, normals[n].y, normals[n].z)
+                    } else {
+                        cross(points[1] - points[0], points[2] - points[0]).unit_vector()
+                    };
+                    triangles.push(Triangle::with_normal(
+                        points[0], points[1], points[2], normal, uvs, material,
+                    ));
+                }
+            }
+        }
+    }
+    let bvh_start = Instant::now();
+    let bvh = BVHNode::from_vec(triangles, TIME_MIN, TIME_MAX)?;
+    stats::BVH_BUILD_NANOS.add(bvh_start.elapsed().as_nanos() as u64);
+    Ok(bvh)
+}
+
+// Reads ASCII or binary STL. Normals in the file are ignored, since they're
+// often left as zero, and recomputed from the vertices instead.
+pub fn load_stl(filename: &str, material: &Arc<dyn Material>) -> Result<Arc<dyn Hittable>> {
+    let data = read(filename)?;
+    let vertices = if is_binary_stl(&data) {
+        read_binary_stl(&data)
+    } else {
+        read_ascii_stl(std::str::from_utf8(&data)?)
+            .map_err(|e| anyhow!("Reading STL file {}: {}", filename, e))?
+    };
+    if vertices.is_empty() {
+        bail!("No triangles in {}", filename);
+    }
+    let triangles = vertices
+        .chunks_exact(3)
+        .map(|v| Triangle::new(v[0], v[1], v[2], material))
+        .collect();
+    let bvh_start = Instant::now();
+    let bvh = BVHNode::from_vec(triangles, TIME_MIN, TIME_MAX)?;
+    stats::BVH_BUILD_NANOS.add(bvh_start.elapsed().as_nanos() as u64);
+    Ok(bvh)
+}
+
+// Binary files can start with "solid" too, so go by whether the size matches
+// the triangle count in the header
+fn is_binary_stl(data: &[u8]) -> bool {
+    if data.len() < 84 {
+        return false;
+    }
+    let count = u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize;
+    data.len() == 84 + 50 * count
+}
+
+// 80 byte header, u32 triangle count, then for each triangle a normal and
+// three vertices as f32s and two bytes of attributes
+fn read_binary_stl(data: &[u8]) -> Vec<Point3> {
+    let float = |offset: usize| f32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+    let mut vertices = Vec::new();
+    for facet in (84..data.len()).step_by(50) {
+        for vertex in 1..4 {
+            let offset = facet + 12 * vertex;
+            vertices.push(Point3::new(
+                float(offset),
+                float(offset + 4),
+                float(offset + 8),
+            ));
+        }
+    }
+    vertices
+}
+
+fn read_ascii_stl(text: &str) -> Result<Vec<Point3>> {
+    let mut vertices = Vec::new();
+    let mut tokens = text.split_whitespace();
+    while let Some(token) = tokens.next() {
+        if token == "vertex" {
+            let mut coordinate = || -> Result<f64> {
+                let token = tokens
+                    .next()
+                    .ok_or_else(|| anyhow!("File ends mid-vertex"))?;
+                token
+                    .parse()
+                    .map_err(|_| anyhow!("Expected a number, found {}", token))
+            };
+            vertices.push(Point3::new(coordinate()?, coordinate()?, coordinate()?));
+        }
+    }
+    if vertices.len() % 3 != 0 {
+        bail!(
+            "Found {} vertices, which isn't whole triangles",
+            vertices.len()
+        );
+    }
+    Ok(vertices)
+}
+
+// Terrain from a greyscale image. Each pixel is a vertex, spread evenly
+// between the corners, with black at the bottom and white at the top.
+pub struct HeightField {
+    mesh: Arc<dyn Hittable>,
+    bbox: AABB,
+}
+
+impl HeightField {
+    pub fn new(
+        image: &GrayImage,
+        minimum: Point3,
+        maximum: Point3,
+        material: &Arc<dyn Material>,
+    ) -> Result<Arc<dyn Hittable>> {
+        let (width, height) = image.dimensions();
+        if width < 2 || height < 2 {
+            bail!(
+                "A height field needs at least 2x2 pixels, not {}x{}",
+                width,
+                height
+            );
+        }
+        let uv = |i: u32, j: u32| {
+            (
+                i as f64 / (width - 1) as f64,
+                j as f64 / (height - 1) as f64,
+            )
+        };
+        let vertex = |(i, j): (u32, u32)| {
+            let (u, v) = uv(i, j);
+            let Luma([luma]) = *image.get_pixel(i, j);
+            let y = luma as f64 / 255.0;
+            Point3::new(
+                minimum.x + u * (maximum.x - minimum.x),
+                minimum.y + y * (maximum.y - minimum.y),
+                minimum.z + v * (maximum.z - minimum.z),
+            )
+        };
+        let mut triangles = Vec::with_capacity(2 * (width * height) as usize);
+        for j in 0..height - 1 {
+            for i in 0..width - 1 {
+                // two triangles per quad, wound so the normals point up
+                let corners = [(i, j), (i, j + 1), (i + 1, j), (i + 1, j + 1)];
+                for &[a, b, c] in &[[0, 1, 2], [2, 1, 3]] {
+                    let [a, b, c] = [corners[a], corners[b], corners[c]];
+                    let [pa, pb, pc] = [vertex(a), vertex(b), vertex(c)];
+                    triangles.push(Triangle::with_normal(
+                        pa,
+                        pb,
+                        pc,
+                        cross(pb - pa, pc - pa).unit_vector(),
+                        Some([uv(a.0, a.1), uv(b.0, b.1), uv(c.0, c.1)]),
                         material,
                     ));
                 }
             }
         }
+        let bvh_start = Instant::now();
+        let mesh = BVHNode::from_vec(triangles, TIME_MIN, TIME_MAX)?;
+        stats::BVH_BUILD_NANOS.add(bvh_start.elapsed().as_nanos() as u64);
+        Ok(Arc::new(HeightField {
+            mesh,
+            bbox: vertices_to_bbox(vec![minimum, maximum]),
+        }))
+    }
+    pub fn from_file(
+        filename: &str,
+        minimum: Point3,
+        maximum: Point3,
+        material: &Arc<dyn Material>,
+    ) -> Result<Arc<dyn Hittable>> {
+        let image = image::io::Reader::open(filename)?.decode()?.into_luma8();
+        Self::new(&image, minimum, maximum, material)
+            .map_err(|e| anyhow!("Reading height field {}: {}", filename, e))
+    }
+}
+
+impl Hittable for HeightField {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        self.mesh.hit(ray, min_dist, max_dist)
+    }
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        Some(self.bbox)
+    }
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + shared_memory_bytes(&self.mesh, seen)
+    }
+}
+
+impl fmt::Display for HeightField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "height field (min: {}, max: {})",
+            self.bbox.minimum, self.bbox.maximum
+        )
+    }
+}
+
+// Loads every mesh in the file's scene. Without a material, each primitive
+// gets a Lambertian one made from its base colour, or base colour texture.
+pub fn load_gltf(
+    filename: &str,
+    material: Option<&Arc<dyn Material>>,
+) -> Result<Arc<dyn Hittable>> {
+    let (document, buffers, images) = gltf::import(filename)?;
+    let mut gltf_file = GltfFile {
+        buffers,
+        images,
+        material: material.map(Arc::clone),
+        materials: HashMap::new(),
+        triangles: Vec::new(),
+    };
+    if let Some(scene) = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        for node in scene.nodes() {
+            gltf_file.add_node(&node, Mat4::identity())?;
+        }
+    } else {
+        for mesh in document.meshes() {
+            gltf_file.add_mesh(&mesh, Mat4::identity())?;
+        }
+    }
+    if gltf_file.triangles.is_empty() {
+        bail!("No triangles in {}", filename);
+    }
+    let bvh_start = Instant::now();
+    let bvh = BVHNode::from_vec(gltf_file.triangles, TIME_MIN, TIME_MAX)?;
+    stats::BVH_BUILD_NANOS.add(bvh_start.elapsed().as_nanos() as u64);
+    Ok(bvh)
+}
+
+struct GltfFile {
+    buffers: Vec<gltf::buffer::Data>,
+    images: Vec<gltf::image::Data>,
+    material: Option<Arc<dyn Material>>,
+    // materials made so far, by index in the file
+    materials: HashMap<Option<usize>, Arc<dyn Material>>,
+    triangles: Vec<Arc<dyn Hittable>>,
+}
+
+impl GltfFile {
+    fn add_node(&mut self, node: &gltf::Node, parent: Mat4) -> Result<()> {
+        // gltf matrices are column-major
+        let columns = node.transform().matrix();
+        let mut local = Mat4::identity();
+        for (i, column) in columns.iter().enumerate() {
+            for (j, value) in column.iter().enumerate() {
+                local.m[j][i] = *value as f64;
+            }
+        }
+        let transform = parent * local;
+        if let Some(mesh) = node.mesh() {
+            self.add_mesh(&mesh, transform)?;
+        }
+        for child in node.children() {
+            self.add_node(&child, transform)?;
+        }
+        Ok(())
+    }
+
+    fn add_mesh(&mut self, mesh: &gltf::Mesh, transform: Mat4) -> Result<()> {
+        for primitive in mesh.primitives() {
+            if primitive.mode() != Mode::Triangles {
+                continue;
+            }
+            let material = self.material_for(&primitive.material())?;
+            let buffers = &self.buffers;
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let positions = reader
+                .read_positions()
+                .ok_or_else(|| anyhow!("Mesh primitive without positions"))?
+                .map(|[x, y, z]| transform.transform_point(Point3::new(x, y, z)))
+                .collect::<Vec<Point3>>();
+            let normals = reader.read_normals().map(|normals| {
+                normals
+                    .map(|[x, y, z]| transform.transform_normal(Vec3::new(x, y, z)))
+                    .collect::<Vec<Vec3>>()
+            });
+            // gltf puts v = 0 at the top of the image, and ImageTexture at the bottom
+            let uvs = reader.read_tex_coords(0).map(|uvs| {
+                uvs.into_f32()
+                    .map(|[u, v]| (u as f64, 1.0 - v as f64))
+                    .collect::<Vec<(f64, f64)>>()
+            });
+            let indices = if let Some(indices) = reader.read_indices() {
+                indices.into_u32().map(|i| i as usize).collect()
+            } else {
+                (0..positions.len()).collect::<Vec<usize>>()
+            };
+            // a broken file shouldn't be able to index past the end of its own accessors
+            if let Some(i) = indices.iter().find(|&&i| i >= positions.len()) {
+                bail!(
+                    "Mesh primitive index {} is past its {} vertices",
+                    i,
+                    positions.len()
+                );
+            }
+            if normals.as_ref().is_some_and(|n| n.len() != positions.len()) {
+                bail!("Mesh primitive has a different number of normals and positions");
+            }
+            if uvs.as_ref().is_some_and(|uvs| uvs.len() != positions.len()) {
+                bail!("Mesh primitive has a different number of texture coordinates and positions");
+            }
+            for face in indices.chunks_exact(3) {
+                let (a, b, c) = (face[0], face[1], face[2]);
+                let face_uvs = uvs.as_ref().map(|uvs| [uvs[a], uvs[b], uvs[c]]);
+                self.triangles.push(if let Some(normals) = &normals {
+                    Triangle::with_vertex_normals(
+                        (positions[a], normals[a]),
+                        (positions[b], normals[b]),
+                        (positions[c], normals[c]),
+                        face_uvs,
+                        &material,
+                    )
+                } else {
+                    let normal = cross(positions[b] - positions[a], positions[c] - positions[a]);
+                    Triangle::with_normal(
+                        positions[a],
+                        positions[b],
+                        positions[c],
+                        normal.unit_vector(),
+                        face_uvs,
+                        &material,
+                    )
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn material_for(&mut self, material: &gltf::Material) -> Result<Arc<dyn Material>> {
+        if let Some(m) = &self.material {
+            return Ok(Arc::clone(m));
+        }
+        if let Some(m) = self.materials.get(&material.index()) {
+            return Ok(Arc::clone(m));
+        }
+        let pbr = material.pbr_metallic_roughness();
+        let texture: Arc<dyn Texture> = if let Some(info) = pbr.base_color_texture() {
+            let image = &self.images[info.texture().source().index()];
+            let pixels = match image.format {
+                Format::R8G8B8 => image.pixels.clone(),
+                Format::R8G8B8A8 => image
+                    .pixels
+                    .chunks_exact(4)
+                    .flat_map(|p| p[..3].iter().copied())
+                    .collect(),
+                format => bail!("Unsupported gltf texture format {:?}", format),
+            };
+            let image = ImageBuffer::from_raw(image.width, image.height, pixels)
+                .ok_or_else(|| anyhow!("Bad gltf texture data"))?;
+            Arc::new(ImageTexture::new(image, true))
+        } else {
+            let [r, g, b, _] = pbr.base_color_factor();
+            Arc::new(SolidColour {
+                colour: Colour::new(r, g, b),
+            })
+        };
+        let lambertian = Lambertian::with_texture(&texture);
+        self.materials
+            .insert(material.index(), Arc::clone(&lambertian));
+        Ok(lambertian)
     }
-    Ok(BVHNode::from_vec(triangles, TIME_MIN, TIME_MAX))
 }
 
 #[test]
 fn plane_test() {
     let material = Lambertian::with_colour(Colour::new(1, 1, 1));
-    let plane = Plane::new(
+    let plane = InfinitePlane::new(
         Point3::new(0, 0, 0),
         Point3::new(0, 0, 1),
         Point3::new(1, 0, 0),
@@ -773,4 +1899,305 @@ fn plane_test() {
     );
     let r1 = Ray::new(Point3::new(0, 5, 0), Vec3::new(-1.3, -1, 1), 0.0);
     dbg!(plane.hit(&r1, 0.0, f64::INFINITY));
+    // uv wraps into [0, 1) on both sides of the origin
+    for &(x, z) in &[(-7.5, -2.5), (7.5, 2.5), (-5.0, 0.5), (12.5, -12.5)] {
+        let ray = Ray::new(Point3::new(x, 5, z), Vec3::new(0, -1, 0), 0.0);
+        let hit = plane.hit(&ray, 0.0, f64::INFINITY).unwrap();
+        assert!((0.0..1.0).contains(&hit.surface_u), "u = {}", hit.surface_u);
+        assert!((0.0..1.0).contains(&hit.surface_v), "v = {}", hit.surface_v);
+    }
+}
+
+#[test]
+fn moving_sphere_bounding_box_test() {
+    let material = Lambertian::with_colour(Colour::new(1, 1, 1));
+    let sphere = MovingSphere::new(
+        Point3::new(0, 0, 0),
+        Point3::new(10, 0, 0),
+        0.0,
+        1.0,
+        1.0,
+        &material,
+    );
+    let bbox = sphere.bounding_box(0.2, 0.4).unwrap();
+    assert!((bbox.minimum.x - 1.0).abs() < 1e-9);
+    assert!((bbox.maximum.x - 5.0).abs() < 1e-9);
+    // the sphere stops at centre1, however long after time1 the window goes on
+    let bbox = sphere.bounding_box(0.5, 2.0).unwrap();
+    assert!((bbox.minimum.x - 4.0).abs() < 1e-9);
+    assert!((bbox.maximum.x - 11.0).abs() < 1e-9);
+    let ray = Ray::new(Point3::new(10, 5, 0), Vec3::new(0, -1, 0), 2.0);
+    assert!(sphere.hit(&ray, 0.0, f64::INFINITY).is_some());
+}
+
+#[test]
+fn infinite_plane_bvh_test() {
+    let material = Lambertian::with_colour(Colour::new(1, 1, 1));
+    let floor = InfinitePlane::new(
+        Point3::new(0, 0, 0),
+        Point3::new(0, 0, 1),
+        Point3::new(1, 0, 0),
+        5.0,
+        &material,
+    );
+    assert!(floor.bounding_box(TIME_MIN, TIME_MAX).is_none());
+    // nothing but unbounded objects still makes a world, but nothing at all doesn't
+    let only_floor = BVHNode::from_vec(vec![Arc::clone(&floor)], TIME_MIN, TIME_MAX).unwrap();
+    let ray = Ray::new(Point3::new(0, 5, 0), Vec3::new(0, -1, 0), 0.0);
+    assert!(only_floor.hit(&ray, 0.001, f64::INFINITY).is_some());
+    assert!(BVHNode::from_vec(Vec::new(), TIME_MIN, TIME_MAX).is_err());
+    let world = BVHNode::from_vec(
+        vec![
+            Sphere::new(Point3::new(-3, 1, 0), 1.0, &material),
+            floor,
+            Sphere::new(Point3::new(3, 1, 0), 1.0, &material),
+        ],
+        TIME_MIN,
+        TIME_MAX,
+    )
+    .unwrap();
+    // far from either sphere, only the plane can be hit
+    let ray = Ray::new(Point3::new(100, 5, 100), Vec3::new(0, -1, 0), 0.0);
+    let hit = world.hit(&ray, 0.001, f64::INFINITY).unwrap();
+    assert_eq!(hit.intersection, Point3::new(100, 0, 100));
+    let ray = Ray::new(Point3::new(3, 5, 0), Vec3::new(0, -1, 0), 0.0);
+    let hit = world.hit(&ray, 0.001, f64::INFINITY).unwrap();
+    assert_eq!(hit.intersection, Point3::new(3, 2, 0));
+}
+
+#[test]
+fn bezier_patch_test() {
+    let material = Lambertian::with_colour(Colour::new(1, 1, 1));
+    let grid = |height: fn(usize, usize) -> f64| {
+        let point = |i, j| Point3::new(j as f64, i as f64, height(i, j));
+        let row = |i| [point(i, 0), point(i, 1), point(i, 2), point(i, 3)];
+        [row(0), row(1), row(2), row(3)]
+    };
+    let flat = BezierPatch::new(grid(|_, _| 0.0), &material);
+    let ray = Ray::new(Point3::new(1.5, 0.75, -5), Vec3::new(0, 0, 1), 0.0);
+    let hit = flat.hit(&ray, 0.001, f64::INFINITY).unwrap();
+    assert!((hit.distance - 5.0).abs() < 1e-6);
+    assert!((hit.surface_u - 0.5).abs() < 1e-6);
+    assert!((hit.surface_v - 0.25).abs() < 1e-6);
+    assert!(flat
+        .hit(
+            &Ray::new(Point3::new(4, 1, -5), Vec3::new(0, 0, 1), 0.0),
+            0.001,
+            f64::INFINITY
+        )
+        .is_none());
+
+    // raised in the middle, so a ray down the centre stops above the base
+    let dome = BezierPatch::new(
+        grid(|i, j| {
+            if (1..3).contains(&i) && (1..3).contains(&j) {
+                2.0
+            } else {
+                0.0
+            }
+        }),
+        &material,
+    );
+    let ray = Ray::new(Point3::new(1.5, 1.5, 5), Vec3::new(0, 0, -1), 0.0);
+    let hit = dome.hit(&ray, 0.001, f64::INFINITY).unwrap();
+    // the centre is the average of the inner four, weighted 9/16
+    assert!((hit.intersection.z - 1.125).abs() < 1e-6);
+    assert!(hit.front_face);
+}
+
+#[test]
+fn triangle_bounding_box_test() {
+    let material = Lambertian::with_colour(Colour::new(1, 1, 1));
+    let triangle = Triangle::new(
+        Point3::new(0, 0, 0),
+        Point3::new(1, 0, 1),
+        Point3::new(0, 1, 2),
+        &material,
+    );
+    let bbox = triangle.bounding_box(0.0, 1.0).unwrap();
+    assert_eq!(bbox.minimum, Point3::new(0, 0, 0));
+    assert_eq!(bbox.maximum, Point3::new(1, 1, 2));
+    // flat in z, but a ray straight at it still gets through the box
+    let flat = Triangle::new(
+        Point3::new(0, 0, 1),
+        Point3::new(1, 0, 1),
+        Point3::new(0, 1, 1),
+        &material,
+    );
+    let bbox = flat.bounding_box(0.0, 1.0).unwrap();
+    let ray = Ray::new(Point3::new(0.25, 0.25, -1), Vec3::new(0, 0, 1), 0.0);
+    assert!(bbox.intersects(&ray, 0.0, f64::INFINITY));
+}
+
+#[test]
+fn vertex_normal_test() {
+    let material = Lambertian::with_colour(Colour::new(1, 1, 1));
+    let triangle = Triangle::with_vertex_normals(
+        (Point3::new(0, 0, 0), Vec3::new(0, 0, -1)),
+        (Point3::new(1, 0, 0), Vec3::new(-1, 0, -1)),
+        (Point3::new(0, 1, 0), Vec3::new(0, -1, -1)),
+        None,
+        &material,
+    );
+    let ray = Ray::new(Point3::new(0.5, 0.25, -1), Vec3::new(0, 0, 1), 0.0);
+    let hit = triangle.hit(&ray, 0.0, f64::INFINITY).unwrap();
+    let expected = 0.25 * Vec3::new(0, 0, -1)
+        + 0.5 * Vec3::new(-1, 0, -1).unit_vector()
+        + 0.25 * Vec3::new(0, -1, -1).unit_vector();
+    assert!((hit.normal - expected.unit_vector()).near_zero());
+}
+
+#[test]
+fn mesh_uv_test() {
+    let material = Lambertian::with_colour(Colour::new(1, 1, 1));
+    let cube = load_mesh("examples/cube.obj", "cube", &material, false).unwrap();
+    let ray = Ray::new(Point3::new(0.25, 0.75, -1), Vec3::new(0, 0, 1), 0.0);
+    let hit = cube.hit(&ray, 0.0, f64::INFINITY).unwrap();
+    assert!((hit.surface_u - 0.25).abs() < 1e-9);
+    assert!((hit.surface_v - 0.75).abs() < 1e-9);
+}
+
+#[test]
+fn height_field_test() {
+    let material = Lambertian::with_colour(Colour::new(1, 1, 1));
+    // a ramp rising along x, from black to white
+    let image = GrayImage::from_fn(3, 2, |i, _| Luma([(i * 255 / 2) as u8]));
+    let terrain = HeightField::new(
+        &image,
+        Point3::new(0, 1, 0),
+        Point3::new(4, 3, 2),
+        &material,
+    )
+    .unwrap();
+    let bbox = terrain.bounding_box(0.0, 1.0).unwrap();
+    assert_eq!(bbox.minimum, Point3::new(0, 1, 0));
+    assert_eq!(bbox.maximum, Point3::new(4, 3, 2));
+    let ray = Ray::new(Point3::new(3, 10, 0.5), Vec3::new(0, -1, 0), 0.0);
+    let hit = terrain.hit(&ray, 0.0, f64::INFINITY).unwrap();
+    assert!((hit.intersection.y - (1.0 + 2.0 * 0.75)).abs() < 0.01);
+    assert!((hit.surface_u - 0.75).abs() < 1e-9);
+    assert!((hit.surface_v - 0.25).abs() < 1e-9);
+    assert!(hit.normal.y > 0.0);
+    assert!(
+        HeightField::new(&GrayImage::new(1, 5), bbox.minimum, bbox.maximum, &material).is_err()
+    );
+}
+
+#[test]
+fn gltf_test() {
+    let triangle = load_gltf("examples/triangle.gltf", None).unwrap();
+    // the node moves the triangle from z = 0 to z = 1
+    let ray = Ray::new(Point3::new(0.25, 0.25, -1), Vec3::new(0, 0, 1), 0.0);
+    let hit = triangle.hit(&ray, 0.0, f64::INFINITY).unwrap();
+    assert!((hit.intersection - Point3::new(0.25, 0.25, 1)).near_zero());
+    let (_, attenuation) = hit.material.scatter(&ray, &hit).unwrap();
+    assert!((attenuation - Colour::new(0.8, 0.2, 0.2)).length() < 1e-6);
+
+    // the same triangle, with its last index pointing past its three vertices
+    let source = std::fs::read_to_string("examples/triangle.gltf").unwrap();
+    let broken = source.replace("AAABAAIAAAA=", "AAABAAMAAAA=");
+    assert_ne!(source, broken);
+    let path =
+        std::env::temp_dir().join(format!("raytracer_gltf_test_{}.gltf", std::process::id()));
+    std::fs::write(&path, broken).unwrap();
+    let err = load_gltf(path.to_str().unwrap(), None).err().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(err.to_string().contains("past its 3 vertices"), "{}", err);
+}
+
+#[test]
+fn stl_test() {
+    let material = Lambertian::with_colour(Colour::new(1, 1, 1));
+    let ray = Ray::new(Point3::new(0.25, 0.25, -1), Vec3::new(0, 0, 1), 0.0);
+    let ascii = load_stl("examples/tetrahedron.stl", &material).unwrap();
+    assert!(ascii.hit(&ray, 0.0, f64::INFINITY).is_some());
+
+    let mut binary = vec![0u8; 80];
+    binary.extend_from_slice(&1u32.to_le_bytes());
+    for value in &[
+        0.0f32, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0,
+    ] {
+        binary.extend_from_slice(&value.to_le_bytes());
+    }
+    binary.extend_from_slice(&[0, 0]);
+    let path = std::env::temp_dir().join(format!("raytracer_stl_test_{}.stl", std::process::id()));
+    std::fs::write(&path, binary).unwrap();
+    let triangle = load_stl(path.to_str().unwrap(), &material).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let hit = triangle.hit(&ray, 0.0, f64::INFINITY).unwrap();
+    assert!((hit.intersection - Point3::new(0.25, 0.25, 0)).near_zero());
+}
+
+#[test]
+fn directional_light_test() {
+    use crate::hitting::find_emitters;
+    let sun = DirectionalLight::new(Vec3::new(0, 1, 1), Colour::new(10, 10, 10), 1.0);
+    let towards = Ray::new(Point3::new(5, 5, 5), Vec3::new(0, 1, 1.01), 0.0);
+    let hit = sun.hit(&towards, 0.001, f64::INFINITY).unwrap();
+    assert_eq!(
+        hit.material.emitted(&towards, &hit),
+        Colour::new(10, 10, 10)
+    );
+    let away = Ray::new(Point3::new(5, 5, 5), Vec3::new(0, 1, 1.1), 0.0);
+    assert!(sun.hit(&away, 0.001, f64::INFINITY).is_none());
+    // anything in the way blocks it
+    assert!(sun.hit(&towards, 0.001, 1000.0).is_none());
+
+    let sun = DirectionalLight {
+        direction: Vec3::new(0, 1, 0),
+        cos_angular_radius: 2.0_f64.to_radians().cos(),
+        material: DiffuseLight::with_colour(Colour::new(10, 10, 10), 1.0, false),
+    };
+    let expected = 1.0 / (2.0 * PI * (1.0 - 2.0_f64.to_radians().cos()));
+    for _ in 0..10 {
+        let (ray, pdf, colour) = sun.sample_towards(Point3::new(5, 5, 5), 0.0);
+        assert!(dot(ray.direction, sun.direction) >= sun.cos_angular_radius - 1e-12);
+        assert!((pdf - expected).abs() < 1e-9 * expected);
+        assert_eq!(colour, Colour::new(10, 10, 10));
+    }
+    assert_eq!(
+        sun.pdf_towards(Point3::new(0, 0, 0), Vec3::new(0, 1, 1)),
+        0.0
+    );
+    let sun: Arc<dyn Hittable> = Arc::new(sun);
+    assert_eq!(find_emitters(&[sun]).len(), 1);
+    // a point-sized sun can't be sampled
+    let point = DirectionalLight::new(Vec3::new(0, 1, 0), Colour::new(1, 1, 1), 0.0);
+    assert!(find_emitters(&[point]).is_empty());
+}
+
+#[test]
+fn emitter_test() {
+    let light = DiffuseLight::with_colour(Colour::new(4, 4, 4), 1.0, false);
+    let rect = XZRect {
+        x0: -1.0,
+        x1: 1.0,
+        z0: -1.0,
+        z1: 1.0,
+        k: 2.0,
+        material: Arc::clone(&light),
+        facing_positive: false,
+        face_id: None,
+    };
+    let from = Point3::new(0, 0, 0);
+    for _ in 0..10 {
+        let (ray, pdf, colour) = rect.sample_towards(from, 0.0);
+        assert!((rect.pdf_towards(from, ray.direction) - pdf).abs() < 1e-9);
+        assert!(colour.x == 4.0 && colour.y == 4.0 && colour.z == 4.0);
+    }
+    assert_eq!(rect.pdf_towards(from, Vec3::new(0, -1, 0)), 0.0);
+    // straight up: distance 2, area 4, facing the point head on
+    assert!((rect.pdf_towards(from, Vec3::new(0, 1, 0)) - 1.0).abs() < 1e-9);
+
+    let sphere = Sphere {
+        centre: Point3::new(0, 0, 2),
+        radius: 1.0,
+        material: light,
+    };
+    let (ray, pdf, colour) = sphere.sample_towards(from, 0.0);
+    assert!((sphere.pdf_towards(from, ray.direction) - pdf).abs() < 1e-9);
+    assert!(colour.x == 4.0);
+    // the sphere covers a cone with a half-angle of 30 degrees
+    let expected = 1.0 / (2.0 * PI * (1.0 - (PI / 6.0).cos()));
+    assert!((pdf - expected).abs() < 1e-9);
 }
@@ -1,8 +1,8 @@
 use anyhow::{anyhow, bail, Context, Result};
 
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub struct PartFile {
     pub file: File,
@@ -10,7 +10,7 @@ pub struct PartFile {
 }
 
 impl PartFile {
-    pub fn new(image_name: &PathBuf) -> Result<PartFile> {
+    pub fn new(image_name: &Path, image_width: u32, image_height: u32) -> Result<PartFile> {
         let mut path = PathBuf::new();
         let base = image_name
             .file_name()
@@ -23,9 +23,16 @@ impl PartFile {
                 break;
             }
         }
+        let mut file = File::create(&path)?;
+        file.write_all(format!("{} {}\n", image_width, image_height).as_bytes())?;
+        Ok(PartFile { file, path })
+    }
+
+    // Opens an existing part file to continue writing rows to it
+    pub fn append_to(path: &Path) -> Result<PartFile> {
         Ok(PartFile {
-            file: File::create(&path)?,
-            path,
+            file: OpenOptions::new().append(true).open(path)?,
+            path: path.to_path_buf(),
         })
     }
 
@@ -39,7 +46,7 @@ impl PartFile {
     }
 
     pub fn read(
-        name: &PathBuf,
+        name: &Path,
         image_height: u32,
         image_width: u32,
         recover_corrupt: bool,
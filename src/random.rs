@@ -0,0 +1,54 @@
+use rand::{Error, Rng, RngCore, SeedableRng};
+use rand_xoshiro::Xoshiro256PlusPlus;
+
+use std::cell::RefCell;
+use std::f64::consts::PI;
+
+thread_local! {
+    static GENERATOR: RefCell<Xoshiro256PlusPlus> =
+        RefCell::new(Xoshiro256PlusPlus::from_entropy());
+}
+
+// A handle to the current thread's generator, used in place of rand::thread_rng().
+// Trait objects like Material can't take a generic Rng, so rather than pass one
+// down, each thread's generator is reseeded whenever reproducible output is needed.
+pub struct ThreadRng;
+
+pub fn rng() -> ThreadRng {
+    ThreadRng
+}
+
+// Restarts this thread's generator. Each stream (e.g. each row of the image)
+// gets its own sequence, so the output doesn't depend on how work is scheduled.
+pub fn reseed(seed: u64, stream: u64) {
+    // seed_from_u64 scrambles its input, so nearby streams aren't correlated
+    let generator =
+        Xoshiro256PlusPlus::seed_from_u64(seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    GENERATOR.with(|g| *g.borrow_mut() = generator);
+}
+
+// A normally distributed number that depends only on seed and stream, for
+// noise that has to come out the same however the work is split up
+pub fn fixed_normal(seed: u64, stream: u64) -> f64 {
+    let mut generator =
+        Xoshiro256PlusPlus::seed_from_u64(seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    // Box-Muller; u1 is kept above 0 for the log
+    let u1 = 1.0 - generator.gen::<f64>();
+    let u2 = generator.gen::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+impl RngCore for ThreadRng {
+    fn next_u32(&mut self) -> u32 {
+        GENERATOR.with(|g| g.borrow_mut().next_u32())
+    }
+    fn next_u64(&mut self) -> u64 {
+        GENERATOR.with(|g| g.borrow_mut().next_u64())
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        GENERATOR.with(|g| g.borrow_mut().fill_bytes(dest))
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        GENERATOR.with(|g| g.borrow_mut().try_fill_bytes(dest))
+    }
+}
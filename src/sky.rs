@@ -0,0 +1,154 @@
+use std::f64::consts::PI;
+
+use crate::camera::Sky;
+use crate::colour_space::xyz_to_rgb;
+use crate::hitting::Colour;
+use crate::math::{coeff, dot, Ray, Vec3};
+
+// Preetham, Shirley & Smits, "A Practical Analytic Model for Daylight" (1999).
+// Each Perez coefficient A to E is linear in turbidity: [slope, intercept].
+const PEREZ_LUMINANCE: [[f64; 2]; 5] = [
+    [0.1787, -1.4630],
+    [-0.3554, 0.4275],
+    [-0.0227, 5.3251],
+    [0.1206, -2.5771],
+    [-0.0670, 0.3703],
+];
+const PEREZ_X: [[f64; 2]; 5] = [
+    [-0.0193, -0.2592],
+    [-0.0665, 0.0008],
+    [-0.0004, 0.2125],
+    [-0.0641, -0.8989],
+    [-0.0033, 0.0452],
+];
+const PEREZ_Y: [[f64; 2]; 5] = [
+    [-0.0167, -0.2608],
+    [-0.0950, 0.0092],
+    [-0.0079, 0.2102],
+    [-0.0441, -1.6537],
+    [-0.0109, 0.0529],
+];
+
+// Zenith chromaticity is [T^2, T, 1] * matrix * [s^3, s^2, s, 1], where T is
+// turbidity and s is the sun's angle from the zenith
+const ZENITH_X: [[f64; 4]; 3] = [
+    [0.00166, -0.00375, 0.00209, 0.0],
+    [-0.02903, 0.06377, -0.03202, 0.00394],
+    [0.11693, -0.21196, 0.06052, 0.25886],
+];
+const ZENITH_Y: [[f64; 4]; 3] = [
+    [0.00275, -0.00610, 0.00317, 0.0],
+    [-0.04214, 0.08970, -0.04153, 0.00516],
+    [0.15346, -0.26756, 0.06670, 0.26688],
+];
+
+// The model gives luminance in kcd/m^2; this brings a midday sky to around 0.5
+const LUMINANCE_SCALE: f64 = 0.05;
+
+struct Perez {
+    coefficients: [f64; 5],
+    zenith: f64,
+    // the distribution's value at the zenith, which everything is relative to
+    at_zenith: f64,
+}
+
+impl Perez {
+    fn new(table: &[[f64; 2]; 5], turbidity: f64, zenith: f64, sun_theta: f64) -> Perez {
+        let mut coefficients = [0.0; 5];
+        for (c, [slope, intercept]) in coefficients.iter_mut().zip(table) {
+            *c = slope * turbidity + intercept;
+        }
+        let mut perez = Perez {
+            coefficients,
+            zenith,
+            at_zenith: 1.0,
+        };
+        perez.at_zenith = perez.distribution(1.0, sun_theta);
+        perez
+    }
+    fn distribution(&self, cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = self.coefficients;
+        (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+    }
+    fn value(&self, cos_theta: f64, gamma: f64) -> f64 {
+        self.zenith * self.distribution(cos_theta, gamma) / self.at_zenith
+    }
+}
+
+fn zenith_chromaticity(matrix: &[[f64; 4]; 3], turbidity: f64, sun_theta: f64) -> f64 {
+    let t = [turbidity * turbidity, turbidity, 1.0];
+    let s = [sun_theta.powi(3), sun_theta.powi(2), sun_theta, 1.0];
+    let mut acc = 0.0;
+    for (row, t) in matrix.iter().zip(&t) {
+        for (m, s) in row.iter().zip(&s) {
+            acc += t * m * s;
+        }
+    }
+    acc
+}
+
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Colour {
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    let rgb = xyz_to_rgb(Vec3::new(big_x, luminance, big_z));
+    // some sky colours are outside the sRGB gamut
+    rgb.clamp_components(0.0, f64::INFINITY)
+}
+
+// Daylight for a sun in the given direction, with +y as straight up. The
+// ground below the horizon reflects the sky just above it.
+pub struct PhysicalSky {
+    sun: Vec3,
+    luminance: Perez,
+    x: Perez,
+    y: Perez,
+    ground_albedo: Colour,
+}
+
+impl PhysicalSky {
+    pub fn new(sun_direction: Vec3, turbidity: f64, ground_albedo: Colour) -> PhysicalSky {
+        let sun = sun_direction.unit_vector();
+        let sun_theta = sun.y.max(0.0).acos();
+
+        let chi = (4.0 / 9.0 - turbidity / 120.0) * (PI - 2.0 * sun_theta);
+        let zenith_luminance =
+            (4.0453 * turbidity - 4.9710) * chi.tan() - 0.2155 * turbidity + 2.4192;
+        PhysicalSky {
+            sun,
+            luminance: Perez::new(&PEREZ_LUMINANCE, turbidity, zenith_luminance, sun_theta),
+            x: Perez::new(
+                &PEREZ_X,
+                turbidity,
+                zenith_chromaticity(&ZENITH_X, turbidity, sun_theta),
+                sun_theta,
+            ),
+            y: Perez::new(
+                &PEREZ_Y,
+                turbidity,
+                zenith_chromaticity(&ZENITH_Y, turbidity, sun_theta),
+                sun_theta,
+            ),
+            ground_albedo,
+        }
+    }
+}
+
+impl Sky for PhysicalSky {
+    fn colour(&self, ray: &Ray) -> Colour {
+        let dir = ray.direction.unit_vector();
+        // below the horizon, look at the sky just above it instead
+        let above = Vec3::new(dir.x, dir.y.max(0.01), dir.z).unit_vector();
+        let cos_theta = above.y;
+        let gamma = dot(above, self.sun).clamp(-1.0, 1.0).acos();
+        let colour = xyy_to_rgb(
+            self.x.value(cos_theta, gamma),
+            self.y.value(cos_theta, gamma),
+            LUMINANCE_SCALE * self.luminance.value(cos_theta, gamma),
+        );
+        if dir.y < 0.0 {
+            coeff(self.ground_albedo, colour)
+        } else {
+            colour
+        }
+    }
+}
@@ -0,0 +1,90 @@
+#[cfg(feature = "stats")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// Counters for the render statistics report.
+// They only count anything when built with the "stats" feature,
+// so they cost nothing in normal builds.
+#[derive(Default)]
+pub struct Counter {
+    #[cfg(feature = "stats")]
+    count: AtomicU64,
+}
+
+impl Counter {
+    pub const fn new() -> Counter {
+        Counter {
+            #[cfg(feature = "stats")]
+            count: AtomicU64::new(0),
+        }
+    }
+    #[inline]
+    pub fn increment(&self) {
+        self.add(1);
+    }
+    #[inline]
+    pub fn add(&self, _amount: u64) {
+        #[cfg(feature = "stats")]
+        self.count.fetch_add(_amount, Ordering::Relaxed);
+    }
+    #[cfg(feature = "stats")]
+    pub fn get(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+    #[cfg(not(feature = "stats"))]
+    pub fn get(&self) -> u64 {
+        0
+    }
+}
+
+pub static PRIMARY_RAYS: Counter = Counter::new();
+pub static SECONDARY_RAYS: Counter = Counter::new();
+pub static GEOMETRY_HITS: Counter = Counter::new();
+pub static SKY_HITS: Counter = Counter::new();
+pub static BVH_NODES_VISITED: Counter = Counter::new();
+pub static BVH_NODES_HIT: Counter = Counter::new();
+pub static TEXTURE_SAMPLES: Counter = Counter::new();
+pub static BVH_BUILD_NANOS: Counter = Counter::new();
+
+pub fn print_report(setup_time: Duration, render_time: Duration) {
+    eprintln!("Render statistics:");
+    eprintln!("  Scene setup:        {:.3}s", setup_time.as_secs_f64());
+    eprintln!(
+        "    BVH construction: {:.3}s",
+        Duration::from_nanos(BVH_BUILD_NANOS.get()).as_secs_f64()
+    );
+    eprintln!("  Rendering:          {:.3}s", render_time.as_secs_f64());
+    let visited = BVH_NODES_VISITED.get();
+    let hit = BVH_NODES_HIT.get();
+    let primary = PRIMARY_RAYS.get();
+    let secondary = SECONDARY_RAYS.get();
+    let geometry_hits = GEOMETRY_HITS.get();
+    let traced = geometry_hits + SKY_HITS.get();
+    eprintln!("  Total rays:         {}", primary + secondary);
+    eprintln!("  Primary rays:       {}", primary);
+    eprintln!("  Secondary rays:     {}", secondary);
+    eprintln!(
+        "  Geometry hit rate:  {:.1}%",
+        percentage(geometry_hits, traced)
+    );
+    // every secondary ray is one bounce along some primary ray's path
+    eprintln!(
+        "  Mean path depth:    {:.2}",
+        if primary > 0 {
+            secondary as f64 / primary as f64
+        } else {
+            0.0
+        }
+    );
+    eprintln!("  BVH nodes visited:  {}", visited);
+    eprintln!("  BVH hit rate:       {:.1}%", percentage(hit, visited));
+    eprintln!("  Texture samples:    {}", TEXTURE_SAMPLES.get());
+}
+
+fn percentage(part: u64, whole: u64) -> f64 {
+    if whole > 0 {
+        100.0 * part as f64 / whole as f64
+    } else {
+        0.0
+    }
+}
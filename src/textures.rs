@@ -1,14 +1,22 @@
 use anyhow::Result;
 use image::{self, ImageBuffer, Rgb};
 
-use std::sync::Arc;
+use std::fmt;
+use std::sync::{Arc, Mutex, Weak};
 
+use crate::colour_space::srgb_to_linear;
 use crate::hitting::Colour;
-use crate::math::{clamp, Point3};
+use crate::math::{clamp, get_sphere_uv, Point3, Vec3};
+use crate::stats;
 
-pub trait Texture: Send + Sync {
-    fn value(&self, u: f64, v: f64, p: Point3) -> Colour;
-    fn _print(&self) -> String;
+pub trait Texture: fmt::Display + Send + Sync {
+    // the normal is for textures that follow the shape of the surface
+    fn value(&self, u: f64, v: f64, p: Point3, normal: Vec3) -> Colour;
+    // A rough idea of the texture's colour, which tells lights from
+    // everything else when picking which ones to sample
+    fn average_colour(&self) -> Colour {
+        self.value(0.5, 0.5, Point3::new(0, 0, 0), Vec3::new(0, 1, 0))
+    }
 }
 
 pub struct SolidColour {
@@ -16,41 +24,670 @@ pub struct SolidColour {
 }
 
 impl Texture for SolidColour {
-    fn value(&self, _u: f64, _v: f64, _p: Point3) -> Colour {
+    fn value(&self, _u: f64, _v: f64, _p: Point3, _normal: Vec3) -> Colour {
+        stats::TEXTURE_SAMPLES.increment();
+        self.colour
+    }
+    fn average_colour(&self) -> Colour {
         self.colour
     }
-    fn _print(&self) -> String {
-        format!("Solid colour: {}", self.colour)
+}
+
+impl fmt::Display for SolidColour {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Solid colour: {}", self.colour)
     }
 }
 
+#[derive(Clone, Copy)]
+pub enum FilterMode {
+    // the colour of whichever pixel the point falls in
+    Nearest,
+    // a blend of the four pixels around the point, so magnified images stay smooth
+    Bilinear,
+}
+
+// What to do with texture coordinates outside [0, 1]
+#[derive(Clone, Copy)]
+pub enum Wrap {
+    // use the nearest edge pixel
+    Clamp,
+    // tile the image
+    Repeat,
+    // tile the image, flipping every other tile so the edges line up
+    MirrorRepeat,
+}
+
+impl Wrap {
+    // brings a pixel index into 0..size
+    fn index(self, i: i64, size: u32) -> u32 {
+        let size = size as i64;
+        let i = match self {
+            Wrap::Clamp => i.max(0).min(size - 1),
+            Wrap::Repeat => i.rem_euclid(size),
+            Wrap::MirrorRepeat => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+        };
+        i as u32
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct WrapMode {
+    pub u: Wrap,
+    pub v: Wrap,
+}
+
 pub struct ImageTexture {
     pub image: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    // most images store sRGB-encoded colours, but the renderer works in linear light
+    pub is_srgb: bool,
+    pub filter: FilterMode,
+    pub wrap: WrapMode,
+    // mean of all the pixels, in linear light
+    average: Colour,
 }
 
 impl ImageTexture {
-    pub fn from_file(filename: &str) -> Result<Arc<dyn Texture>> {
+    pub fn new(image: ImageBuffer<Rgb<u8>, Vec<u8>>, is_srgb: bool) -> ImageTexture {
+        let mut texture = ImageTexture {
+            image,
+            is_srgb,
+            filter: FilterMode::Nearest,
+            wrap: WrapMode {
+                u: Wrap::Clamp,
+                v: Wrap::Clamp,
+            },
+            average: Colour::new(0, 0, 0),
+        };
+        let mut sum = Colour::new(0, 0, 0);
+        for (i, j, _) in texture.image.enumerate_pixels() {
+            sum += texture.pixel_colour(i, j);
+        }
+        let count = texture.image.width() as f64 * texture.image.height() as f64;
+        texture.average = sum / count.max(1.0);
+        texture
+    }
+    pub fn from_file(filename: &str, is_srgb: bool) -> Result<ImageTexture> {
         let dyn_image = image::io::Reader::open(filename)?.decode()?;
         let image = dyn_image.into_rgb8();
-        Ok(Arc::new(ImageTexture { image }))
+        Ok(ImageTexture::new(image, is_srgb))
+    }
+    pub fn with_filter(mut self, filter: FilterMode) -> ImageTexture {
+        self.filter = filter;
+        self
+    }
+    pub fn with_wrap(mut self, wrap: WrapMode) -> ImageTexture {
+        self.wrap = wrap;
+        self
+    }
+    fn pixel_colour(&self, i: u32, j: u32) -> Colour {
+        let colour_scale = 1.0 / 255.0;
+        let pixel = self.image.get_pixel(i, j);
+        let colour = colour_scale * Colour::new(pixel[0], pixel[1], pixel[2]);
+        if self.is_srgb {
+            srgb_to_linear(colour)
+        } else {
+            colour
+        }
     }
 }
 
 impl Texture for ImageTexture {
-    fn value(&self, u: f64, v: f64, _p: Point3) -> Colour {
-        let u = clamp(u, 0.0, 1.0);
-        let v = 1.0 - clamp(v, 0.0, 1.0); // flip v
+    fn value(&self, u: f64, v: f64, _p: Point3, _normal: Vec3) -> Colour {
+        stats::TEXTURE_SAMPLES.increment();
+        let v = 1.0 - v; // flip v
+        let (width, height) = (self.image.width(), self.image.height());
+        let column = |i: f64| self.wrap.u.index(i as i64, width);
+        let row = |j: f64| self.wrap.v.index(j as i64, height);
 
-        let i = (u * self.image.width() as f64) as u32;
-        let j = (v * self.image.height() as f64) as u32;
-        let i = u32::min(i, self.image.width() - 1);
-        let j = u32::min(j, self.image.height() - 1);
+        match self.filter {
+            FilterMode::Nearest => self.pixel_colour(
+                column((u * width as f64).floor()),
+                row((v * height as f64).floor()),
+            ),
+            FilterMode::Bilinear => {
+                // pixel centres are at half-integer coordinates
+                let x = u * width as f64 - 0.5;
+                let y = v * height as f64 - 0.5;
+                let (fx, fy) = (x - x.floor(), y - y.floor());
+                let (i0, i1) = (column(x.floor()), column(x.floor() + 1.0));
+                let (j0, j1) = (row(y.floor()), row(y.floor() + 1.0));
+                let top = self
+                    .pixel_colour(i0, j0)
+                    .lerp(self.pixel_colour(i1, j0), fx);
+                let bottom = self
+                    .pixel_colour(i0, j1)
+                    .lerp(self.pixel_colour(i1, j1), fx);
+                top.lerp(bottom, fy)
+            }
+        }
+    }
+    fn average_colour(&self) -> Colour {
+        self.average
+    }
+}
 
-        let colour_scale = 1.0 / 255.0;
-        let pixel = self.image.get_pixel(i, j);
-        colour_scale * Colour::new(pixel[0], pixel[1], pixel[2])
+impl fmt::Display for ImageTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "image texture")
+    }
+}
+
+// One image holding many textures, e.g. decals or a trim sheet, each used
+// through an AtlasTile
+pub struct TextureAtlas {
+    pub image: ImageTexture,
+}
+
+impl TextureAtlas {
+    // Each file is only loaded once, however many tiles use it
+    pub fn open(filename: &str) -> Result<Arc<TextureAtlas>> {
+        static LOADED: Mutex<Vec<(String, Weak<TextureAtlas>)>> = Mutex::new(Vec::new());
+        let mut loaded = LOADED.lock().unwrap();
+        let existing = loaded
+            .iter()
+            .filter(|(name, _)| name == filename)
+            .find_map(|(_, atlas)| atlas.upgrade());
+        if let Some(atlas) = existing {
+            return Ok(atlas);
+        }
+        let atlas = Arc::new(TextureAtlas {
+            image: ImageTexture::from_file(filename, true)?,
+        });
+        loaded.retain(|(_, atlas)| atlas.strong_count() > 0);
+        loaded.push((filename.to_owned(), Arc::downgrade(&atlas)));
+        Ok(atlas)
     }
-    fn _print(&self) -> String {
-        format!("image texture")
+}
+
+// The rectangle from (u0, v0) to (u1, v1) of an atlas, stretched over the
+// whole of the usual uv range
+pub struct AtlasTile {
+    pub atlas: Arc<TextureAtlas>,
+    pub u0: f64,
+    pub v0: f64,
+    pub u1: f64,
+    pub v1: f64,
+}
+
+impl Texture for AtlasTile {
+    fn value(&self, u: f64, v: f64, p: Point3, normal: Vec3) -> Colour {
+        // clamped so neighbouring tiles don't bleed in
+        let u = self.u0 + clamp(u, 0.0, 1.0) * (self.u1 - self.u0);
+        let v = self.v0 + clamp(v, 0.0, 1.0) * (self.v1 - self.v0);
+        self.atlas.image.value(u, v, p, normal)
+    }
+}
+
+impl fmt::Display for AtlasTile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "atlas tile from ({}, {}) to ({}, {})",
+            self.u0, self.v0, self.u1, self.v1
+        )
+    }
+}
+
+// Three uniform numbers in [0, 1) that depend only on the lattice cell and the seed
+fn lattice_randoms(seed: u64, cell: [i64; 3]) -> [f64; 3] {
+    let mut state = seed;
+    for (c, k) in cell.iter().zip(&[
+        0x9e37_79b9_7f4a_7c15u64,
+        0xc2b2_ae3d_27d4_eb4f,
+        0x1656_67b1_9e37_79f9,
+    ]) {
+        state ^= (*c as u64).wrapping_mul(*k);
+        state = state.rotate_left(31);
+    }
+    let mut out = [0.0; 3];
+    for r in out.iter_mut() {
+        // splitmix64
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        *r = (z >> 11) as f64 / (1u64 << 53) as f64;
+    }
+    out
+}
+
+// Stars scattered over the directions from the origin, meant for the inside
+// of a big emissive sphere around the scene. Directions are split into a fine
+// grid of cells, and each cell holds a star with probability `density`.
+pub struct StarField {
+    pub density: f64,
+    pub min_brightness: f64,
+    pub max_brightness: f64,
+    pub seed: u64,
+}
+
+const STAR_GRID_WIDTH: f64 = 4096.0;
+const STAR_GRID_HEIGHT: f64 = 2048.0;
+// width of a star's gaussian profile, as a fraction of its cell
+const STAR_SIGMA: f64 = 0.12;
+
+impl Texture for StarField {
+    fn value(&self, _u: f64, _v: f64, p: Point3, _normal: Vec3) -> Colour {
+        stats::TEXTURE_SAMPLES.increment();
+        let (u, v) = get_sphere_uv(p.unit_vector());
+        let (x, y) = (u * STAR_GRID_WIDTH, v * STAR_GRID_HEIGHT);
+        let (i, j) = (x.floor(), y.floor());
+        let [present, position, brightness] = lattice_randoms(self.seed, [i as i64, j as i64, 0]);
+        if present >= self.density {
+            return Colour::new(0, 0, 0);
+        }
+        // keep the star away from the cell's edges so it isn't cut off
+        let centre_x = 0.25 + 0.5 * position;
+        let centre_y = 0.25 + 0.5 * (position * 7919.0).fract();
+        let dx = x - i - centre_x;
+        let dy = y - j - centre_y;
+        let falloff = (-(dx * dx + dy * dy) / (2.0 * STAR_SIGMA * STAR_SIGMA)).exp();
+        let brightness =
+            self.min_brightness + brightness * (self.max_brightness - self.min_brightness);
+        brightness * falloff * Colour::new(1, 1, 1)
+    }
+    fn average_colour(&self) -> Colour {
+        let mean_brightness = (self.min_brightness + self.max_brightness) / 2.0;
+        let star_area = 2.0 * std::f64::consts::PI * STAR_SIGMA * STAR_SIGMA;
+        self.density * mean_brightness * star_area * Colour::new(1, 1, 1)
+    }
+}
+
+impl fmt::Display for StarField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "star field: density {}", self.density)
     }
 }
+
+pub enum VoronoiMode {
+    // distance to the nearest feature point
+    F1,
+    // difference between the nearest two distances, which is dark along cell edges
+    F2MinusF1,
+    // a random colour for each cell
+    CellColour,
+}
+
+// Cellular noise, with one feature point at a random spot in each unit cube
+// of the scaled space
+pub struct VoronoiTexture {
+    pub scale: f64,
+    pub colour_mode: VoronoiMode,
+}
+
+impl Texture for VoronoiTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3, _normal: Vec3) -> Colour {
+        stats::TEXTURE_SAMPLES.increment();
+        let p = self.scale * p;
+        let base = [p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64];
+        let mut f1 = f64::INFINITY;
+        let mut f2 = f64::INFINITY;
+        let mut nearest = base;
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let cell = [base[0] + dx, base[1] + dy, base[2] + dz];
+                    let [ox, oy, oz] = lattice_randoms(0, cell);
+                    let point = Point3::new(
+                        cell[0] as f64 + ox,
+                        cell[1] as f64 + oy,
+                        cell[2] as f64 + oz,
+                    );
+                    let distance = (point - p).length();
+                    if distance < f1 {
+                        f2 = f1;
+                        f1 = distance;
+                        nearest = cell;
+                    } else if distance < f2 {
+                        f2 = distance;
+                    }
+                }
+            }
+        }
+        match self.colour_mode {
+            VoronoiMode::F1 => clamp(f1, 0.0, 1.0) * Colour::new(1, 1, 1),
+            VoronoiMode::F2MinusF1 => clamp(f2 - f1, 0.0, 1.0) * Colour::new(1, 1, 1),
+            VoronoiMode::CellColour => {
+                // a different seed, so the colour doesn't follow the point's position
+                let [r, g, b] = lattice_randoms(1, nearest);
+                Colour::new(r, g, b)
+            }
+        }
+    }
+}
+
+impl fmt::Display for VoronoiTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "voronoi texture: scale {}", self.scale)
+    }
+}
+
+// Projects the inner texture along each axis, using the coordinates as uv, and
+// blends the three by how much the surface faces each axis. Meshes without
+// good uv coordinates can use this to avoid seams.
+pub struct TriPlanar {
+    pub inner: Arc<dyn Texture>,
+    // higher values make the transitions between projections sharper
+    pub blend_sharpness: f64,
+}
+
+impl Texture for TriPlanar {
+    fn value(&self, _u: f64, _v: f64, p: Point3, normal: Vec3) -> Colour {
+        let facing = normal.abs();
+        let weights = Vec3::new(
+            facing.x.powf(self.blend_sharpness),
+            facing.y.powf(self.blend_sharpness),
+            facing.z.powf(self.blend_sharpness),
+        );
+        let total = weights.x + weights.y + weights.z;
+        if total == 0.0 {
+            return self.inner.value(p.x, p.z, p, normal);
+        }
+        (weights.x * self.inner.value(p.y, p.z, p, normal)
+            + weights.y * self.inner.value(p.x, p.z, p, normal)
+            + weights.z * self.inner.value(p.x, p.y, p, normal))
+            / total
+    }
+    fn average_colour(&self) -> Colour {
+        self.inner.average_colour()
+    }
+}
+
+impl fmt::Display for TriPlanar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tri-planar projection of {}", self.inner)
+    }
+}
+
+// One minus each channel, e.g. to flip a mask
+pub struct InvertTexture {
+    pub inner: Arc<dyn Texture>,
+}
+
+impl Texture for InvertTexture {
+    fn value(&self, u: f64, v: f64, p: Point3, normal: Vec3) -> Colour {
+        Colour::new(1, 1, 1) - self.inner.value(u, v, p, normal)
+    }
+    fn average_colour(&self) -> Colour {
+        Colour::new(1, 1, 1) - self.inner.average_colour()
+    }
+}
+
+impl fmt::Display for InvertTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "inverse of {}", self.inner)
+    }
+}
+
+pub struct ScaleTexture {
+    pub inner: Arc<dyn Texture>,
+    pub scale: f64,
+}
+
+impl Texture for ScaleTexture {
+    fn value(&self, u: f64, v: f64, p: Point3, normal: Vec3) -> Colour {
+        self.scale * self.inner.value(u, v, p, normal)
+    }
+    fn average_colour(&self) -> Colour {
+        self.scale * self.inner.average_colour()
+    }
+}
+
+impl fmt::Display for ScaleTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} times {}", self.scale, self.inner)
+    }
+}
+
+// Wraps one texture around a block made with objects::Block::unwrapped, its
+// faces laid out in a cross so that the edges line up:
+//
+//          +y
+//     -x   +z   +x   -z
+//          -y
+//
+// Each face gets a quarter of the width and a third of the height, with v
+// going up. UVs from anything else pass through unchanged.
+pub struct BoxUnwrapTexture {
+    pub inner: Arc<dyn Texture>,
+}
+
+impl Texture for BoxUnwrapTexture {
+    fn value(&self, u: f64, v: f64, p: Point3, normal: Vec3) -> Colour {
+        let (u, v) = box_unwrap(u, v);
+        self.inner.value(u, v, p, normal)
+    }
+    fn average_colour(&self) -> Colour {
+        self.inner.average_colour()
+    }
+}
+
+impl fmt::Display for BoxUnwrapTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} unwrapped around a block", self.inner)
+    }
+}
+
+fn box_unwrap(u: f64, v: f64) -> (f64, f64) {
+    // faces add 2 * (id + 1) to u, with ids in the order -z, +z, -y, +y, -x, +x
+    let face = (u / 2.0).floor() - 1.0;
+    let u = u - 2.0 * (face + 1.0);
+    // which cell of the cross, and where across and up it the point is
+    let (column, row, across, up) = match face as i64 {
+        0 => (3.0, 1.0, 1.0 - u, v),
+        1 => (1.0, 1.0, u, v),
+        2 => (1.0, 0.0, u, v),
+        3 => (1.0, 2.0, u, 1.0 - v),
+        // side faces have u going up, and v along z
+        4 => (0.0, 1.0, v, u),
+        5 => (2.0, 1.0, 1.0 - v, u),
+        _ => return (u, v),
+    };
+    ((column + across) / 4.0, (row + up) / 3.0)
+}
+
+#[test]
+fn srgb_texture_test() {
+    let image = ImageBuffer::from_pixel(1, 1, Rgb([128, 128, 128]));
+    let srgb = ImageTexture::new(image.clone(), true);
+    let linear = ImageTexture::new(image, false);
+    let p = Point3::new(0, 0, 0);
+    let up = Vec3::new(0, 1, 0);
+    assert!((srgb.value(0.5, 0.5, p, up).x - 0.216).abs() < 0.001);
+    assert!((linear.value(0.5, 0.5, p, up).x - 0.502).abs() < 0.001);
+    assert!((srgb.average_colour().x - 0.216).abs() < 0.001);
+
+    // halfway between a black pixel and a white one
+    let mut image = ImageBuffer::from_pixel(2, 1, Rgb([0, 0, 0]));
+    image.put_pixel(1, 0, Rgb([255, 255, 255]));
+    let nearest = ImageTexture::new(image.clone(), false);
+    let bilinear = ImageTexture::new(image, false).with_filter(FilterMode::Bilinear);
+    assert_eq!(nearest.value(0.49, 0.5, p, up).x, 0.0);
+    assert!((bilinear.value(0.5, 0.5, p, up).x - 0.5).abs() < 0.001);
+    assert_eq!(bilinear.value(0.0, 0.5, p, up).x, 0.0);
+
+    assert_eq!(Wrap::Clamp.index(-3, 4), 0);
+    assert_eq!(Wrap::Clamp.index(9, 4), 3);
+    assert_eq!(Wrap::Repeat.index(-1, 4), 3);
+    assert_eq!(Wrap::Repeat.index(9, 4), 1);
+    assert_eq!(Wrap::MirrorRepeat.index(-1, 4), 0);
+    assert_eq!(Wrap::MirrorRepeat.index(5, 4), 2);
+    assert_eq!(Wrap::MirrorRepeat.index(9, 4), 1);
+}
+
+#[test]
+fn star_field_test() {
+    let stars = StarField {
+        density: 0.05,
+        min_brightness: 5.0,
+        max_brightness: 10.0,
+        seed: 1,
+    };
+    let up = Vec3::new(0, 1, 0);
+    // a patch of sky around 25 cells across, sampled several times per cell
+    let mut lit = 0;
+    let mut brightest: f64 = 0.0;
+    for a in 0..200 {
+        for b in 0..200 {
+            let (phi, theta) = (1.0 + a as f64 * 0.0002, 1.0 + b as f64 * 0.0002);
+            let p = Point3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            );
+            let colour = stars.value(0.0, 0.0, p, up);
+            assert_eq!(colour, stars.value(0.0, 0.0, p, up));
+            if colour.x > 0.01 {
+                lit += 1;
+            }
+            brightest = brightest.max(colour.x);
+        }
+    }
+    // mostly dark, with a few small bright spots
+    assert!(lit > 0 && lit < 4000);
+    assert!(brightest > 1.0 && brightest <= 10.0);
+}
+
+#[test]
+fn voronoi_test() {
+    let texture = |colour_mode| VoronoiTexture {
+        scale: 2.0,
+        colour_mode,
+    };
+    let (f1, edges, cells) = (
+        texture(VoronoiMode::F1),
+        texture(VoronoiMode::F2MinusF1),
+        texture(VoronoiMode::CellColour),
+    );
+    let up = Vec3::new(0, 1, 0);
+    for n in 0..1000 {
+        let p = Point3::new(n as f64 * 0.013, n as f64 * -0.007, 0.5);
+        let d1 = f1.value(0.0, 0.0, p, up).x;
+        let edge = edges.value(0.0, 0.0, p, up).x;
+        assert!((0.0..=1.0).contains(&d1) && (0.0..=1.0).contains(&edge));
+        // a tiny step almost never crosses into another cell
+        let step = Point3::new(1e-9, 0, 0);
+        assert_eq!(
+            cells.value(0.0, 0.0, p, up),
+            cells.value(0.0, 0.0, p + step, up)
+        );
+    }
+}
+
+#[test]
+fn atlas_test() {
+    let atlas = TextureAtlas::open("examples/globe.jpg").unwrap();
+    assert!(Arc::ptr_eq(
+        &atlas,
+        &TextureAtlas::open("examples/globe.jpg").unwrap()
+    ));
+    let tile = AtlasTile {
+        atlas: Arc::clone(&atlas),
+        u0: 0.5,
+        v0: 0.25,
+        u1: 0.75,
+        v1: 0.5,
+    };
+    let (p, up) = (Point3::new(0, 0, 0), Vec3::new(0, 1, 0));
+    assert_eq!(
+        tile.value(0.5, 0.5, p, up),
+        atlas.image.value(0.625, 0.375, p, up)
+    );
+    assert_eq!(
+        tile.value(2.0, -1.0, p, up),
+        atlas.image.value(0.75, 0.25, p, up)
+    );
+}
+
+#[test]
+fn box_unwrap_test() {
+    use crate::materials::Lambertian;
+    use crate::math::Ray;
+    use crate::objects::Block;
+
+    let block = Block::unwrapped(
+        Point3::new(0, 0, 0),
+        Point3::new(1, 1, 1),
+        &Lambertian::with_colour(Colour::new(1, 1, 1)),
+    );
+    // where on the unwrapped texture a ray from `from` along `direction` lands
+    let unwrap = |from: Point3, direction: Vec3| {
+        let hit = block
+            .hit(&Ray::new(from, direction, 0.0), 0.001, f64::INFINITY)
+            .unwrap();
+        box_unwrap(hit.surface_u, hit.surface_v)
+    };
+    let near = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3;
+    let (x, y, z) = (Vec3::new(1, 0, 0), Vec3::new(0, 1, 0), Vec3::new(0, 0, 1));
+
+    // the middle of each face is the middle of its cell in the cross
+    let centre = Point3::new(0.5, 0.5, 0.5);
+    for &(axis, expected) in &[
+        (-z, (3.5 / 4.0, 1.5 / 3.0)),
+        (z, (1.5 / 4.0, 1.5 / 3.0)),
+        (-y, (1.5 / 4.0, 0.5 / 3.0)),
+        (y, (1.5 / 4.0, 2.5 / 3.0)),
+        (-x, (0.5 / 4.0, 1.5 / 3.0)),
+        (x, (2.5 / 4.0, 1.5 / 3.0)),
+    ] {
+        assert!(near(unwrap(centre + 5.0 * axis, -axis), expected));
+    }
+
+    // faces that meet in the cross meet at the same point in the texture,
+    // checked just either side of each shared edge
+    let e = 1e-4;
+    let edges = [
+        // +z and +x, along x = 1, z = 1
+        (
+            Point3::new(1.0 - e, 0.3, 1.0),
+            z,
+            Point3::new(1.0, 0.3, 1.0 - e),
+            x,
+        ),
+        // +z and -x, along x = 0, z = 1
+        (
+            Point3::new(e, 0.3, 1.0),
+            z,
+            Point3::new(0.0, 0.3, 1.0 - e),
+            -x,
+        ),
+        // +x and -z, along x = 1, z = 0
+        (
+            Point3::new(1.0, 0.3, e),
+            x,
+            Point3::new(1.0 - e, 0.3, 0.0),
+            -z,
+        ),
+        // +z and +y, along y = 1, z = 1
+        (
+            Point3::new(0.3, 1.0 - e, 1.0),
+            z,
+            Point3::new(0.3, 1.0, 1.0 - e),
+            y,
+        ),
+        // +z and -y, along y = 0, z = 1
+        (
+            Point3::new(0.3, e, 1.0),
+            z,
+            Point3::new(0.3, 0.0, 1.0 - e),
+            -y,
+        ),
+    ];
+    for &(p0, normal0, p1, normal1) in &edges {
+        let uv0 = unwrap(p0 + normal0, -normal0);
+        let uv1 = unwrap(p1 + normal1, -normal1);
+        assert!(near(uv0, uv1), "{:?} and {:?} should meet", uv0, uv1);
+    }
+
+    // anything else is left alone
+    assert_eq!(box_unwrap(0.25, 0.75), (0.25, 0.75));
+}
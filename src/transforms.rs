@@ -1,8 +1,12 @@
+use std::collections::HashSet;
+use std::f64::consts::FRAC_PI_2;
+use std::fmt;
+use std::mem;
 use std::sync::Arc;
 
 use crate::camera::{TIME_MAX, TIME_MIN};
-use crate::hitting::{HitRecord, Hittable, AABB};
-use crate::math::{Point3, Ray, Vec3};
+use crate::hitting::{shared_memory_bytes, HitRecord, Hittable, AABB};
+use crate::math::{Mat4, Point3, Quat, Ray, Vec3};
 
 pub struct Translate {
     original: Arc<dyn Hittable>,
@@ -20,7 +24,8 @@ impl Translate {
 
 impl Hittable for Translate {
     fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
-        let moved_ray = Ray::new(ray.origin - self.offset, ray.direction, ray.time);
+        let moved_ray =
+            Ray::new(ray.origin - self.offset, ray.direction, ray.time).of_kind(ray.kind);
         if let Some(hit) = self.original.hit(&moved_ray, min_dist, max_dist) {
             Some(HitRecord {
                 distance: hit.distance,
@@ -45,8 +50,14 @@ impl Hittable for Translate {
             None
         }
     }
-    fn _print(&self) -> String {
-        format!("translate {}", self.original._print())
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + shared_memory_bytes(&self.original, seen)
+    }
+}
+
+impl fmt::Display for Translate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "translate {}", self.original)
     }
 }
 
@@ -64,7 +75,22 @@ impl RotateX {
     pub fn by_radians(original: &Arc<dyn Hittable>, radians: f64) -> Arc<dyn Hittable> {
         let sin_theta = radians.sin();
         let cos_theta = radians.cos();
-        let bounding_box = if let Some(bbox) = original.bounding_box(TIME_MIN, TIME_MAX) {
+        let bounding_box = Self::rotated_bbox(original, sin_theta, cos_theta, TIME_MIN, TIME_MAX);
+        Arc::new(RotateX {
+            original: Arc::clone(original),
+            sin_theta,
+            cos_theta,
+            bbox: bounding_box,
+        })
+    }
+    fn rotated_bbox(
+        original: &Arc<dyn Hittable>,
+        sin_theta: f64,
+        cos_theta: f64,
+        time0: f64,
+        time1: f64,
+    ) -> Option<AABB> {
+        if let Some(bbox) = original.bounding_box(time0, time1) {
             let mut minimum = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
             let mut maximum = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
             for i in 0..3 {
@@ -89,13 +115,7 @@ impl RotateX {
             Some(AABB { minimum, maximum })
         } else {
             None
-        };
-        Arc::new(RotateX {
-            original: Arc::clone(original),
-            sin_theta,
-            cos_theta,
-            bbox: bounding_box,
-        })
+        }
     }
 }
 
@@ -109,7 +129,7 @@ impl Hittable for RotateX {
         let y = self.sin_theta * ray.direction.z + self.cos_theta * ray.direction.y;
         let direction = Vec3::new(ray.direction.x, y, z);
 
-        let rotated = Ray::new(origin, direction, ray.time);
+        let rotated = Ray::new(origin, direction, ray.time).of_kind(ray.kind);
 
         if let Some(hit) = self.original.hit(&rotated, min_dist, max_dist) {
             let z = self.cos_theta * hit.intersection.z + self.sin_theta * hit.intersection.y;
@@ -133,11 +153,22 @@ impl Hittable for RotateX {
             None
         }
     }
-    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
-        self.bbox
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        // the cached box covers the whole shutter
+        if time0 == TIME_MIN && time1 == TIME_MAX {
+            self.bbox
+        } else {
+            Self::rotated_bbox(&self.original, self.sin_theta, self.cos_theta, time0, time1)
+        }
+    }
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + shared_memory_bytes(&self.original, seen)
     }
-    fn _print(&self) -> String {
-        format!("rotate x {}", self.original._print())
+}
+
+impl fmt::Display for RotateX {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rotate x {}", self.original)
     }
 }
 
@@ -155,7 +186,22 @@ impl RotateY {
     pub fn by_radians(original: &Arc<dyn Hittable>, radians: f64) -> Arc<dyn Hittable> {
         let sin_theta = radians.sin();
         let cos_theta = radians.cos();
-        let bounding_box = if let Some(bbox) = original.bounding_box(TIME_MIN, TIME_MAX) {
+        let bounding_box = Self::rotated_bbox(original, sin_theta, cos_theta, TIME_MIN, TIME_MAX);
+        Arc::new(RotateY {
+            original: Arc::clone(original),
+            sin_theta,
+            cos_theta,
+            bbox: bounding_box,
+        })
+    }
+    fn rotated_bbox(
+        original: &Arc<dyn Hittable>,
+        sin_theta: f64,
+        cos_theta: f64,
+        time0: f64,
+        time1: f64,
+    ) -> Option<AABB> {
+        if let Some(bbox) = original.bounding_box(time0, time1) {
             let mut minimum = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
             let mut maximum = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
             for i in 0..3 {
@@ -180,13 +226,7 @@ impl RotateY {
             Some(AABB { minimum, maximum })
         } else {
             None
-        };
-        Arc::new(RotateY {
-            original: Arc::clone(original),
-            sin_theta,
-            cos_theta,
-            bbox: bounding_box,
-        })
+        }
     }
 }
 
@@ -200,7 +240,7 @@ impl Hittable for RotateY {
         let z = self.sin_theta * ray.direction.x + self.cos_theta * ray.direction.z;
         let direction = Vec3::new(x, ray.direction.y, z);
 
-        let rotated = Ray::new(origin, direction, ray.time);
+        let rotated = Ray::new(origin, direction, ray.time).of_kind(ray.kind);
 
         if let Some(hit) = self.original.hit(&rotated, min_dist, max_dist) {
             let x = self.cos_theta * hit.intersection.x + self.sin_theta * hit.intersection.z;
@@ -224,11 +264,144 @@ impl Hittable for RotateY {
             None
         }
     }
-    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
-        self.bbox
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        // the cached box covers the whole shutter
+        if time0 == TIME_MIN && time1 == TIME_MAX {
+            self.bbox
+        } else {
+            Self::rotated_bbox(&self.original, self.sin_theta, self.cos_theta, time0, time1)
+        }
     }
-    fn _print(&self) -> String {
-        format!("rotate y {}", self.original._print())
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + shared_memory_bytes(&self.original, seen)
+    }
+}
+
+impl fmt::Display for RotateY {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rotate y {}", self.original)
+    }
+}
+
+// Turns about the y axis while the shutter is open, from radians0 at time0 to
+// radians1 at time1
+pub struct AnimatedRotateY {
+    original: Arc<dyn Hittable>,
+    radians0: f64,
+    radians1: f64,
+    time0: f64,
+    time1: f64,
+    bbox: Option<AABB>,
+}
+
+impl AnimatedRotateY {
+    pub fn by_degrees(
+        original: &Arc<dyn Hittable>,
+        degrees0: f64,
+        degrees1: f64,
+        time0: f64,
+        time1: f64,
+    ) -> Arc<dyn Hittable> {
+        let mut rotate = AnimatedRotateY {
+            original: Arc::clone(original),
+            radians0: degrees0.to_radians(),
+            radians1: degrees1.to_radians(),
+            time0,
+            time1,
+            bbox: None,
+        };
+        rotate.bbox = rotate.swept_bbox(TIME_MIN, TIME_MAX);
+        Arc::new(rotate)
+    }
+    // holds still before time0 and after time1
+    fn radians(&self, time: f64) -> f64 {
+        if self.time0 == self.time1 {
+            return self.radians0;
+        }
+        let time = time.clamp(self.time0.min(self.time1), self.time0.max(self.time1));
+        self.radians0
+            + ((time - self.time0) / (self.time1 - self.time0)) * (self.radians1 - self.radians0)
+    }
+    // Each corner of the original box sweeps out an arc. The arc's extremes
+    // are its ends, and wherever it crosses the x or z axis.
+    fn swept_bbox(&self, time0: f64, time1: f64) -> Option<AABB> {
+        let bbox = self.original.bounding_box(time0, time1)?;
+        let (from, to) = (self.radians(time0), self.radians(time1));
+        let mut minimum = Point3::new(f64::INFINITY, bbox.minimum.y, f64::INFINITY);
+        let mut maximum = Point3::new(f64::NEG_INFINITY, bbox.maximum.y, f64::NEG_INFINITY);
+        for &x in &[bbox.minimum.x, bbox.maximum.x] {
+            for &z in &[bbox.minimum.z, bbox.maximum.z] {
+                let radius = (x * x + z * z).sqrt();
+                let angle = z.atan2(x);
+                // rotating by theta takes the corner's angle to angle - theta
+                let start = angle - from.max(to);
+                let end = angle - from.min(to);
+                let mut include = |angle: f64| {
+                    let (x, z) = (radius * angle.cos(), radius * angle.sin());
+                    minimum.x = minimum.x.min(x);
+                    maximum.x = maximum.x.max(x);
+                    minimum.z = minimum.z.min(z);
+                    maximum.z = maximum.z.max(z);
+                };
+                include(start);
+                include(end);
+                let mut quarter = (start / FRAC_PI_2).ceil() * FRAC_PI_2;
+                while quarter < end {
+                    include(quarter);
+                    quarter += FRAC_PI_2;
+                }
+            }
+        }
+        Some(AABB { minimum, maximum })
+    }
+}
+
+impl Hittable for AnimatedRotateY {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        let radians = self.radians(ray.time);
+        let (sin_theta, cos_theta) = radians.sin_cos();
+
+        let x = cos_theta * ray.origin.x - sin_theta * ray.origin.z;
+        let z = sin_theta * ray.origin.x + cos_theta * ray.origin.z;
+        let origin = Vec3::new(x, ray.origin.y, z);
+
+        let x = cos_theta * ray.direction.x - sin_theta * ray.direction.z;
+        let z = sin_theta * ray.direction.x + cos_theta * ray.direction.z;
+        let direction = Vec3::new(x, ray.direction.y, z);
+
+        let rotated = Ray::new(origin, direction, ray.time).of_kind(ray.kind);
+        let hit = self.original.hit(&rotated, min_dist, max_dist)?;
+
+        let x = cos_theta * hit.intersection.x + sin_theta * hit.intersection.z;
+        let z = -sin_theta * hit.intersection.x + cos_theta * hit.intersection.z;
+        let intersection = Point3::new(x, hit.intersection.y, z);
+
+        let x = cos_theta * hit.normal.x + sin_theta * hit.normal.z;
+        let z = -sin_theta * hit.normal.x + cos_theta * hit.normal.z;
+        let normal = Point3::new(x, hit.normal.y, z);
+
+        Some(HitRecord {
+            intersection,
+            normal,
+            ..hit
+        })
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        // the cached box covers the whole shutter
+        if time0 == TIME_MIN && time1 == TIME_MAX {
+            self.bbox
+        } else {
+            self.swept_bbox(time0, time1)
+        }
+    }
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + shared_memory_bytes(&self.original, seen)
+    }
+}
+
+impl fmt::Display for AnimatedRotateY {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "animated rotate y {}", self.original)
     }
 }
 
@@ -246,7 +419,22 @@ impl RotateZ {
     pub fn by_radians(original: &Arc<dyn Hittable>, radians: f64) -> Arc<dyn Hittable> {
         let sin_theta = radians.sin();
         let cos_theta = radians.cos();
-        let bounding_box = if let Some(bbox) = original.bounding_box(TIME_MIN, TIME_MAX) {
+        let bounding_box = Self::rotated_bbox(original, sin_theta, cos_theta, TIME_MIN, TIME_MAX);
+        Arc::new(RotateZ {
+            original: Arc::clone(original),
+            sin_theta,
+            cos_theta,
+            bbox: bounding_box,
+        })
+    }
+    fn rotated_bbox(
+        original: &Arc<dyn Hittable>,
+        sin_theta: f64,
+        cos_theta: f64,
+        time0: f64,
+        time1: f64,
+    ) -> Option<AABB> {
+        if let Some(bbox) = original.bounding_box(time0, time1) {
             let mut minimum = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
             let mut maximum = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
             for i in 0..3 {
@@ -271,13 +459,7 @@ impl RotateZ {
             Some(AABB { minimum, maximum })
         } else {
             None
-        };
-        Arc::new(RotateZ {
-            original: Arc::clone(original),
-            sin_theta,
-            cos_theta,
-            bbox: bounding_box,
-        })
+        }
     }
 }
 
@@ -291,7 +473,7 @@ impl Hittable for RotateZ {
         let x = self.sin_theta * ray.direction.y + self.cos_theta * ray.direction.x;
         let direction = Vec3::new(x, y, ray.direction.z);
 
-        let rotated = Ray::new(origin, direction, ray.time);
+        let rotated = Ray::new(origin, direction, ray.time).of_kind(ray.kind);
 
         if let Some(hit) = self.original.hit(&rotated, min_dist, max_dist) {
             let y = self.cos_theta * hit.intersection.y + self.sin_theta * hit.intersection.x;
@@ -315,10 +497,237 @@ impl Hittable for RotateZ {
             None
         }
     }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        // the cached box covers the whole shutter
+        if time0 == TIME_MIN && time1 == TIME_MAX {
+            self.bbox
+        } else {
+            Self::rotated_bbox(&self.original, self.sin_theta, self.cos_theta, time0, time1)
+        }
+    }
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + shared_memory_bytes(&self.original, seen)
+    }
+}
+
+impl fmt::Display for RotateZ {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rotate z {}", self.original)
+    }
+}
+
+// Rotation about the origin by a quaternion. It can turn from one rotation to
+// another over the course of the shutter, for motion blur.
+pub struct RotateQuat {
+    original: Arc<dyn Hittable>,
+    start: Quat,
+    end: Quat,
+    bbox: Option<AABB>,
+}
+
+impl RotateQuat {
+    pub fn from_quat(original: &Arc<dyn Hittable>, q: Quat) -> Arc<dyn Hittable> {
+        Self::turning(original, q, q)
+    }
+    pub fn turning(original: &Arc<dyn Hittable>, start: Quat, end: Quat) -> Arc<dyn Hittable> {
+        let bounding_box = original.bounding_box(TIME_MIN, TIME_MAX).map(|bbox| {
+            let mut minimum = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+            let mut maximum = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+            // somewhere in between, a corner could be anywhere on its sphere
+            let mut radius: f64 = 0.0;
+            for i in 0..2 {
+                for j in 0..2 {
+                    for k in 0..2 {
+                        let x = i as f64 * bbox.maximum.x + (1.0 - i as f64) * bbox.minimum.x;
+                        let y = j as f64 * bbox.maximum.y + (1.0 - j as f64) * bbox.minimum.y;
+                        let z = k as f64 * bbox.maximum.z + (1.0 - k as f64) * bbox.minimum.z;
+
+                        let tester = start.rotate(Point3::new(x, y, z));
+                        radius = radius.max(tester.length());
+
+                        for c in 0..3 {
+                            minimum[c] = f64::min(minimum[c], tester[c]);
+                            maximum[c] = f64::max(maximum[c], tester[c]);
+                        }
+                    }
+                }
+            }
+            if start == end {
+                AABB { minimum, maximum }
+            } else {
+                AABB {
+                    minimum: Point3::new(-radius, -radius, -radius),
+                    maximum: Point3::new(radius, radius, radius),
+                }
+            }
+        });
+        Arc::new(RotateQuat {
+            original: Arc::clone(original),
+            start,
+            end,
+            bbox: bounding_box,
+        })
+    }
+}
+
+impl Hittable for RotateQuat {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        let q = if self.start == self.end {
+            self.start
+        } else {
+            self.start.slerp(self.end, ray.time)
+        };
+        let inverse = q.conjugate();
+        let rotated = Ray::new(
+            inverse.rotate(ray.origin),
+            inverse.rotate(ray.direction),
+            ray.time,
+        )
+        .of_kind(ray.kind);
+        let hit = self.original.hit(&rotated, min_dist, max_dist)?;
+        Some(HitRecord {
+            intersection: q.rotate(hit.intersection),
+            normal: q.rotate(hit.normal),
+            ..hit
+        })
+    }
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
         self.bbox
     }
-    fn _print(&self) -> String {
-        format!("rotate z {}", self.original._print())
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + shared_memory_bytes(&self.original, seen)
+    }
+}
+
+impl fmt::Display for RotateQuat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rotate {}", self.original)
     }
 }
+
+// Applies an arbitrary affine transformation. The original object is shared,
+// not copied, so many instances of one mesh only keep one copy of its triangles.
+pub struct MatrixTransform {
+    original: Arc<dyn Hittable>,
+    matrix: Mat4,
+    inverse: Mat4,
+    bbox: Option<AABB>,
+}
+
+impl MatrixTransform {
+    // None if the matrix can't be inverted
+    pub fn new(original: &Arc<dyn Hittable>, matrix: Mat4) -> Option<Arc<dyn Hittable>> {
+        let inverse = matrix.inverse()?;
+        let bounding_box = if let Some(bbox) = original.bounding_box(TIME_MIN, TIME_MAX) {
+            let mut minimum = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+            let mut maximum = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+            for i in 0..2 {
+                for j in 0..2 {
+                    for k in 0..2 {
+                        let x = i as f64 * bbox.maximum.x + (1.0 - i as f64) * bbox.minimum.x;
+                        let y = j as f64 * bbox.maximum.y + (1.0 - j as f64) * bbox.minimum.y;
+                        let z = k as f64 * bbox.maximum.z + (1.0 - k as f64) * bbox.minimum.z;
+
+                        let tester = matrix.transform_point(Point3::new(x, y, z));
+
+                        for c in 0..3 {
+                            minimum[c] = f64::min(minimum[c], tester[c]);
+                            maximum[c] = f64::max(maximum[c], tester[c]);
+                        }
+                    }
+                }
+            }
+            Some(AABB { minimum, maximum })
+        } else {
+            None
+        };
+        Some(Arc::new(MatrixTransform {
+            original: Arc::clone(original),
+            matrix,
+            inverse,
+            bbox: bounding_box,
+        }))
+    }
+}
+
+impl Hittable for MatrixTransform {
+    fn hit(&self, ray: &Ray, min_dist: f64, max_dist: f64) -> Option<HitRecord> {
+        let origin = self.inverse.transform_point(ray.origin);
+        let direction = self.inverse.transform_vector(ray.direction);
+        // scaling changes distances, and the transformed ray gets normalized
+        let stretch = direction.length();
+        let transformed = Ray::new(origin, direction, ray.time).of_kind(ray.kind);
+
+        if let Some(hit) = self
+            .original
+            .hit(&transformed, min_dist * stretch, max_dist * stretch)
+        {
+            Some(HitRecord {
+                distance: hit.distance / stretch,
+                intersection: self.matrix.transform_point(hit.intersection),
+                front_face: hit.front_face,
+                material: hit.material,
+                normal: self.matrix.transform_normal(hit.normal).unit_vector(),
+                surface_u: hit.surface_u,
+                surface_v: hit.surface_v,
+            })
+        } else {
+            None
+        }
+    }
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        self.bbox
+    }
+    fn estimated_memory_bytes(&self, seen: &mut HashSet<*const ()>) -> usize {
+        mem::size_of_val(self) + shared_memory_bytes(&self.original, seen)
+    }
+}
+
+impl fmt::Display for MatrixTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "matrix transform {}", self.original)
+    }
+}
+
+#[test]
+fn animated_rotate_y_test() {
+    use crate::hitting::Colour;
+    use crate::materials::Lambertian;
+    use crate::objects::Block;
+    let material = Lambertian::with_colour(Colour::new(1, 1, 1));
+    let block = Block::new(Point3::new(1, 0, -0.5), Point3::new(2, 1, 0.5), &material);
+    let spinning = AnimatedRotateY::by_degrees(&block, 0.0, 90.0, 0.0, 1.0);
+
+    // down onto where the block starts, and onto where it ends up
+    let down = |x, z, time| Ray::new(Point3::new(x, 5, z), Vec3::new(0, -1, 0), time);
+    assert!(spinning
+        .hit(&down(1.5, 0.0, 0.0), 0.0, f64::INFINITY)
+        .is_some());
+    assert!(spinning
+        .hit(&down(1.5, 0.0, 1.0), 0.0, f64::INFINITY)
+        .is_none());
+    assert!(spinning
+        .hit(&down(0.0, -1.5, 1.0), 0.0, f64::INFINITY)
+        .is_some());
+
+    // the far corner passes the -z axis halfway through, beyond either end
+    let bbox = spinning.bounding_box(0.0, 1.0).unwrap();
+    let reach = (2.0f64 * 2.0 + 0.5 * 0.5).sqrt();
+    assert!((bbox.minimum.z + reach).abs() < 1e-9);
+    assert!((bbox.maximum.x - reach).abs() < 1e-9);
+    let start = spinning.bounding_box(0.0, 0.0).unwrap();
+    assert!((start.minimum.x - 1.0).abs() < 1e-9);
+    assert!((start.minimum.z + 0.5).abs() < 1e-9);
+
+    // a shorter spin holds still either side of it
+    let brief = AnimatedRotateY::by_degrees(&block, 0.0, 90.0, 0.25, 0.75);
+    assert!(brief
+        .hit(&down(1.5, 0.0, 0.1), 0.0, f64::INFINITY)
+        .is_some());
+    assert!(brief
+        .hit(&down(0.0, -1.5, 0.9), 0.0, f64::INFINITY)
+        .is_some());
+    let after = brief.bounding_box(0.9, 1.0).unwrap();
+    assert!((after.minimum.z + 2.0).abs() < 1e-9);
+    assert!((after.maximum.z + 1.0).abs() < 1e-9);
+}